@@ -0,0 +1,294 @@
+#![warn(missing_docs)]
+//! Derive macros for the `FromNix` and `IntoNix` traits of `nix-bindings`.
+//!
+//! Enable the `derive` feature of `nix-bindings` rather than depending on
+//! this crate directly; the generated code refers to items by their
+//! `::nix_bindings` paths.
+//!
+//! Named structs convert to and from attribute sets, one attribute per
+//! field. Single-field tuple structs convert as their inner value. Field
+//! attributes:
+//!
+//! - `#[nix(rename = "...")]`: use a different attribute name.
+//! - `#[nix(default)]`: use `Default::default()` when the attribute is missing.
+//! - `#[nix(lazy)]`: keep the attribute as an unforced value. The field type
+//!   must be the child value type of the source (`Value`, or `PrimOpValue`
+//!   inside a primop), or something it converts into via `Into`.
+//!
+//! Attribute names, after renaming, must be unique within a struct.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+  Data,
+  DeriveInput,
+  Field,
+  Fields,
+  LitStr,
+  Type,
+  WhereClause,
+  ext::IdentExt,
+  parse_macro_input,
+  parse_quote,
+};
+
+/// Derive `FromNix` for a struct.
+///
+/// See the [crate documentation](crate) for the supported attributes.
+#[proc_macro_derive(FromNix, attributes(nix))]
+pub fn derive_from_nix(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_from_nix(&input)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+/// Derive `IntoNix` for a struct.
+///
+/// See the [crate documentation](crate) for the supported attributes.
+#[proc_macro_derive(IntoNix, attributes(nix))]
+pub fn derive_into_nix(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand_into_nix(&input)
+    .unwrap_or_else(syn::Error::into_compile_error)
+    .into()
+}
+
+/// Options parsed from a field's `#[nix(...)]` attributes.
+#[derive(Default)]
+struct FieldOpts {
+  rename:  Option<String>,
+  default: bool,
+  lazy:    bool,
+}
+
+impl FieldOpts {
+  fn parse(field: &Field) -> syn::Result<Self> {
+    let mut opts = FieldOpts::default();
+    for attr in &field.attrs {
+      if !attr.path().is_ident("nix") {
+        continue;
+      }
+      attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("rename") {
+          let name: LitStr = meta.value()?.parse()?;
+          opts.rename = Some(name.value());
+        } else if meta.path.is_ident("default") {
+          opts.default = true;
+        } else if meta.path.is_ident("lazy") {
+          opts.lazy = true;
+        } else {
+          return Err(meta.error(
+            "unknown nix attribute, expected `rename`, `default` or `lazy`",
+          ));
+        }
+        Ok(())
+      })?;
+    }
+    Ok(opts)
+  }
+}
+
+/// A named field together with its attribute name and options.
+struct NamedField<'a> {
+  field: &'a Field,
+  name:  String,
+  opts:  FieldOpts,
+}
+
+/// The struct shapes the derives support.
+enum Shape<'a> {
+  Named(Vec<NamedField<'a>>),
+  Newtype(&'a Type),
+}
+
+fn shape(input: &DeriveInput) -> syn::Result<Shape<'_>> {
+  let Data::Struct(data) = &input.data else {
+    return Err(syn::Error::new_spanned(
+      input,
+      "FromNix and IntoNix can only be derived for structs",
+    ));
+  };
+  match &data.fields {
+    Fields::Named(fields) => {
+      let fields = fields
+        .named
+        .iter()
+        .map(|field| {
+          let opts = FieldOpts::parse(field)?;
+          let name = opts.rename.clone().unwrap_or_else(|| {
+            // Fields are always named here.
+            field
+              .ident
+              .as_ref()
+              .map(IdentExt::unraw)
+              .unwrap()
+              .to_string()
+          });
+          Ok(NamedField { field, name, opts })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+      // An attribute set cannot hold the same name twice.
+      for (i, f) in fields.iter().enumerate() {
+        if fields[..i].iter().any(|other| other.name == f.name) {
+          return Err(syn::Error::new_spanned(
+            f.field,
+            format!("duplicate attribute name `{}`", f.name),
+          ));
+        }
+      }
+      Ok(Shape::Named(fields))
+    },
+    Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+      let field = &fields.unnamed[0];
+      if field.attrs.iter().any(|attr| attr.path().is_ident("nix")) {
+        return Err(syn::Error::new_spanned(
+          field,
+          "nix attributes are not supported on newtype fields",
+        ));
+      }
+      Ok(Shape::Newtype(&field.ty))
+    },
+    _ => {
+      Err(syn::Error::new_spanned(
+        input,
+        "FromNix and IntoNix require named fields or a single-field tuple \
+         struct",
+      ))
+    },
+  }
+}
+
+/// The struct's own where clause, or an empty one to extend.
+fn where_clause(input: &DeriveInput) -> WhereClause {
+  input
+    .generics
+    .where_clause
+    .clone()
+    .unwrap_or_else(|| parse_quote!(where))
+}
+
+fn expand_from_nix(input: &DeriveInput) -> syn::Result<TokenStream2> {
+  let ident = &input.ident;
+  let mut generics = input.generics.clone();
+  generics
+    .params
+    .push(parse_quote!(__V: ::nix_bindings::NixValueOps));
+  let (impl_generics, ..) = generics.split_for_impl();
+  let (_, ty_generics, _) = input.generics.split_for_impl();
+  let mut where_clause = where_clause(input);
+
+  let body = match shape(input)? {
+    Shape::Named(fields) => {
+      let inits = fields.iter().map(|f| {
+        let NamedField { field, name, opts } = f;
+        let ty = &field.ty;
+        let helper = match (opts.lazy, opts.default) {
+          (false, false) => {
+            where_clause
+              .predicates
+              .push(parse_quote!(#ty: ::nix_bindings::FromNix<__V::Child>));
+            quote!(field)
+          },
+          (false, true) => {
+            where_clause.predicates.push(parse_quote!(
+              #ty: ::nix_bindings::FromNix<__V::Child>
+                + ::core::default::Default
+            ));
+            quote!(field_or_default)
+          },
+          (true, false) => {
+            where_clause
+              .predicates
+              .push(parse_quote!(__V::Child: ::core::convert::Into<#ty>));
+            quote!(lazy_field)
+          },
+          (true, true) => {
+            where_clause
+              .predicates
+              .push(parse_quote!(__V::Child: ::core::convert::Into<#ty>));
+            where_clause
+              .predicates
+              .push(parse_quote!(#ty: ::core::default::Default));
+            quote!(lazy_field_or_default)
+          },
+        };
+        let member = &field.ident;
+        quote! {
+          #member: ::nix_bindings::__private::#helper(value, #name)?
+        }
+      });
+      quote!(Self { #(#inits,)* })
+    },
+    Shape::Newtype(ty) => {
+      where_clause
+        .predicates
+        .push(parse_quote!(#ty: ::nix_bindings::FromNix<__V>));
+      quote!(Self(::nix_bindings::FromNix::from_nix(value)?))
+    },
+  };
+
+  Ok(quote! {
+    #[automatically_derived]
+    impl #impl_generics ::nix_bindings::FromNix<__V> for #ident #ty_generics
+      #where_clause
+    {
+      fn from_nix(value: &__V) -> ::nix_bindings::Result<Self> {
+        ::core::result::Result::Ok(#body)
+      }
+    }
+  })
+}
+
+fn expand_into_nix(input: &DeriveInput) -> syn::Result<TokenStream2> {
+  let ident = &input.ident;
+  let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+  let mut where_clause = where_clause(input);
+
+  let body = match shape(input)? {
+    Shape::Named(fields) => {
+      let vars: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect();
+      let lets = fields.iter().zip(&vars).map(|(f, var)| {
+        let ty = &f.field.ty;
+        where_clause
+          .predicates
+          .push(parse_quote!(#ty: ::nix_bindings::IntoNix));
+        let member = &f.field.ident;
+        let name = &f.name;
+        quote! {
+          let #var = ::nix_bindings::IntoNix::to_nix(&self.#member, alloc)
+            .map_err(|e| e.in_attr(#name))?;
+        }
+      });
+      let names = fields.iter().map(|f| &f.name);
+      quote! {
+        #(#lets)*
+        ::nix_bindings::NixAlloc::make_attrs(alloc, &[#((#names, &#vars)),*])
+      }
+    },
+    Shape::Newtype(ty) => {
+      where_clause
+        .predicates
+        .push(parse_quote!(#ty: ::nix_bindings::IntoNix));
+      quote!(::nix_bindings::IntoNix::to_nix(&self.0, alloc))
+    },
+  };
+
+  Ok(quote! {
+    #[automatically_derived]
+    impl #impl_generics ::nix_bindings::IntoNix for #ident #ty_generics
+      #where_clause
+    {
+      fn to_nix<'__s, __A: ::nix_bindings::NixAlloc>(
+        &self,
+        alloc: &'__s __A,
+      ) -> ::nix_bindings::Result<<__A as ::nix_bindings::NixAlloc>::Value<'__s>>
+      {
+        #body
+      }
+    }
+  })
+}
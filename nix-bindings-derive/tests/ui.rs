@@ -0,0 +1,7 @@
+//! Compile-fail tests for the attribute and shape errors of the derives.
+
+#[test]
+fn ui() {
+  let cases = trybuild::TestCases::new();
+  cases.compile_fail("tests/ui/*.rs");
+}
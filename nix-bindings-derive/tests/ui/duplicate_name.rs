@@ -0,0 +1,10 @@
+use nix_bindings_derive::IntoNix;
+
+#[derive(IntoNix)]
+struct Service {
+  port: u16,
+  #[nix(rename = "port")]
+  other_port: u16,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use nix_bindings_derive::IntoNix;
+
+#[derive(IntoNix)]
+enum Mode {
+  On,
+  Off,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use nix_bindings_derive::FromNix;
+
+#[derive(FromNix)]
+struct Port(#[nix(default)] u16);
+
+fn main() {}
@@ -0,0 +1,6 @@
+use nix_bindings_derive::FromNix;
+
+#[derive(FromNix)]
+struct Pair(u16, u16);
+
+fn main() {}
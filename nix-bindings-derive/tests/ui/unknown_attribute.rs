@@ -0,0 +1,9 @@
+use nix_bindings_derive::FromNix;
+
+#[derive(FromNix)]
+struct Service {
+  #[nix(skip)]
+  port: u16,
+}
+
+fn main() {}
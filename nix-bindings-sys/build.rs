@@ -28,6 +28,14 @@ fn main() {
   println!("cargo:rerun-if-changed=src/wrappers/add_to_store.cc");
   println!("cargo:rerun-if-changed=src/wrappers/init_path.cc");
   println!("cargo:rerun-if-changed=src/wrappers/eval.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/string_context.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/print.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/compare.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/introspect.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/xml.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/settings.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/parse.cc");
+  println!("cargo:rerun-if-changed=src/wrappers/interrupt.cc");
   println!("cargo:rerun-if-changed=src/wrappers/flake.cc");
 
   // docs.rs has no Nix system libraries. Write empty bindings so the crate
@@ -179,9 +187,18 @@ fn main() {
     if env::var("CARGO_FEATURE_EXPR").is_ok() {
       cc_build.file("src/wrappers/init_path.cc");
       cc_build.file("src/wrappers/eval.cc");
-      // Both init_path.cc and eval.cc call into C++ libnixexpr (allowPath,
-      // getDerivation, autoCallFunction). Force it onto the link line so
-      // dependent crates that only use the C API still link correctly.
+      cc_build.file("src/wrappers/string_context.cc");
+      cc_build.file("src/wrappers/print.cc");
+      cc_build.file("src/wrappers/compare.cc");
+      cc_build.file("src/wrappers/introspect.cc");
+      cc_build.file("src/wrappers/xml.cc");
+      cc_build.file("src/wrappers/settings.cc");
+      cc_build.file("src/wrappers/parse.cc");
+      cc_build.file("src/wrappers/interrupt.cc");
+      // The expression shims call into C++ libnixexpr (allowPath,
+      // getDerivation, autoCallFunction, eqValues, ...). Force it onto the
+      // link line so dependent crates that only use the C API still link
+      // correctly.
       println!("cargo:rustc-link-lib=dylib=nixexpr");
     }
 
@@ -1,6 +1,8 @@
 #ifndef NIX_API_EXPR_SHIM_H
 #define NIX_API_EXPR_SHIM_H
 
+#include <stdint.h>
+
 #include <nix_api_expr.h>
 #include <nix_api_util.h>
 
@@ -50,6 +52,28 @@ nix_err nix_eval_state_allow_path(nix_c_context *context, EvalState *state,
 StorePath *nix_get_derivation(nix_c_context *context, EvalState *state,
                               nix_value *value, bool ignoreAssertionFailures);
 
+/**
+ * @brief Determine whether a Nix value is a derivation and return its name.
+ *
+ * Like nix_get_derivation, but forces only the value and its `name`
+ * attribute, not `.drvPath`, so nothing is instantiated. @p callback is
+ * called with the name if @p value is a derivation and not called
+ * otherwise.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[in]  value     Value to inspect.
+ * @param[in]  ignoreAssertionFailures When true, an AssertionError raised while
+ *  forcing @p value is treated as "not a derivation" rather than an error.
+ * @param[in]  callback  Receives the derivation name.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code if forcing fails.
+ */
+nix_err nix_get_derivation_name(nix_c_context *context, EvalState *state,
+                                nix_value *value, bool ignoreAssertionFailures,
+                                nix_get_string_callback callback,
+                                void *user_data);
+
 /**
  * @brief Call a function, drawing its arguments from an attribute set.
  *
@@ -70,6 +94,377 @@ nix_err nix_value_auto_call_function(nix_c_context *context, EvalState *state,
                                      nix_value *auto_args, nix_value *fn_val,
                                      nix_value *result);
 
+/**
+ * @brief Read the string context of a string value without realising it.
+ *
+ * Calls @p callback once per context element, in the evaluator's order, with
+ * the element in its encoded form: `/nix/store/...` for an opaque store path,
+ * `=/nix/store/....drv` for a derivation with all its outputs, and
+ * `!output!/nix/store/....drv` for a single output. Nothing is built.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  value     Forced string value.
+ * @param[in]  callback  Called with each encoded context element.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_get_string_context(nix_c_context *context, const nix_value *value,
+                               nix_get_string_callback callback,
+                               void *user_data);
+
+/**
+ * @brief Initialise a string value carrying the given string context.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[out] value     Allocated value to initialise.
+ * @param[in]  str       NUL-terminated string contents.
+ * @param[in]  elems     Encoded context elements, as produced by
+ *  nix_get_string_context.
+ * @param[in]  elems_len Number of entries in @p elems.
+ * @return NIX_OK on success, an error code if an element does not parse.
+ */
+nix_err nix_init_string_with_context(nix_c_context *context, EvalState *state,
+                                     nix_value *value, const char *str,
+                                     const char *const *elems,
+                                     size_t elems_len);
+
+/**
+ * @brief Identify the attribute set or list a value holds.
+ *
+ * Values that share the same attribute set (or the same list elements)
+ * return the same number, even when they live in different slots. Used to
+ * detect repeated values while printing, as `nix repl` does.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  value   Forced value.
+ * @return An opaque identity, or 0 for other types and empty lists.
+ */
+uintptr_t nix_value_identity(nix_c_context *context, const nix_value *value);
+
+/**
+ * @brief Compare two values with the evaluator's `==`.
+ *
+ * Forces both values as deeply as needed. Derivations compare by
+ * `outPath`, and functions are never equal.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  state   Evaluator state.
+ * @param[in]  a       First value.
+ * @param[in]  b       Second value.
+ * @param[out] result  Receives whether @p a equals @p b.
+ * @return NIX_OK on success, an error code if forcing fails.
+ */
+nix_err nix_value_equal(nix_c_context *context, EvalState *state,
+                        nix_value *a, nix_value *b, bool *result);
+
+/**
+ * @brief Compare two values with the evaluator's `<`.
+ *
+ * Numbers, strings, paths and lists (lexicographically) are comparable;
+ * anything else raises an error.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  state   Evaluator state.
+ * @param[in]  a       Left operand.
+ * @param[in]  b       Right operand.
+ * @param[out] result  Receives whether @p a is less than @p b.
+ * @return NIX_OK on success, an error code if the values are not comparable
+ *  or forcing fails.
+ */
+nix_err nix_value_less_than(nix_c_context *context, EvalState *state,
+                            nix_value *a, nix_value *b, bool *result);
+
+/**
+ * @brief Describe a function value as JSON.
+ *
+ * Forces @p value and passes one JSON object to @p callback:
+ *
+ *  - `{"kind": "lambda", "name", "formals", "ellipsis", "arg", "pos"}`, where
+ *    `formals` is null for a plain `x: ...` lambda or a list of
+ *    `{"name", "default"}` objects, `name` and `arg` may be null, and `pos`
+ *    is null or `{"file", "line", "column"}`;
+ *  - `{"kind": "primop", "name", "arity", "doc"}`, where `doc` may be null;
+ *  - `{"kind": "primop-app", "name", "arity", "remaining"}` for a partially
+ *    applied primop.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[in]  value     Value to describe.
+ * @param[in]  callback  Receives the JSON document.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code if forcing fails or the value is
+ *  not a function.
+ */
+nix_err nix_get_function_info(nix_c_context *context, EvalState *state,
+                              nix_value *value,
+                              nix_get_string_callback callback,
+                              void *user_data);
+
+/**
+ * @brief Find where an attribute was defined, as
+ *  `builtins.unsafeGetAttrPos` does.
+ *
+ * Forces @p value and passes `{"file", "line", "column"}` to @p callback,
+ * or `null` if the attribute is missing or has no position.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[in]  value     Attribute set.
+ * @param[in]  name      Attribute name.
+ * @param[in]  callback  Receives the JSON document.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code if forcing fails or the value is
+ *  not an attribute set.
+ */
+nix_err nix_get_attr_pos(nix_c_context *context, EvalState *state,
+                         nix_value *value, const char *name,
+                         nix_get_string_callback callback, void *user_data);
+
+/**
+ * @brief Render a value as XML, as `builtins.toXML` does.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[in]  value     Value to render.
+ * @param[in]  strict    Force nested values; otherwise unevaluated ones are
+ *  written as `<unevaluated />`.
+ * @param[in]  location  Add `path`, `line` and `column` attributes to
+ *  attributes and functions, as `nix-instantiate --eval --xml` does.
+ * @param[in]  callback  Receives the XML document.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code if forcing fails.
+ */
+nix_err nix_value_to_xml(nix_c_context *context, EvalState *state,
+                         nix_value *value, bool strict, bool location,
+                         nix_get_string_callback callback, void *user_data);
+
+/**
+ * @brief Open an element in the XML document an external value's
+ *  `printValueAsXML` callback receives as `doc`.
+ *
+ * @param[out] context     Optional. Stores error information.
+ * @param[in]  doc         The `doc` passed to `printValueAsXML`.
+ * @param[in]  name        Element name.
+ * @param[in]  attr_names  @p n_attrs attribute names.
+ * @param[in]  attr_values @p n_attrs attribute values.
+ * @param[in]  n_attrs     Number of attributes.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_xml_open_element(nix_c_context *context, void *doc,
+                             const char *name, const char **attr_names,
+                             const char **attr_values, size_t n_attrs);
+
+/**
+ * @brief Close the innermost element opened with nix_xml_open_element().
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  doc     The `doc` passed to `printValueAsXML`.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_xml_close_element(nix_c_context *context, void *doc);
+
+/**
+ * @brief Write an element without children, as nix_xml_open_element().
+ *
+ * @param[out] context     Optional. Stores error information.
+ * @param[in]  doc         The `doc` passed to `printValueAsXML`.
+ * @param[in]  name        Element name.
+ * @param[in]  attr_names  @p n_attrs attribute names.
+ * @param[in]  attr_values @p n_attrs attribute values.
+ * @param[in]  n_attrs     Number of attributes.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_xml_write_empty_element(nix_c_context *context, void *doc,
+                                    const char *name, const char **attr_names,
+                                    const char **attr_values, size_t n_attrs);
+
+/**
+ * @brief Set one of the evaluator settings (`pure-eval`, `restrict-eval`,
+ *  `allowed-uris`, `max-call-depth`, ...) of the EvalState @p builder will
+ *  build.
+ *
+ * Unlike nix_setting_set(), this affects only that evaluator. Call it after
+ * nix_eval_state_builder_load(), which would overwrite it.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  builder Builder whose settings to change.
+ * @param[in]  name    Setting name, as in nix.conf.
+ * @param[in]  value   Setting value, as in nix.conf.
+ * @return NIX_OK on success, NIX_ERR_KEY for an unknown setting, or another
+ *  error code if @p value is invalid.
+ */
+nix_err nix_eval_state_builder_set_setting(nix_c_context *context,
+                                           nix_eval_state_builder *builder,
+                                           const char *name,
+                                           const char *value);
+
+/**
+ * @brief Allow access to @p path and everything below it under
+ *  `restrict-eval` or `pure-eval`.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  state   Eval state whose allowlist to update.
+ * @param[in]  path    Absolute filesystem path.
+ * @return NIX_OK on success, otherwise a nix_err describing the failure.
+ */
+nix_err nix_eval_state_allow_path_prefix(nix_c_context *context,
+                                         EvalState *state, const char *path);
+
+/**
+ * @brief Change the `builtins` set of one evaluator.
+ *
+ * Attribute @p names[i] is set to @p values[i], replacing any builtin of
+ * that name, or removed if @p values[i] is NULL. Other evaluators are
+ * unaffected.
+ *
+ * A removed builtin's top-level name, `name` or `__name`, is removed as well
+ * for expressions parsed afterwards. If @p top_level[i] is set, the
+ * top-level name of builtin @p names[i] is rebound to @p values[i] too; the
+ * base environment cannot grow, so this fails with NIX_ERR_KEY if Nix binds
+ * no such name. Nothing is changed on failure.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator whose builtins to change.
+ * @param[in]  names     @p n attribute names.
+ * @param[in]  values    @p n values, or NULL entries to remove a builtin.
+ * @param[in]  top_level @p n flags; whether to rebind the top-level name.
+ * @param[in]  n         Number of changes.
+ * @return NIX_OK on success, otherwise a nix_err describing the failure.
+ */
+nix_err nix_eval_state_set_builtins(nix_c_context *context, EvalState *state,
+                                    const char **names, nix_value **values,
+                                    const bool *top_level, size_t n);
+
+/**
+ * @brief A parsed expression, evaluable any number of times.
+ *
+ * Valid as long as the EvalState it was parsed by.
+ */
+typedef struct nix_parsed_expr nix_parsed_expr;
+
+/**
+ * @brief Parse an expression without evaluating it.
+ *
+ * On a syntax error, @p syntax_error (if not NULL) receives
+ * `{"file", "line", "column", "message"}` before NULL is returned.
+ *
+ * @param[out] context      Optional. Stores error information.
+ * @param[in]  state        Evaluator state.
+ * @param[in]  expr         Nix source.
+ * @param[in]  path         Base path for relative paths in @p expr.
+ * @param[in]  syntax_error Optional. Receives a syntax error as JSON.
+ * @param[in]  user_data    Passed through to @p syntax_error.
+ * @return The parsed expression, to be freed with nix_parsed_expr_free(),
+ *  or NULL on error.
+ */
+nix_parsed_expr *nix_parse_expr(nix_c_context *context, EvalState *state,
+                                const char *expr, const char *path,
+                                nix_get_string_callback syntax_error,
+                                void *user_data);
+
+/**
+ * @brief Parse an expression for static analysis, accepting unbound
+ *  variables.
+ *
+ * Variables nothing binds are resolved to a placeholder `with` scope
+ * instead of failing. The result can be inspected with nix_parsed_expr_ast()
+ * but not evaluated. Parameters are as for nix_parse_expr().
+ */
+nix_parsed_expr *nix_parse_expr_unbound(nix_c_context *context,
+                                        EvalState *state, const char *expr,
+                                        const char *path,
+                                        nix_get_string_callback syntax_error,
+                                        void *user_data);
+
+/**
+ * @brief List the names in scope at the top level of every expression
+ *  (`true`, `builtins`, `import`, `__add`, ...) as a JSON array.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator state.
+ * @param[in]  callback  Receives the JSON array.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_eval_state_global_names(nix_c_context *context, EvalState *state,
+                                    nix_get_string_callback callback,
+                                    void *user_data);
+
+/**
+ * @brief Evaluate a parsed expression into @p value and force it, as
+ *  nix_expr_eval_from_string() does.
+ *
+ * @param[out] context Optional. Stores error information.
+ * @param[in]  state   Evaluator that parsed @p expr.
+ * @param[in]  expr    Parsed expression.
+ * @param[out] value   Receives the result.
+ * @return NIX_OK on success, an error code if evaluation fails.
+ */
+nix_err nix_parsed_expr_eval(nix_c_context *context, EvalState *state,
+                             nix_parsed_expr *expr, nix_value *value);
+
+/**
+ * @brief Describe the syntax tree of a parsed expression as JSON.
+ *
+ * Each node is an object with a "kind" and a "pos" (`{"file", "line",
+ * "column"}` or null), plus fields depending on the kind.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator that parsed @p expr.
+ * @param[in]  expr      Parsed expression.
+ * @param[in]  callback  Receives the JSON document.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_parsed_expr_ast(nix_c_context *context, EvalState *state,
+                            nix_parsed_expr *expr,
+                            nix_get_string_callback callback,
+                            void *user_data);
+
+/**
+ * @brief Print a parsed expression back as Nix source, as the evaluator
+ *  shows it: fully parenthesised, with desugared operators.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  state     Evaluator that parsed @p expr.
+ * @param[in]  expr      Parsed expression.
+ * @param[in]  callback  Receives the source.
+ * @param[in]  user_data Passed through to @p callback.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_parsed_expr_show(nix_c_context *context, EvalState *state,
+                             nix_parsed_expr *expr,
+                             nix_get_string_callback callback,
+                             void *user_data);
+
+/**
+ * @brief Free a handle returned by nix_parse_expr().
+ *
+ * @param[in] expr Handle to free; NULL is ignored.
+ */
+void nix_parsed_expr_free(nix_parsed_expr *expr);
+
+/**
+ * @brief Polled during evaluation; returning true interrupts it.
+ */
+typedef bool (*nix_interrupt_check)(void *user_data);
+
+/**
+ * @brief Install the interrupt check of the calling thread.
+ *
+ * While installed, evaluation on this thread is aborted with an
+ * "interrupted" error as soon as @p check returns true. The check is polled
+ * often and must be cheap.
+ *
+ * @param[out] context   Optional. Stores error information.
+ * @param[in]  check     The check, or NULL to remove it.
+ * @param[in]  user_data Passed through to @p check.
+ * @return NIX_OK on success, an error code otherwise.
+ */
+nix_err nix_set_interrupt_check(nix_c_context *context,
+                                nix_interrupt_check check, void *user_data);
+
 #ifdef __cplusplus
 }
 #endif
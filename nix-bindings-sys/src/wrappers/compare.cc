@@ -0,0 +1,49 @@
+// Shims exposing the evaluator's `==` and `<` to the C API.
+//
+// Evaluating `a == b` from a string needs both values bound into scope. The
+// evaluator already has the logic: EvalState::eqValues for `==` (deep,
+// derivations compared by outPath, functions never equal) and the
+// `lessThan` builtin that `<` desugars to.
+
+#include <nix/expr/eval.hh>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+extern "C" {
+
+nix_err nix_value_equal(nix_c_context *context, EvalState *state,
+                        nix_value *a, nix_value *b, bool *result) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !a || !a->value || !b || !b->value || !result)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    *result = state->state.eqValues(*a->value, *b->value, nix::noPos,
+                                    "while comparing two values");
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_value_less_than(nix_c_context *context, EvalState *state,
+                            nix_value *a, nix_value *b, bool *result) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !a || !a->value || !b || !b->value || !result)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &lessThan = state->state.getBuiltin("lessThan");
+    nix::Value *args[] = {a->value, b->value};
+    nix::Value res;
+    state->state.callFunction(lessThan, args, res, nix::noPos);
+    *result = state->state.forceBool(res, nix::noPos,
+                                     "while comparing two values");
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
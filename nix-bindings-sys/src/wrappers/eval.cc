@@ -1,4 +1,5 @@
-// Shims for nix_get_derivation and nix_value_auto_call_function.
+// Shims for nix_get_derivation, nix_get_derivation_name and
+// nix_value_auto_call_function.
 //
 // These mirror the functions I've proposed in NixOS/nix#15842. The PR has been
 // stalled upstream, so we implement them locally using the same strategy:
@@ -52,6 +53,26 @@ StorePath *nix_get_derivation(nix_c_context *context, EvalState *state,
   NIXC_CATCH_ERRS_NULL
 }
 
+nix_err nix_get_derivation_name(nix_c_context *context, EvalState *state,
+                                nix_value *value, bool ignoreAssertionFailures,
+                                nix_get_string_callback callback,
+                                void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !value || !value->value || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &v = *value->value;
+    auto maybePkg =
+        nix::getDerivation(state->state, v, ignoreAssertionFailures);
+    if (maybePkg) {
+      auto name = maybePkg->queryName();
+      callback(name.c_str(), (unsigned)name.size(), user_data);
+    }
+  }
+  NIXC_CATCH_ERRS
+}
+
 nix_err nix_value_auto_call_function(nix_c_context *context, EvalState *state,
                                      nix_value *auto_args, nix_value *fn_val,
                                      nix_value *result) {
@@ -0,0 +1,34 @@
+// Shim for interrupting a running evaluation.
+//
+// The evaluator polls checkInterrupt() as it goes, which throws
+// nix::Interrupted once the process-wide interrupt flag is set (by SIGINT)
+// or the calling thread's interruptCheck hook returns true. Installing a
+// hook per thread lets a caller abort its own evaluation without touching
+// evaluations on other threads. Thunks being forced when the exception
+// unwinds are restored, so the EvalState stays usable.
+
+#include <nix/util/signals.hh>
+
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+extern "C" {
+
+nix_err nix_set_interrupt_check(nix_c_context *context,
+                                nix_interrupt_check check, void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  try {
+    if (check)
+      nix::unix::interruptCheck = [check, user_data] {
+        return check(user_data);
+      };
+    else
+      nix::unix::interruptCheck = nullptr;
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
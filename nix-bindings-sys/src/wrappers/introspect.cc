@@ -0,0 +1,132 @@
+// Shims describing functions and source positions.
+//
+// A function value is opaque through the C API: it cannot tell a lambda from
+// a primop, and `builtins.functionArgs` drops the ellipsis and `@` binding.
+// The evaluator's ExprLambda and PrimOp carry all of it, so we describe the
+// function as a small JSON document and let the Rust side decode it.
+// Attribute positions (what `builtins.unsafeGetAttrPos` returns) are
+// reported the same way.
+
+#include <nix/expr/eval.hh>
+#include <nix/expr/nixexpr.hh>
+
+#include <nlohmann/json.hpp>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+namespace {
+
+nlohmann::json symbol_json(nix::EvalState &state, nix::Symbol sym) {
+  if (!sym)
+    return nullptr;
+  return std::string(std::string_view(state.symbols[sym]));
+}
+
+// { "file": ..., "line": ..., "column": ... }, or null without a position.
+// Expressions parsed from a string or stdin have no file; they are named as
+// Nix names them in error messages.
+nlohmann::json pos_json(nix::EvalState &state, nix::PosIdx idx) {
+  auto pos = state.positions[idx];
+  if (!pos)
+    return nullptr;
+  std::string file;
+  if (auto path = pos.getSourcePath())
+    file = path->to_string();
+  else if (std::holds_alternative<nix::Pos::Stdin>(pos.origin))
+    file = "«stdin»";
+  else
+    file = "«string»";
+  return {{"file", file}, {"line", pos.line}, {"column", pos.column}};
+}
+
+nlohmann::json function_json(nix::EvalState &state, nix::Value &v) {
+  if (v.isLambda()) {
+    auto fun = v.lambda().fun;
+    nlohmann::json formals = nullptr;
+    bool ellipsis = false;
+    if (fun->hasFormals()) {
+      formals = nlohmann::json::array();
+      for (auto &formal : fun->formals->formals)
+        formals.push_back({{"name", symbol_json(state, formal.name)},
+                           {"default", formal.def != nullptr}});
+      ellipsis = fun->formals->ellipsis;
+    }
+    return {{"kind", "lambda"},
+            {"name", symbol_json(state, fun->name)},
+            {"formals", formals},
+            {"ellipsis", ellipsis},
+            {"arg", symbol_json(state, fun->arg)},
+            {"pos", pos_json(state, fun->pos)}};
+  }
+  if (v.isPrimOp()) {
+    auto prim = v.primOp();
+    nlohmann::json doc = nullptr;
+    if (prim->doc)
+      doc = std::string(*prim->doc);
+    return {{"kind", "primop"},
+            {"name", prim->name},
+            {"arity", prim->arity},
+            {"doc", doc}};
+  }
+  if (v.isPrimOpApp()) {
+    size_t applied = 0;
+    for (auto *p = &v; p->isPrimOpApp(); p = p->primOpApp().left)
+      applied++;
+    auto prim = v.primOpAppPrimOp();
+    return {{"kind", "primop-app"},
+            {"name", prim->name},
+            {"arity", prim->arity},
+            {"remaining", prim->arity - applied}};
+  }
+  throw nix::Error("value is not a function");
+}
+
+} // namespace
+
+extern "C" {
+
+nix_err nix_get_function_info(nix_c_context *context, EvalState *state,
+                              nix_value *value,
+                              nix_get_string_callback callback,
+                              void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !value || !value->value || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &v = *value->value;
+    state->state.forceValue(v, nix::noPos);
+    auto s = function_json(state->state, v).dump();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_get_attr_pos(nix_c_context *context, EvalState *state,
+                         nix_value *value, const char *name,
+                         nix_get_string_callback callback, void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !value || !value->value || !name || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &v = *value->value;
+    state->state.forceValue(v, nix::noPos);
+    if (v.type() != nix::nAttrs)
+      return nix_set_err_msg(context, NIX_ERR_UNKNOWN,
+                             "value is not an attribute set");
+    nlohmann::json pos = nullptr;
+    if (auto attr = v.attrs()->get(state->state.symbols.create(name)))
+      pos = pos_json(state->state, attr->pos);
+    auto s = pos.dump();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
@@ -0,0 +1,412 @@
+// Shims for parsing an expression once and evaluating it many times.
+//
+// nix_expr_eval_from_string parses and evaluates in one go, and reports a
+// syntax error only as a formatted message. Here the parsed Expr is kept in
+// a handle; like every Expr the parser creates, it lives as long as the
+// EvalState. A syntax error is also passed to a callback as
+// `{"file", "line", "column", "message"}` so callers can point at it.
+//
+// The parsed tree itself is exported as JSON, one object per Expr node with
+// a "kind" and a "pos", for the Rust side to decode into its AST enum.
+//
+// The parser resolves variables as it goes and rejects unbound ones. For
+// static analysis we want those too, so nix_parse_expr_unbound parses under
+// a placeholder `with` that catches every name nothing else binds. Such an
+// expression can be inspected but not evaluated.
+
+#include <algorithm>
+#include <memory>
+#include <sstream>
+
+#include <nix/expr/eval.hh>
+#include <nix/expr/nixexpr.hh>
+#include <nix/util/terminal.hh>
+
+#include <nlohmann/json.hpp>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+struct nix_parsed_expr {
+  nix::Expr *expr;
+  // The placeholder scope of nix_parse_expr_unbound, or null.
+  std::unique_ptr<nix::ExprWith> unbound;
+};
+
+namespace {
+
+// { "file": ..., "line": ..., "column": ... }, named as in introspect.cc.
+nlohmann::json pos_json(const nix::Pos &pos) {
+  std::string file;
+  if (auto path = pos.getSourcePath())
+    file = path->to_string();
+  else if (std::holds_alternative<nix::Pos::Stdin>(pos.origin))
+    file = "«stdin»";
+  else
+    file = "«string»";
+  return {{"file", file}, {"line", pos.line}, {"column", pos.column}};
+}
+
+nlohmann::json syntax_error_json(const nix::ParseError &e) {
+  nlohmann::json json = {{"file", "«string»"}, {"line", 0}, {"column", 0}};
+  if (auto pos = e.info().pos)
+    json = pos_json(*pos);
+  json["message"] = nix::filterANSIEscapes(e.info().msg.str(), true);
+  return json;
+}
+
+struct AstWriter {
+  nix::EvalState &state;
+  const nix::ExprWith *unbound;
+
+  nlohmann::json pos(nix::PosIdx idx) {
+    auto pos = state.positions[idx];
+    if (!pos)
+      return nullptr;
+    return pos_json(pos);
+  }
+
+  nlohmann::json symbol(nix::Symbol sym) {
+    if (!sym)
+      return nullptr;
+    return std::string(std::string_view(state.symbols[sym]));
+  }
+
+  nlohmann::json node(const char *kind, nix::PosIdx idx) {
+    return {{"kind", kind}, {"pos", pos(idx)}};
+  }
+
+  // A component of an attribute path: a name, or an interpolated expression.
+  nlohmann::json attr_name(const nix::AttrName &name) {
+    if (name.symbol)
+      return {{"name", symbol(name.symbol)}};
+    return {{"expr", expr(name.expr)}};
+  }
+
+  template <typename Path> nlohmann::json attr_path(const Path &path) {
+    auto json = nlohmann::json::array();
+    for (auto &name : path)
+      json.push_back(attr_name(name));
+    return json;
+  }
+
+  // The bindings of a set or `let`, in source order. Inherited bindings
+  // refer to their source by index into "inherit_from".
+  nlohmann::json attrs(nix::ExprAttrs &e) {
+    std::vector<std::pair<nix::PosIdx, nlohmann::json>> bindings;
+    for (auto &[name, def] : e.attrs) {
+      nlohmann::json binding = {{"name", {{"name", symbol(name)}}},
+                                {"pos", pos(def.pos)}};
+      switch (def.kind) {
+      case nix::ExprAttrs::AttrDef::Kind::Plain:
+        binding["value"] = expr(def.e);
+        break;
+      case nix::ExprAttrs::AttrDef::Kind::Inherited:
+        binding["inherit"] = true;
+        break;
+      case nix::ExprAttrs::AttrDef::Kind::InheritedFrom: {
+        auto &select = dynamic_cast<nix::ExprSelect &>(*def.e);
+        auto &from = dynamic_cast<nix::ExprInheritFrom &>(*select.e);
+        binding["inherit_from"] = from.displ;
+        break;
+      }
+      }
+      bindings.emplace_back(def.pos, std::move(binding));
+    }
+    for (auto &def : e.dynamicAttrs)
+      bindings.emplace_back(
+          def.pos, nlohmann::json{{"name", {{"expr", expr(def.nameExpr)}}},
+                                  {"pos", pos(def.pos)},
+                                  {"value", expr(def.valueExpr)}});
+    std::stable_sort(bindings.begin(), bindings.end(),
+                     [](auto &a, auto &b) { return a.first < b.first; });
+
+    auto json = nlohmann::json::array();
+    for (auto &[_, binding] : bindings)
+      json.push_back(std::move(binding));
+    auto from = nlohmann::json::array();
+    if (e.inheritFromExprs)
+      for (auto *source : *e.inheritFromExprs)
+        from.push_back(expr(source));
+    return {{"rec", e.recursive}, {"bindings", json}, {"inherit_from", from}};
+  }
+
+  nlohmann::json binary(const char *op, nix::PosIdx idx, nix::Expr *e1,
+                        nix::Expr *e2) {
+    auto json = node("binary", idx);
+    json["op"] = op;
+    json["lhs"] = expr(e1);
+    json["rhs"] = expr(e2);
+    return json;
+  }
+
+  nlohmann::json expr(nix::Expr *e) {
+    if (auto *v = dynamic_cast<nix::ExprInt *>(e)) {
+      auto json = node("int", v->getPos());
+      json["value"] = v->v.integer().value;
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprFloat *>(e)) {
+      auto json = node("float", v->getPos());
+      json["value"] = v->v.fpoint();
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprString *>(e)) {
+      auto json = node("string", v->getPos());
+      json["value"] = std::string(v->v.string_view());
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprPath *>(e)) {
+      auto json = node("path", v->getPos());
+      json["value"] = std::string(v->v.pathStr());
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprVar *>(e)) {
+      auto json = node("var", v->pos);
+      json["name"] = symbol(v->name);
+      json["from_with"] = v->fromWith && v->fromWith != unbound;
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprSelect *>(e)) {
+      auto json = node("select", v->pos);
+      json["expr"] = expr(v->e);
+      json["path"] = attr_path(v->getAttrPath());
+      json["default"] = v->def ? expr(v->def) : nullptr;
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprOpHasAttr *>(e)) {
+      auto json = node("has_attr", v->getPos());
+      json["expr"] = expr(v->e);
+      json["path"] = attr_path(v->attrPath);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprAttrs *>(e)) {
+      auto json = node("attrs", v->pos);
+      json["attrs"] = attrs(*v);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprList *>(e)) {
+      auto json = node("list", v->getPos());
+      json["elems"] = nlohmann::json::array();
+      for (auto *elem : v->elems)
+        json["elems"].push_back(expr(elem));
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprLambda *>(e)) {
+      auto json = node("lambda", v->pos);
+      json["name"] = symbol(v->name);
+      json["arg"] = symbol(v->arg);
+      json["formals"] = nullptr;
+      json["ellipsis"] = false;
+      if (v->hasFormals()) {
+        json["formals"] = nlohmann::json::array();
+        for (auto &formal : v->formals->formals)
+          json["formals"].push_back(
+              {{"name", symbol(formal.name)},
+               {"pos", pos(formal.pos)},
+               {"default", formal.def ? expr(formal.def) : nullptr}});
+        json["ellipsis"] = v->formals->ellipsis;
+      }
+      json["body"] = expr(v->body);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprCall *>(e)) {
+      auto json = node("call", v->pos);
+      json["fun"] = expr(v->fun);
+      json["args"] = nlohmann::json::array();
+      for (auto *arg : v->args)
+        json["args"].push_back(expr(arg));
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprLet *>(e)) {
+      auto json = node("let", v->getPos());
+      json["attrs"] = attrs(*v->attrs);
+      json["body"] = expr(v->body);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprWith *>(e)) {
+      auto json = node("with", v->pos);
+      json["env"] = expr(v->attrs);
+      json["body"] = expr(v->body);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprIf *>(e)) {
+      auto json = node("if", v->pos);
+      json["cond"] = expr(v->cond);
+      json["then"] = expr(v->then);
+      json["else"] = expr(v->else_);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprAssert *>(e)) {
+      auto json = node("assert", v->pos);
+      json["cond"] = expr(v->cond);
+      json["body"] = expr(v->body);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprOpNot *>(e)) {
+      auto json = node("not", v->getPos());
+      json["expr"] = expr(v->e);
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprOpEq *>(e))
+      return binary("==", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpNEq *>(e))
+      return binary("!=", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpAnd *>(e))
+      return binary("&&", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpOr *>(e))
+      return binary("||", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpImpl *>(e))
+      return binary("->", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpUpdate *>(e))
+      return binary("//", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprOpConcatLists *>(e))
+      return binary("++", v->pos, v->e1, v->e2);
+    if (auto *v = dynamic_cast<nix::ExprConcatStrings *>(e)) {
+      // `a + b` and interpolation share a node. String interpolation forces
+      // every part to a string; path interpolation does not, but starts with
+      // a path that, unlike any path literal, ends in a slash.
+      auto *path = v->es.empty()
+                       ? nullptr
+                       : dynamic_cast<nix::ExprPath *>(v->es.front().second);
+      auto json = node("concat", v->pos);
+      json["interpolation"] =
+          v->forceString || (path && path->v.pathStr().ends_with('/'));
+      json["parts"] = nlohmann::json::array();
+      for (auto &[_, part] : v->es)
+        json["parts"].push_back(expr(part));
+      return json;
+    }
+    if (auto *v = dynamic_cast<nix::ExprPos *>(e))
+      return node("cur_pos", v->pos);
+    throw nix::Error("unsupported expression in AST export");
+  }
+};
+
+nix_parsed_expr *parse(nix_c_context *context, EvalState *state,
+                       const char *expr, const char *path, bool allow_unbound,
+                       nix_get_string_callback syntax_error,
+                       void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !expr || !path) {
+    nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+    return nullptr;
+  }
+  try {
+    try {
+      auto &es = state->state;
+      auto base = es.rootPath(nix::CanonPath(path));
+      if (!allow_unbound)
+        return new nix_parsed_expr{es.parseExprFromString(expr, base), nullptr};
+      auto unbound =
+          std::make_unique<nix::ExprWith>(nix::noPos, nullptr, nullptr);
+      auto env =
+          std::make_shared<nix::StaticEnv>(unbound.get(), es.staticBaseEnv);
+      auto *parsed = es.parseExprFromString(expr, base, env);
+      return new nix_parsed_expr{parsed, std::move(unbound)};
+    } catch (nix::ParseError &e) {
+      if (syntax_error) {
+        auto s = syntax_error_json(e).dump();
+        syntax_error(s.c_str(), (unsigned)s.size(), user_data);
+      }
+      throw;
+    }
+  }
+  NIXC_CATCH_ERRS_NULL
+}
+
+} // namespace
+
+extern "C" {
+
+nix_parsed_expr *nix_parse_expr(nix_c_context *context, EvalState *state,
+                                const char *expr, const char *path,
+                                nix_get_string_callback syntax_error,
+                                void *user_data) {
+  return parse(context, state, expr, path, false, syntax_error, user_data);
+}
+
+nix_parsed_expr *nix_parse_expr_unbound(nix_c_context *context,
+                                        EvalState *state, const char *expr,
+                                        const char *path,
+                                        nix_get_string_callback syntax_error,
+                                        void *user_data) {
+  return parse(context, state, expr, path, true, syntax_error, user_data);
+}
+
+nix_err nix_eval_state_global_names(nix_c_context *context, EvalState *state,
+                                    nix_get_string_callback callback,
+                                    void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto names = nlohmann::json::array();
+    for (auto &[name, _] : state->state.staticBaseEnv->vars)
+      names.push_back(
+          std::string(std::string_view(state->state.symbols[name])));
+    auto s = names.dump();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_parsed_expr_eval(nix_c_context *context, EvalState *state,
+                             nix_parsed_expr *expr, nix_value *value) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !expr || !value || !value->value)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  if (expr->unbound)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN,
+                           "expression was parsed for analysis only");
+  try {
+    state->state.eval(expr->expr, *value->value);
+    state->state.forceValue(*value->value, nix::noPos);
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_parsed_expr_ast(nix_c_context *context, EvalState *state,
+                            nix_parsed_expr *expr,
+                            nix_get_string_callback callback,
+                            void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !expr || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto s = AstWriter{state->state, expr->unbound.get()}
+                 .expr(expr->expr)
+                 .dump();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_parsed_expr_show(nix_c_context *context, EvalState *state,
+                             nix_parsed_expr *expr,
+                             nix_get_string_callback callback,
+                             void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !expr || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    std::ostringstream out;
+    expr->expr->show(state->state.symbols, out);
+    auto s = out.str();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+void nix_parsed_expr_free(nix_parsed_expr *expr) { delete expr; }
+
+} // extern "C"
@@ -0,0 +1,37 @@
+// Shim for NixPrinter's repeated-value detection.
+//
+// Two nix_values holding the same attribute set or list are distinct slots
+// that share one Bindings (or element array). `nix repl` marks the second
+// occurrence as «repeated» by comparing those shared pointers; the C API
+// offers no way to see them, so we expose the pointer as an opaque number.
+
+#include <nix/expr/eval.hh>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+extern "C" {
+
+uintptr_t nix_value_identity(nix_c_context *context, const nix_value *value) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!value || !value->value)
+    return 0;
+  auto &v = *value->value;
+  switch (v.type()) {
+  case nix::nAttrs:
+    return reinterpret_cast<uintptr_t>(v.attrs());
+  case nix::nList:
+    if (v.listSize() == 0)
+      return 0;
+    return reinterpret_cast<uintptr_t>(v.listView().data());
+  default:
+    return 0;
+  }
+}
+
+} // extern "C"
@@ -0,0 +1,130 @@
+// Shims for per-evaluator settings.
+//
+// nix_eval_state_builder owns the EvalSettings its EvalState is built with,
+// but the C API only fills them from the process-wide configuration
+// (nix_eval_state_builder_load). Setting them by name lets two evaluators in
+// one process run under different policies. Restricted evaluation also needs
+// a way to allow paths outside the store, which nix_eval_state_allow_path
+// does not.
+
+#include <map>
+#include <string>
+#include <vector>
+
+#include <nix/expr/eval.hh>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+extern "C" {
+
+nix_err nix_eval_state_builder_set_setting(nix_c_context *context,
+                                           nix_eval_state_builder *builder,
+                                           const char *name,
+                                           const char *value) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!builder || !name || !value)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    if (!builder->settings.set(name, value))
+      return nix_set_err_msg(
+          context, NIX_ERR_KEY,
+          (std::string("unknown evaluator setting '") + name + "'").c_str());
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_eval_state_allow_path_prefix(nix_c_context *context,
+                                         EvalState *state, const char *path) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !path)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    state->state.allowPath(nix::Path(path));
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_eval_state_set_builtins(nix_c_context *context, EvalState *state,
+                                    const char **names, nix_value **values,
+                                    const bool *top_level, size_t n) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || (n && (!names || !values || !top_level)))
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &es = state->state;
+    // Later changes to a name win.
+    std::map<std::string, std::pair<nix::Value *, bool>> changes;
+    for (size_t i = 0; i < n; i++) {
+      if (!names[i])
+        return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+      changes[names[i]] = {values[i] ? values[i]->value : nullptr,
+                           top_level[i]};
+    }
+
+    // The top-level name of builtin `name` is `name` or `__name`, whichever
+    // the base environment binds to the same value.
+    auto &builtins = es.getBuiltins();
+    auto &staticEnv = *es.staticBaseEnv;
+    auto topLevelName = [&](const std::string &name) -> nix::Symbol {
+      auto attr = builtins.attrs()->get(es.symbols.create(name));
+      if (!attr)
+        return {};
+      for (auto &var : {name, "__" + name}) {
+        auto sym = es.symbols.create(var);
+        auto it = staticEnv.find(sym);
+        if (it != staticEnv.vars.end() &&
+            es.baseEnv.values[it->second] == attr->value)
+          return sym;
+      }
+      return {};
+    };
+
+    // Resolve every top-level name before changing anything, so that an
+    // error leaves the evaluator as it was.
+    std::vector<std::pair<nix::Symbol, nix::Value *>> topLevel;
+    for (auto &[name, change] : changes) {
+      auto &[value, bind] = change;
+      if (value && !bind)
+        continue;
+      auto sym = topLevelName(name);
+      if (sym)
+        topLevel.emplace_back(sym, value);
+      else if (value)
+        return nix_set_err_msg(
+            context, NIX_ERR_KEY,
+            ("'" + name + "' is not a top-level builtin").c_str());
+    }
+
+    // The base environment has no room for new names, so a top-level name is
+    // rebound in place or removed from the static environment, which makes it
+    // unbound in expressions parsed from now on.
+    for (auto &[sym, value] : topLevel) {
+      auto it = staticEnv.find(sym);
+      if (value)
+        es.baseEnv.values[it->second] = value;
+      else
+        staticEnv.vars.erase(it);
+    }
+
+    auto bindings = es.buildBindings(builtins.attrs()->size() + n);
+    for (auto &attr : *builtins.attrs())
+      if (!changes.count(
+              std::string(std::string_view(es.symbols[attr.name]))))
+        bindings.insert(attr);
+    for (auto &[name, change] : changes)
+      if (change.first)
+        bindings.insert(es.symbols.create(name), change.first);
+    builtins.mkAttrs(bindings.finish());
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
@@ -0,0 +1,62 @@
+// Shims for reading and building string context without realising it.
+//
+// The C API only exposes string context through nix_string_realise, which
+// builds every referenced derivation. Snapshotting or reconstructing a value
+// must carry the context along unchanged instead, so we read and write it
+// through the C++ API in its encoded form (NixStringContextElem::to_string):
+//
+//   /nix/store/...-foo          opaque store path
+//   =/nix/store/...-foo.drv     derivation with all its outputs
+//   !out!/nix/store/...-foo.drv single derivation output
+
+#include <nix/expr/eval.hh>
+#include <nix/expr/value/context.hh>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+extern "C" {
+
+nix_err nix_get_string_context(nix_c_context *context, const nix_value *value,
+                               nix_get_string_callback callback,
+                               void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!value || !value->value || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    auto &v = *value->value;
+    if (v.type() != nix::nString)
+      return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "value is not a string");
+    nix::NixStringContext ctx;
+    nix::copyContext(v, ctx);
+    for (auto &elem : ctx) {
+      auto s = elem.to_string();
+      callback(s.c_str(), (unsigned)s.size(), user_data);
+    }
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_init_string_with_context(nix_c_context *context, EvalState *state,
+                                     nix_value *value, const char *str,
+                                     const char *const *elems,
+                                     size_t elems_len) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !value || !value->value || !str || (elems_len && !elems))
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    nix::NixStringContext ctx;
+    for (size_t i = 0; i < elems_len; i++)
+      ctx.insert(nix::NixStringContextElem::parse(elems[i]));
+    value->value->mkString(str, ctx, state->state.mem);
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
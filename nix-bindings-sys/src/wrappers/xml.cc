@@ -0,0 +1,92 @@
+// Shims for XML export, as `builtins.toXML` and `nix-instantiate --xml` do.
+//
+// printValueAsXML writes to a std::ostream the C API cannot reach, so we
+// collect its output and pass it to a callback. External values print
+// themselves into an XMLWriter that the C API hands them as an opaque
+// pointer; the element helpers below let them write to it.
+
+#include <sstream>
+
+#include <nix/expr/eval.hh>
+#include <nix/expr/value-to-xml.hh>
+#include <nix/util/xml-writer.hh>
+
+#include <nix_api_expr.h>
+#include <nix_api_expr_internal.h>
+#include <nix_api_util.h>
+#include <nix_api_util_internal.h>
+
+#include "nix_api_expr_shim.h"
+
+namespace {
+
+nix::XMLAttrs xml_attrs(const char **attr_names, const char **attr_values,
+                        size_t n_attrs) {
+  nix::XMLAttrs attrs;
+  for (size_t i = 0; i < n_attrs; i++)
+    attrs.emplace(attr_names[i], attr_values[i]);
+  return attrs;
+}
+
+} // namespace
+
+extern "C" {
+
+nix_err nix_value_to_xml(nix_c_context *context, EvalState *state,
+                         nix_value *value, bool strict, bool location,
+                         nix_get_string_callback callback, void *user_data) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!state || !value || !value->value || !callback)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    std::ostringstream out;
+    nix::NixStringContext string_context;
+    nix::printValueAsXML(state->state, strict, location, *value->value, out,
+                         string_context, nix::noPos);
+    auto s = out.str();
+    callback(s.c_str(), (unsigned)s.size(), user_data);
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_xml_open_element(nix_c_context *context, void *doc,
+                             const char *name, const char **attr_names,
+                             const char **attr_values, size_t n_attrs) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!doc || !name || (n_attrs && (!attr_names || !attr_values)))
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    static_cast<nix::XMLWriter *>(doc)->openElement(
+        name, xml_attrs(attr_names, attr_values, n_attrs));
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_xml_close_element(nix_c_context *context, void *doc) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!doc)
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    static_cast<nix::XMLWriter *>(doc)->closeElement();
+  }
+  NIXC_CATCH_ERRS
+}
+
+nix_err nix_xml_write_empty_element(nix_c_context *context, void *doc,
+                                    const char *name, const char **attr_names,
+                                    const char **attr_values, size_t n_attrs) {
+  if (context)
+    context->last_err_code = NIX_OK;
+  if (!doc || !name || (n_attrs && (!attr_names || !attr_values)))
+    return nix_set_err_msg(context, NIX_ERR_UNKNOWN, "null argument");
+  try {
+    static_cast<nix::XMLWriter *>(doc)->writeEmptyElement(
+        name, xml_attrs(attr_names, attr_values, n_attrs));
+  }
+  NIXC_CATCH_ERRS
+}
+
+} // extern "C"
@@ -0,0 +1,512 @@
+//! Static analysis of Nix expressions.
+//!
+//! Finds variables nothing binds, bindings that shadow an enclosing one and
+//! `let` bindings that are never used, without evaluating anything. Scoping
+//! follows the real parser's [syntax tree](crate::Ast), including its rules
+//! for `with`: a lexical binding always wins over a `with`, and a name that
+//! is not bound lexically inside a `with` may come from its set, so it is
+//! never reported as free.
+
+#![cfg(feature = "shim")]
+
+use std::{collections::HashSet, fmt, path::Path};
+
+use crate::{
+  Ast,
+  AstKind,
+  AttrName,
+  AttrSet,
+  BindingValue,
+  Error,
+  EvalState,
+  Result,
+  SourcePos,
+  error::checked_string_from_callback,
+  json::JsonTree,
+  sys,
+};
+
+/// A finding of [`EvalState::analyze`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  /// What was found.
+  pub kind: DiagnosticKind,
+  /// The variable or binding concerned.
+  pub name: String,
+  /// Where the variable is used or the binding is introduced.
+  pub pos:  Option<SourcePos>,
+}
+
+/// The kinds of [`Diagnostic`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+  /// A variable that is not bound lexically, not a builtin, and not inside
+  /// any `with`. Evaluating it fails with "undefined variable".
+  FreeVariable,
+  /// A `let` binding, `rec` attribute or function argument with the same
+  /// name as an enclosing one, which becomes unreachable in its scope.
+  Shadowed {
+    /// Where the shadowed binding was introduced.
+    previous: Option<SourcePos>,
+  },
+  /// A `let` binding that nothing refers to. Names starting with `_` are
+  /// exempt, as is customary.
+  UnusedBinding,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(pos) = &self.pos {
+      write!(f, "{pos}: ")?;
+    }
+    let name = &self.name;
+    match &self.kind {
+      DiagnosticKind::FreeVariable => write!(f, "undefined variable '{name}'"),
+      DiagnosticKind::Shadowed {
+        previous: Some(pos),
+      } => {
+        write!(f, "'{name}' shadows the binding at {pos}")
+      },
+      DiagnosticKind::Shadowed { previous: None } => {
+        write!(f, "'{name}' shadows an enclosing binding")
+      },
+      DiagnosticKind::UnusedBinding => write!(f, "unused binding '{name}'"),
+    }
+  }
+}
+
+impl EvalState {
+  /// Analyse a Nix expression without evaluating it.
+  ///
+  /// `base_path` is the directory relative paths in `expr` resolve against.
+  /// Diagnostics are returned in source order.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::Syntax`] if `expr` does not parse.
+  pub fn analyze(
+    &self,
+    expr: &str,
+    base_path: &str,
+  ) -> Result<Vec<Diagnostic>> {
+    let ast = self.parse_unbound(expr, base_path)?.ast()?;
+    let globals = self.global_names()?;
+
+    let mut analyzer = Analyzer {
+      globals:     &globals,
+      scopes:      Vec::new(),
+      diagnostics: Vec::new(),
+    };
+    analyzer.expr(&ast);
+
+    let mut diagnostics = analyzer.diagnostics;
+    diagnostics.sort_by_key(|d| d.pos.as_ref().map(|p| (p.line, p.column)));
+    Ok(diagnostics)
+  }
+
+  /// Analyse the Nix file at `path`, as [`analyze`](Self::analyze) does.
+  ///
+  /// Relative paths resolve against the file's directory, and diagnostics
+  /// name the file.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::Io`] if the file cannot be read as UTF-8, or
+  /// [`Error::Syntax`] if it does not parse.
+  pub fn analyze_file(
+    &self,
+    path: impl AsRef<Path>,
+  ) -> Result<Vec<Diagnostic>> {
+    let path = path.as_ref();
+    let expr = std::fs::read_to_string(path)?;
+    let base_path = path.parent().unwrap_or_else(|| Path::new("."));
+    let file = path.display().to_string();
+
+    let in_file = |pos: &mut Option<SourcePos>| {
+      if let Some(pos) = pos {
+        pos.file.clone_from(&file);
+      }
+    };
+    match self.analyze(&expr, &base_path.to_string_lossy()) {
+      Ok(mut diagnostics) => {
+        for diagnostic in &mut diagnostics {
+          in_file(&mut diagnostic.pos);
+          if let DiagnosticKind::Shadowed { previous } = &mut diagnostic.kind {
+            in_file(previous);
+          }
+        }
+        Ok(diagnostics)
+      },
+      Err(Error::Syntax {
+        line,
+        column,
+        message,
+        ..
+      }) => {
+        Err(Error::Syntax {
+          file,
+          line,
+          column,
+          message,
+        })
+      },
+      Err(e) => Err(e),
+    }
+  }
+
+  /// Names in scope at the top level: `true`, `builtins`, `import`, the
+  /// `__`-prefixed builtins and so on.
+  fn global_names(&self) -> Result<HashSet<String>> {
+    // SAFETY: context and state are valid; the callback only runs during
+    // the call.
+    let json = unsafe {
+      let ctx = self.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_eval_state_global_names(
+          ctx,
+          self.inner.as_ptr(),
+          callback,
+          user_data,
+        )
+      })?
+    };
+    JsonTree::parse(&json)?
+      .as_list()
+      .ok_or_else(|| Error::Unknown("malformed global names from Nix".into()))?
+      .iter()
+      .map(|name| {
+        name.as_str().map(str::to_owned).ok_or_else(|| {
+          Error::Unknown("malformed global names from Nix".into())
+        })
+      })
+      .collect()
+  }
+}
+
+/// A binding while its scope is open.
+struct Bound {
+  name: String,
+  pos:  Option<SourcePos>,
+  used: bool,
+}
+
+enum Scope {
+  Bindings {
+    names:         Vec<Bound>,
+    report_unused: bool,
+  },
+  With,
+}
+
+struct Analyzer<'a> {
+  globals:     &'a HashSet<String>,
+  scopes:      Vec<Scope>,
+  diagnostics: Vec<Diagnostic>,
+}
+
+impl Analyzer<'_> {
+  fn report(
+    &mut self,
+    kind: DiagnosticKind,
+    name: &str,
+    pos: &Option<SourcePos>,
+  ) {
+    self.diagnostics.push(Diagnostic {
+      kind,
+      name: name.to_owned(),
+      pos: pos.clone(),
+    });
+  }
+
+  /// The innermost lexical binding of `name`.
+  fn lookup(&mut self, name: &str) -> Option<&mut Bound> {
+    self.scopes.iter_mut().rev().find_map(|scope| {
+      match scope {
+        Scope::Bindings { names, .. } => {
+          names.iter_mut().find(|bound| bound.name == name)
+        },
+        Scope::With => None,
+      }
+    })
+  }
+
+  fn var(&mut self, name: &str, pos: &Option<SourcePos>) {
+    if let Some(bound) = self.lookup(name) {
+      bound.used = true;
+      return;
+    }
+    let in_with = self.scopes.iter().any(|scope| matches!(scope, Scope::With));
+    if !in_with && !self.globals.contains(name) {
+      self.report(DiagnosticKind::FreeVariable, name, pos);
+    }
+  }
+
+  /// Open a scope binding `names`. Names introduced by `inherit name;` are
+  /// passed with `shadows: false`, since they rebind the same value.
+  fn push(
+    &mut self,
+    names: Vec<(String, Option<SourcePos>, bool)>,
+    report_unused: bool,
+  ) {
+    let mut bound = Vec::with_capacity(names.len());
+    for (name, pos, shadows) in names {
+      if shadows && let Some(previous) = self.lookup(&name) {
+        let previous = previous.pos.clone();
+        self.report(DiagnosticKind::Shadowed { previous }, &name, &pos);
+      }
+      bound.push(Bound {
+        name,
+        pos,
+        used: false,
+      });
+    }
+    self.scopes.push(Scope::Bindings {
+      names: bound,
+      report_unused,
+    });
+  }
+
+  fn pop(&mut self) {
+    if let Some(Scope::Bindings {
+      names,
+      report_unused: true,
+    }) = self.scopes.pop()
+    {
+      for bound in names {
+        if !bound.used && !bound.name.starts_with('_') {
+          self.report(DiagnosticKind::UnusedBinding, &bound.name, &bound.pos);
+        }
+      }
+    }
+  }
+
+  fn attr_path(&mut self, path: &[AttrName]) {
+    for name in path {
+      if let AttrName::Dynamic(expr) = name {
+        self.expr(expr);
+      }
+    }
+  }
+
+  /// Resolve the bindings of a set or `let`. For recursive ones, the
+  /// scope they open is left open for the caller to close.
+  fn attrs(&mut self, set: &AttrSet, report_unused: bool) {
+    // `inherit name;` looks `name` up outside the set, even in `rec`.
+    for binding in &set.bindings {
+      if let (AttrName::Static(name), BindingValue::Inherit) =
+        (&binding.name, &binding.value)
+      {
+        self.var(name, &binding.pos);
+      }
+    }
+
+    if set.recursive {
+      let names = set
+        .bindings
+        .iter()
+        .filter_map(|binding| {
+          match &binding.name {
+            AttrName::Static(name) => {
+              let shadows = binding.value != BindingValue::Inherit;
+              Some((name.clone(), binding.pos.clone(), shadows))
+            },
+            AttrName::Dynamic(_) => None,
+          }
+        })
+        .collect();
+      self.push(names, report_unused);
+    }
+
+    for source in &set.inherit_from {
+      self.expr(source);
+    }
+    for binding in &set.bindings {
+      if let AttrName::Dynamic(name) = &binding.name {
+        self.expr(name);
+      }
+      if let BindingValue::Expr(value) = &binding.value {
+        self.expr(value);
+      }
+    }
+  }
+
+  fn expr(&mut self, ast: &Ast) {
+    match &ast.kind {
+      AstKind::Int(_)
+      | AstKind::Float(_)
+      | AstKind::String(_)
+      | AstKind::Path(_)
+      | AstKind::CurPos => {},
+      AstKind::Var { name, .. } => self.var(name, &ast.pos),
+      AstKind::Select {
+        expr,
+        path,
+        default,
+      } => {
+        self.expr(expr);
+        self.attr_path(path);
+        if let Some(default) = default {
+          self.expr(default);
+        }
+      },
+      AstKind::HasAttr { expr, path } => {
+        self.expr(expr);
+        self.attr_path(path);
+      },
+      AstKind::Attrs(set) => {
+        self.attrs(set, false);
+        if set.recursive {
+          self.pop();
+        }
+      },
+      AstKind::Let { bindings, body } => {
+        self.attrs(bindings, true);
+        self.expr(body);
+        self.pop();
+      },
+      AstKind::Lambda {
+        arg, formals, body, ..
+      } => {
+        let mut names = Vec::new();
+        if let Some(arg) = arg {
+          names.push((arg.clone(), ast.pos.clone(), true));
+        }
+        for formal in formals.iter().flatten() {
+          names.push((formal.name.clone(), formal.pos.clone(), true));
+        }
+        self.push(names, false);
+        for formal in formals.iter().flatten() {
+          if let Some(default) = &formal.default {
+            self.expr(default);
+          }
+        }
+        self.expr(body);
+        self.pop();
+      },
+      AstKind::With { env, body } => {
+        self.expr(env);
+        self.scopes.push(Scope::With);
+        self.expr(body);
+        self.scopes.pop();
+      },
+      AstKind::Call { fun, args } => {
+        self.expr(fun);
+        for arg in args {
+          self.expr(arg);
+        }
+      },
+      AstKind::List(items) | AstKind::Interpolation(items) => {
+        for item in items {
+          self.expr(item);
+        }
+      },
+      AstKind::If { cond, then, else_ } => {
+        self.expr(cond);
+        self.expr(then);
+        self.expr(else_);
+      },
+      AstKind::Assert { cond, body } => {
+        self.expr(cond);
+        self.expr(body);
+      },
+      AstKind::Not(expr) => self.expr(expr),
+      AstKind::BinaryOp { lhs, rhs, .. } => {
+        self.expr(lhs);
+        self.expr(rhs);
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  fn findings(state: &EvalState, expr: &str) -> Vec<(DiagnosticKind, String)> {
+    state
+      .analyze(expr, "/")
+      .expect("Failed to analyze")
+      .into_iter()
+      .map(|d| (d.kind, d.name))
+      .collect()
+  }
+
+  #[test]
+  #[serial]
+  fn test_analyze_free_variables() {
+    let state = setup();
+    let diagnostics = state.analyze("{ a }: a + b", "/").unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::FreeVariable);
+    assert_eq!(diagnostics[0].name, "b");
+    let pos = diagnostics[0].pos.as_ref().expect("b has a position");
+    assert_eq!((pos.line, pos.column), (1, 12));
+
+    // Builtins are bound; anything may come from a `with`.
+    assert!(findings(&state, "map toString [ true null ]").is_empty());
+    assert_eq!(findings(&state, "with pkgs; [ hello ]"), vec![(
+      DiagnosticKind::FreeVariable,
+      "pkgs".to_owned()
+    )]);
+    // Interpolated path parts are expressions like any other.
+    assert!(findings(&state, "x: ./a/${x}/b").is_empty());
+    assert_eq!(findings(&state, "x: ./a/${y}"), vec![(
+      DiagnosticKind::FreeVariable,
+      "y".to_owned()
+    )]);
+  }
+
+  #[test]
+  #[serial]
+  fn test_analyze_bindings() {
+    let state = setup();
+    assert_eq!(findings(&state, "let a = 1; b = 2; _c = 3; in a"), vec![(
+      DiagnosticKind::UnusedBinding,
+      "b".to_owned()
+    )]);
+    // Attributes of a `rec` set are its result, so never unused.
+    assert!(findings(&state, "rec { a = 1; b = a; }").is_empty());
+    // `inherit x;` rebinds the outer `x` rather than shadowing it.
+    assert!(findings(&state, "x: let inherit x; in x").is_empty());
+
+    let diagnostics = state.analyze("let x = 1; in x: x", "/").unwrap();
+    assert_eq!(diagnostics.len(), 2, "{diagnostics:?}");
+    assert_eq!(diagnostics[0].kind, DiagnosticKind::UnusedBinding);
+    let DiagnosticKind::Shadowed {
+      previous: Some(previous),
+    } = &diagnostics[1].kind
+    else {
+      panic!("expected shadowing, got {diagnostics:?}");
+    };
+    assert_eq!((previous.line, previous.column), (1, 5));
+  }
+
+  #[test]
+  #[serial]
+  fn test_analyze_file() {
+    let state = setup();
+    let mut file =
+      tempfile::NamedTempFile::new().expect("Failed to create temp file");
+    write!(file, "{{ lib }}:\nlib.id unknown").unwrap();
+
+    let diagnostics = state.analyze_file(file.path()).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    let pos = diagnostics[0].pos.as_ref().unwrap();
+    assert_eq!(pos.file, file.path().display().to_string());
+    assert_eq!(pos.line, 2);
+
+    let missing = file.path().with_extension("missing");
+    assert!(matches!(state.analyze_file(missing), Err(Error::Io(_))));
+
+    // The parse is for analysis only; syntax errors still surface.
+    assert!(matches!(
+      state.analyze("let in", "/"),
+      Err(Error::Syntax { .. })
+    ));
+  }
+}
@@ -0,0 +1,672 @@
+//! The syntax tree of a parsed expression, as the Nix parser builds it.
+//!
+//! [`ParsedExpr::ast`] exports the evaluator's own `Expr` tree, so what a
+//! linter sees is exactly what Nix evaluates. That includes the parser's
+//! desugaring: `a.b.c = 1;` becomes nested sets, `a - b` and `a < b` become
+//! calls to `__sub` and `__lessThan`, and `a.b or c` is a [`AstKind::Select`]
+//! with a default. Variables already know whether they resolve through a
+//! `with`.
+
+#![cfg(feature = "shim")]
+
+use std::fmt;
+
+use crate::{
+  Error,
+  Result,
+  SourcePos,
+  error::checked_string_from_callback,
+  json::JsonTree,
+  parse::ParsedExpr,
+  sys,
+};
+
+/// A node of the syntax tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ast {
+  /// What the node is.
+  pub kind: AstKind,
+  /// Where the node starts, if the parser recorded it. `let` has none.
+  pub pos:  Option<SourcePos>,
+}
+
+/// The kinds of syntax tree nodes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AstKind {
+  /// An integer literal.
+  Int(i64),
+  /// A float literal.
+  Float(f64),
+  /// A string without interpolation.
+  String(String),
+  /// A path literal, already made absolute.
+  Path(String),
+  /// A variable.
+  Var {
+    /// The identifier.
+    name:      String,
+    /// Whether no lexical binding matches, so the name is looked up in the
+    /// enclosing `with` scopes.
+    from_with: bool,
+  },
+  /// `expr.a.b`, or `expr.a.b or default`.
+  Select {
+    /// The set selected from.
+    expr:    Box<Ast>,
+    /// The attribute path.
+    path:    Vec<AttrName>,
+    /// The `or` fallback.
+    default: Option<Box<Ast>>,
+  },
+  /// `expr ? a.b`.
+  HasAttr {
+    /// The set tested.
+    expr: Box<Ast>,
+    /// The attribute path.
+    path: Vec<AttrName>,
+  },
+  /// `{ ... }` or `rec { ... }`.
+  Attrs(AttrSet),
+  /// `[ ... ]`.
+  List(Vec<Ast>),
+  /// A function.
+  Lambda {
+    /// Name the lambda was bound to, e.g. `f` in `let f = x: x;`.
+    name:     Option<String>,
+    /// Name the whole argument is bound to: `x` in `x: ...` or `args` in
+    /// `{ a }@args: ...`.
+    arg:      Option<String>,
+    /// Formal arguments of a `{ a, b ? 1 }: ...` lambda, sorted by name, or
+    /// `None` for a plain `x: ...` lambda.
+    formals:  Option<Vec<AstFormal>>,
+    /// Whether the formals end in `...`.
+    ellipsis: bool,
+    /// The function body.
+    body:     Box<Ast>,
+  },
+  /// Function application, with consecutive arguments collected.
+  Call {
+    /// The function called.
+    fun:  Box<Ast>,
+    /// The arguments, in order.
+    args: Vec<Ast>,
+  },
+  /// `let ...; in body`.
+  Let {
+    /// The bindings, always recursive.
+    bindings: AttrSet,
+    /// The body.
+    body:     Box<Ast>,
+  },
+  /// `with env; body`.
+  With {
+    /// The set brought into scope.
+    env:  Box<Ast>,
+    /// The body.
+    body: Box<Ast>,
+  },
+  /// `if cond then then_ else else_`.
+  If {
+    /// The condition.
+    cond:  Box<Ast>,
+    /// The `then` branch.
+    then:  Box<Ast>,
+    /// The `else` branch.
+    else_: Box<Ast>,
+  },
+  /// `assert cond; body`.
+  Assert {
+    /// The assertion.
+    cond: Box<Ast>,
+    /// The body.
+    body: Box<Ast>,
+  },
+  /// `!expr`.
+  Not(Box<Ast>),
+  /// A binary operator the parser keeps as its own node.
+  BinaryOp {
+    /// The operator.
+    op:  BinaryOp,
+    /// The left operand.
+    lhs: Box<Ast>,
+    /// The right operand.
+    rhs: Box<Ast>,
+  },
+  /// A string or path with interpolation: the literal pieces and the
+  /// interpolated expressions, in order.
+  Interpolation(Vec<Ast>),
+  /// `__curPos`.
+  CurPos,
+}
+
+/// Operators with their own node. The arithmetic and comparison operators
+/// other than `+` are calls to builtins such as `__sub` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+  /// `==`
+  Eq,
+  /// `!=`
+  NotEq,
+  /// `&&`
+  And,
+  /// `||`
+  Or,
+  /// `->`
+  Impl,
+  /// `//`
+  Update,
+  /// `++`
+  Concat,
+  /// `+`, which also concatenates strings and paths.
+  Add,
+}
+
+impl fmt::Display for BinaryOp {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      BinaryOp::Eq => "==",
+      BinaryOp::NotEq => "!=",
+      BinaryOp::And => "&&",
+      BinaryOp::Or => "||",
+      BinaryOp::Impl => "->",
+      BinaryOp::Update => "//",
+      BinaryOp::Concat => "++",
+      BinaryOp::Add => "+",
+    })
+  }
+}
+
+/// A component of an attribute path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrName {
+  /// A plain or quoted name.
+  Static(String),
+  /// An interpolated name, `${expr}`.
+  Dynamic(Box<Ast>),
+}
+
+/// The bindings of a set or a `let`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttrSet {
+  /// Whether bindings can refer to each other (`rec` and `let`).
+  pub recursive:    bool,
+  /// The bindings, in source order.
+  pub bindings:     Vec<Binding>,
+  /// The sources of `inherit (source) ...;`, referred to by
+  /// [`BindingValue::InheritFrom`].
+  pub inherit_from: Vec<Ast>,
+}
+
+/// One binding of an [`AttrSet`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+  /// The attribute name. Only sets, not `let`, have dynamic names.
+  pub name:  AttrName,
+  /// Where the name was written.
+  pub pos:   Option<SourcePos>,
+  /// What the name is bound to.
+  pub value: BindingValue,
+}
+
+/// The right-hand side of a [`Binding`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BindingValue {
+  /// `name = expr;`
+  Expr(Ast),
+  /// `inherit name;`, taking the variable of the same name from the
+  /// enclosing scope.
+  Inherit,
+  /// `inherit (source) name;`, with the index of the source in
+  /// [`AttrSet::inherit_from`].
+  InheritFrom(usize),
+}
+
+/// A formal argument of a lambda taking an attribute set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AstFormal {
+  /// Attribute name.
+  pub name:    String,
+  /// The default after `?`.
+  pub default: Option<Ast>,
+  /// Where the formal was written.
+  pub pos:     Option<SourcePos>,
+}
+
+fn malformed() -> Error {
+  Error::Unknown("malformed syntax tree from Nix".into())
+}
+
+impl Ast {
+  /// Recurses once per node, which is bounded because [`JsonTree::parse`]
+  /// rejects trees deeper than [`MAX_JSON_DEPTH`](crate::MAX_JSON_DEPTH).
+  fn from_json(tree: &JsonTree) -> Result<Self> {
+    let child = |key| Ast::from_json(tree.get(key)).map(Box::new);
+    let children = |key| {
+      tree
+        .get(key)
+        .as_list()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(Ast::from_json)
+        .collect::<Result<Vec<_>>>()
+    };
+    let string = |key| tree.get(key).as_str().map(str::to_owned);
+    let optional = |key| {
+      match tree.get(key) {
+        JsonTree::Null => Ok(None),
+        node => Ast::from_json(node).map(Some),
+      }
+    };
+
+    let kind = match tree.get("kind").as_str().ok_or_else(malformed)? {
+      "int" => AstKind::Int(tree.get("value").as_int().ok_or_else(malformed)?),
+      "float" => {
+        AstKind::Float(match tree.get("value") {
+          JsonTree::Float(f) => *f,
+          JsonTree::Int(i) => *i as f64,
+          // JSON has no infinity; a literal too large for a double is one.
+          JsonTree::Null => f64::INFINITY,
+          _ => return Err(malformed()),
+        })
+      },
+      "string" => AstKind::String(string("value").ok_or_else(malformed)?),
+      "path" => AstKind::Path(string("value").ok_or_else(malformed)?),
+      "var" => {
+        AstKind::Var {
+          name:      string("name").ok_or_else(malformed)?,
+          from_with: tree.get("from_with").as_bool().unwrap_or(false),
+        }
+      },
+      "select" => {
+        AstKind::Select {
+          expr:    child("expr")?,
+          path:    attr_path(tree.get("path"))?,
+          default: optional("default")?.map(Box::new),
+        }
+      },
+      "has_attr" => {
+        AstKind::HasAttr {
+          expr: child("expr")?,
+          path: attr_path(tree.get("path"))?,
+        }
+      },
+      "attrs" => AstKind::Attrs(AttrSet::from_json(tree.get("attrs"))?),
+      "list" => AstKind::List(children("elems")?),
+      "lambda" => {
+        let formals = match tree.get("formals") {
+          JsonTree::Null => None,
+          formals => {
+            Some(
+              formals
+                .as_list()
+                .ok_or_else(malformed)?
+                .iter()
+                .map(AstFormal::from_json)
+                .collect::<Result<_>>()?,
+            )
+          },
+        };
+        AstKind::Lambda {
+          name: string("name"),
+          arg: string("arg"),
+          formals,
+          ellipsis: tree.get("ellipsis").as_bool().unwrap_or(false),
+          body: child("body")?,
+        }
+      },
+      "call" => {
+        AstKind::Call {
+          fun:  child("fun")?,
+          args: children("args")?,
+        }
+      },
+      "let" => {
+        AstKind::Let {
+          bindings: AttrSet::from_json(tree.get("attrs"))?,
+          body:     child("body")?,
+        }
+      },
+      "with" => {
+        AstKind::With {
+          env:  child("env")?,
+          body: child("body")?,
+        }
+      },
+      "if" => {
+        AstKind::If {
+          cond:  child("cond")?,
+          then:  child("then")?,
+          else_: child("else")?,
+        }
+      },
+      "assert" => {
+        AstKind::Assert {
+          cond: child("cond")?,
+          body: child("body")?,
+        }
+      },
+      "not" => AstKind::Not(child("expr")?),
+      "binary" => {
+        let op = match tree.get("op").as_str() {
+          Some("==") => BinaryOp::Eq,
+          Some("!=") => BinaryOp::NotEq,
+          Some("&&") => BinaryOp::And,
+          Some("||") => BinaryOp::Or,
+          Some("->") => BinaryOp::Impl,
+          Some("//") => BinaryOp::Update,
+          Some("++") => BinaryOp::Concat,
+          _ => return Err(malformed()),
+        };
+        AstKind::BinaryOp {
+          op,
+          lhs: child("lhs")?,
+          rhs: child("rhs")?,
+        }
+      },
+      // `a + b` and string or path interpolation share a node.
+      "concat" => {
+        let mut parts = children("parts")?;
+        if tree.get("interpolation").as_bool().unwrap_or(true) {
+          AstKind::Interpolation(parts)
+        } else {
+          let (Some(rhs), Some(lhs), None) =
+            (parts.pop(), parts.pop(), parts.pop())
+          else {
+            return Err(malformed());
+          };
+          AstKind::BinaryOp {
+            op:  BinaryOp::Add,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+          }
+        }
+      },
+      "cur_pos" => AstKind::CurPos,
+      _ => return Err(malformed()),
+    };
+    Ok(Ast {
+      kind,
+      pos: SourcePos::from_json(tree.get("pos"))?,
+    })
+  }
+}
+
+fn attr_name(tree: &JsonTree) -> Result<AttrName> {
+  match tree.get("name").as_str() {
+    Some(name) => Ok(AttrName::Static(name.to_owned())),
+    None => {
+      Ok(AttrName::Dynamic(Box::new(Ast::from_json(
+        tree.get("expr"),
+      )?)))
+    },
+  }
+}
+
+fn attr_path(tree: &JsonTree) -> Result<Vec<AttrName>> {
+  tree
+    .as_list()
+    .ok_or_else(malformed)?
+    .iter()
+    .map(attr_name)
+    .collect()
+}
+
+impl AttrSet {
+  fn from_json(tree: &JsonTree) -> Result<Self> {
+    let bindings = tree
+      .get("bindings")
+      .as_list()
+      .ok_or_else(malformed)?
+      .iter()
+      .map(|binding| {
+        let value = if let Some(index) = binding.get("inherit_from").as_int() {
+          BindingValue::InheritFrom(
+            usize::try_from(index).map_err(|_| malformed())?,
+          )
+        } else if binding.get("inherit").as_bool() == Some(true) {
+          BindingValue::Inherit
+        } else {
+          BindingValue::Expr(Ast::from_json(binding.get("value"))?)
+        };
+        Ok(Binding {
+          name: attr_name(binding.get("name"))?,
+          pos: SourcePos::from_json(binding.get("pos"))?,
+          value,
+        })
+      })
+      .collect::<Result<_>>()?;
+    Ok(AttrSet {
+      recursive: tree.get("rec").as_bool().unwrap_or(false),
+      bindings,
+      inherit_from: tree
+        .get("inherit_from")
+        .as_list()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(Ast::from_json)
+        .collect::<Result<_>>()?,
+    })
+  }
+}
+
+impl AstFormal {
+  fn from_json(tree: &JsonTree) -> Result<Self> {
+    Ok(AstFormal {
+      name:    tree.get("name").as_str().ok_or_else(malformed)?.to_owned(),
+      default: match tree.get("default") {
+        JsonTree::Null => None,
+        node => Some(Ast::from_json(node)?),
+      },
+      pos:     SourcePos::from_json(tree.get("pos"))?,
+    })
+  }
+}
+
+impl ParsedExpr<'_> {
+  /// The syntax tree of the expression.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the tree cannot be exported, and [`Error::Json`]
+  /// if it nests deeper than [`MAX_JSON_DEPTH`](crate::MAX_JSON_DEPTH)
+  /// JSON levels.
+  pub fn ast(&self) -> Result<Ast> {
+    // SAFETY: context, state and expression are valid; the callback only
+    // runs during the call.
+    let json = unsafe {
+      let ctx = self.state.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_parsed_expr_ast(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          callback,
+          user_data,
+        )
+      })?
+    };
+    Ast::from_json(&JsonTree::parse(&json)?)
+  }
+
+  /// The expression printed back as Nix source, the way the evaluator shows
+  /// it: fully parenthesised and with the parser's desugaring applied.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if printing fails.
+  pub fn to_source(&self) -> Result<String> {
+    // SAFETY: context, state and expression are valid; the callback only
+    // runs during the call.
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_parsed_expr_show(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          callback,
+          user_data,
+        )
+      })
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::{EvalState, test_util::setup};
+
+  fn ast(state: &EvalState, expr: &str) -> Ast {
+    state
+      .parse(expr, "/")
+      .expect("Failed to parse")
+      .ast()
+      .expect("Failed to export")
+  }
+
+  #[test]
+  #[serial]
+  fn test_ast_let_lambda() {
+    let state = setup();
+    let tree = ast(&state, "let f = { a, b ? 1, ... }@args: a; in f");
+
+    let AstKind::Let { bindings, body } = tree.kind else {
+      panic!("expected let, got {tree:?}");
+    };
+    assert!(bindings.recursive);
+    assert_eq!(bindings.bindings.len(), 1);
+    assert_eq!(bindings.bindings[0].name, AttrName::Static("f".into()));
+    let BindingValue::Expr(lambda) = &bindings.bindings[0].value else {
+      panic!("expected a value");
+    };
+    let AstKind::Lambda {
+      name,
+      arg,
+      formals: Some(formals),
+      ellipsis,
+      ..
+    } = &lambda.kind
+    else {
+      panic!("expected a lambda, got {lambda:?}");
+    };
+    assert_eq!(name.as_deref(), Some("f"));
+    assert_eq!(arg.as_deref(), Some("args"));
+    assert!(ellipsis);
+    assert_eq!(formals.len(), 2);
+    assert!(formals[1].default.is_some());
+
+    let pos = lambda.pos.as_ref().expect("lambda has a position");
+    assert_eq!((pos.line, pos.column), (1, 9));
+    assert!(matches!(
+      body.kind,
+      AstKind::Var { ref name, from_with: false } if name == "f"
+    ));
+  }
+
+  #[test]
+  #[serial]
+  fn test_ast_attrs_and_strings() {
+    let state = setup();
+    let tree = ast(
+      &state,
+      "{ s, x, n }: with s; rec { inherit x; inherit (s) y z; \"${n}\" = \
+       \"a${w}\"; }",
+    );
+
+    let AstKind::Lambda { body, .. } = tree.kind else {
+      panic!("expected a lambda, got {tree:?}");
+    };
+    let AstKind::With { env, body } = body.kind else {
+      panic!("expected with, got {body:?}");
+    };
+    assert!(matches!(env.kind, AstKind::Var { .. }));
+    let AstKind::Attrs(set) = body.kind else {
+      panic!("expected a set");
+    };
+    assert!(set.recursive);
+    assert_eq!(set.inherit_from.len(), 1);
+    let values: Vec<_> = set.bindings.iter().map(|b| &b.value).collect();
+    assert_eq!(values[0], &BindingValue::Inherit);
+    assert_eq!(values[1], &BindingValue::InheritFrom(0));
+    assert_eq!(values[2], &BindingValue::InheritFrom(0));
+    assert!(matches!(set.bindings[3].name, AttrName::Dynamic(_)));
+    let BindingValue::Expr(string) = &set.bindings[3].value else {
+      panic!("expected a value");
+    };
+    let AstKind::Interpolation(parts) = &string.kind else {
+      panic!("expected interpolation, got {string:?}");
+    };
+    assert_eq!(parts.len(), 2);
+    // Unbound inside `with`, so looked up dynamically.
+    assert!(matches!(parts[1].kind, AstKind::Var {
+      from_with: true,
+      ..
+    }));
+  }
+
+  #[test]
+  #[serial]
+  fn test_ast_operators() {
+    let state = setup();
+    let body = |tree: Ast| {
+      match tree.kind {
+        AstKind::Lambda { body, .. } => *body,
+        _ => panic!("expected a lambda, got {tree:?}"),
+      }
+    };
+
+    let tree = body(ast(&state, "a: a.b or 1 + 2"));
+    let AstKind::BinaryOp { op, lhs, .. } = tree.kind else {
+      panic!("expected +, got {tree:?}");
+    };
+    assert_eq!(op, BinaryOp::Add);
+    assert!(matches!(lhs.kind, AstKind::Select {
+      default: Some(_),
+      ..
+    }));
+
+    // Path interpolation is not `+`, however many parts it has.
+    for (expr, len) in [("x: ./a/${x}/b", 3), ("x: ./a/${x}", 2)] {
+      let tree = body(ast(&state, expr));
+      let AstKind::Interpolation(parts) = tree.kind else {
+        panic!("expected interpolation, got {tree:?}");
+      };
+      assert_eq!(parts.len(), len);
+      assert!(matches!(parts[0].kind, AstKind::Path(_)));
+    }
+    let tree = body(ast(&state, "x: ./a + x"));
+    assert!(matches!(tree.kind, AstKind::BinaryOp {
+      op: BinaryOp::Add,
+      ..
+    }));
+
+    let tree = body(ast(&state, "a: a - 2"));
+    let AstKind::Call { fun, args } = tree.kind else {
+      panic!("expected a call, got {tree:?}");
+    };
+    assert!(
+      matches!(fun.kind, AstKind::Var { ref name, .. } if name == "__sub")
+    );
+    assert_eq!(args.len(), 2);
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_source() {
+    let state = setup();
+    let parsed = state.parse("a: if a then 1 else 2", "/").unwrap();
+    let source = parsed.to_source().expect("Failed to print");
+    assert_eq!(source, "(a: (if a then 1 else 2))");
+
+    for (expr, expected) in [
+      ("x: ./a/${x}/b", "(x: (/a/ + x + \"/b\"))"),
+      ("x: ./a/${x}", "(x: (/a/ + x))"),
+    ] {
+      let parsed = state.parse(expr, "/").unwrap();
+      assert_eq!(parsed.to_source().expect("Failed to print"), expected);
+    }
+  }
+}
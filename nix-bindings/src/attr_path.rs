@@ -0,0 +1,485 @@
+//! Attribute paths such as `pkgs.python3Packages.requests`.
+//!
+//! [`AttrPath`] parses and prints the dotted selection syntax accepted by
+//! `nix eval` and friends, extended with `[n]` for list elements, and
+//! [`Value::get_attr_path`] follows one, reporting the first missing
+//! component with "did you mean" suggestions.
+
+#![cfg(feature = "expr")]
+
+use std::{fmt, str::FromStr};
+
+use crate::{Error, NixValueOps, Result, Value, attrs::format_attr_name};
+
+/// Suggestions further than this edit distance from the missing name are
+/// dropped, as in Nix.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// At most this many suggestions are offered, as in Nix.
+const MAX_SUGGESTIONS: usize = 5;
+
+/// A parsed attribute path.
+///
+/// Attribute names are separated by `.`; a name containing dots, brackets
+/// or other special characters is written in double quotes, e.g.
+/// `a."b.c".d`. Inside quotes, `\"`, `\\`, `\n`, `\r`, `\t` and `\$` are
+/// unescaped so that [`Display`](fmt::Display) output parses back to the
+/// same path. A list element is written `[n]` directly after the list, e.g.
+/// `a.list[0].b`. The empty string is the empty path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AttrPath(Vec<AttrPathSegment>);
+
+/// One component of an [`AttrPath`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AttrPathSegment {
+  /// An attribute name.
+  Attr(String),
+  /// A list index, starting at 0.
+  Index(usize),
+}
+
+impl AttrPathSegment {
+  /// The attribute name, or `None` for a list index.
+  #[must_use]
+  pub fn as_attr(&self) -> Option<&str> {
+    match self {
+      AttrPathSegment::Attr(name) => Some(name),
+      AttrPathSegment::Index(_) => None,
+    }
+  }
+
+  /// The list index, or `None` for an attribute name.
+  #[must_use]
+  pub fn as_index(&self) -> Option<usize> {
+    match self {
+      AttrPathSegment::Attr(_) => None,
+      AttrPathSegment::Index(index) => Some(*index),
+    }
+  }
+}
+
+impl fmt::Display for AttrPathSegment {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AttrPathSegment::Attr(name) => f.write_str(&format_attr_name(name)),
+      AttrPathSegment::Index(index) => write!(f, "[{index}]"),
+    }
+  }
+}
+
+impl From<String> for AttrPathSegment {
+  fn from(name: String) -> Self {
+    AttrPathSegment::Attr(name)
+  }
+}
+
+impl From<&str> for AttrPathSegment {
+  fn from(name: &str) -> Self {
+    AttrPathSegment::Attr(name.to_string())
+  }
+}
+
+impl From<usize> for AttrPathSegment {
+  fn from(index: usize) -> Self {
+    AttrPathSegment::Index(index)
+  }
+}
+
+impl PartialEq<str> for AttrPathSegment {
+  fn eq(&self, other: &str) -> bool {
+    self.as_attr() == Some(other)
+  }
+}
+
+impl PartialEq<&str> for AttrPathSegment {
+  fn eq(&self, other: &&str) -> bool {
+    self.as_attr() == Some(*other)
+  }
+}
+
+impl AttrPath {
+  /// The empty path, selecting the value itself.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Parse an attribute path.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::InvalidAttrPath`] if a quote or bracket is not
+  /// closed, a name is empty, an index is not a number, or a segment is
+  /// followed by anything but `.` or `[`.
+  pub fn parse(input: &str) -> Result<Self> {
+    let invalid = |message: &str| {
+      Error::InvalidAttrPath {
+        input:   input.to_string(),
+        message: message.to_string(),
+      }
+    };
+
+    let mut segments = Vec::new();
+    if input.is_empty() {
+      return Ok(AttrPath(segments));
+    }
+    let mut chars = input.chars().peekable();
+    let mut after_dot = false;
+    loop {
+      if !after_dot && chars.peek() == Some(&'[') {
+        chars.next();
+        let mut digits = String::new();
+        loop {
+          match chars.next() {
+            None => return Err(invalid("missing closing bracket")),
+            Some(']') => break,
+            Some(c) => digits.push(c),
+          }
+        }
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+          return Err(invalid("list index must be a number"));
+        }
+        let index = digits
+          .parse()
+          .map_err(|_| invalid("list index must be a number"))?;
+        segments.push(AttrPathSegment::Index(index));
+      } else {
+        let mut segment = String::new();
+        if chars.peek() == Some(&'"') {
+          chars.next();
+          loop {
+            match chars.next() {
+              None => return Err(invalid("missing closing quote")),
+              Some('"') => break,
+              Some('\\') => {
+                match chars.next() {
+                  Some('n') => segment.push('\n'),
+                  Some('r') => segment.push('\r'),
+                  Some('t') => segment.push('\t'),
+                  Some(c) => segment.push(c),
+                  None => return Err(invalid("missing closing quote")),
+                }
+              },
+              Some(c) => segment.push(c),
+            }
+          }
+          if !matches!(chars.peek(), None | Some('.' | '[')) {
+            return Err(invalid(
+              "expected '.' or '[' after a quoted attribute name",
+            ));
+          }
+        } else {
+          while let Some(&c) = chars.peek() {
+            if matches!(c, '.' | '[') {
+              break;
+            }
+            if c == '"' {
+              return Err(invalid("unexpected quote inside an attribute name"));
+            }
+            segment.push(c);
+            chars.next();
+          }
+          if segment.is_empty() {
+            return Err(invalid("empty attribute name; write it as \"\""));
+          }
+        }
+        segments.push(AttrPathSegment::Attr(segment));
+      }
+      // Anything left starts another segment: a name after '.', an index
+      // at '['. A trailing '.' leaves an empty name.
+      match chars.peek() {
+        None => return Ok(AttrPath(segments)),
+        Some('.') => {
+          chars.next();
+          after_dot = true;
+        },
+        Some('[') => after_dot = false,
+        Some(_) => {
+          return Err(invalid("expected '.' or '[' after a list index"));
+        },
+      }
+    }
+  }
+
+  /// The path's segments, outermost first.
+  #[must_use]
+  pub fn segments(&self) -> &[AttrPathSegment] {
+    &self.0
+  }
+
+  /// Whether this is the empty path.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// Number of segments.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  /// Append a segment: an attribute name, or a list index given as a
+  /// `usize`.
+  pub fn push(&mut self, segment: impl Into<AttrPathSegment>) {
+    self.0.push(segment.into());
+  }
+}
+
+impl FromStr for AttrPath {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::parse(s)
+  }
+}
+
+impl fmt::Display for AttrPath {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for (i, segment) in self.0.iter().enumerate() {
+      if i > 0 && matches!(segment, AttrPathSegment::Attr(_)) {
+        f.write_str(".")?;
+      }
+      write!(f, "{segment}")?;
+    }
+    Ok(())
+  }
+}
+
+impl<S: Into<AttrPathSegment>> FromIterator<S> for AttrPath {
+  fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+    AttrPath(iter.into_iter().map(Into::into).collect())
+  }
+}
+
+impl From<Vec<String>> for AttrPath {
+  fn from(names: Vec<String>) -> Self {
+    names.into_iter().collect()
+  }
+}
+
+impl From<Vec<AttrPathSegment>> for AttrPath {
+  fn from(segments: Vec<AttrPathSegment>) -> Self {
+    AttrPath(segments)
+  }
+}
+
+impl<'a> IntoIterator for &'a AttrPath {
+  type IntoIter = std::slice::Iter<'a, AttrPathSegment>;
+  type Item = &'a AttrPathSegment;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.0.iter()
+  }
+}
+
+impl<'a> Value<'a> {
+  /// Follow a dotted attribute path such as `a."b.c".d`, forcing each
+  /// component.
+  ///
+  /// See [`AttrPath`] for the syntax.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::InvalidAttrPath`] if `path` does not parse, and
+  /// otherwise see [`select`](Self::select).
+  pub fn get_attr_path(&self, path: &str) -> Result<Value<'a>> {
+    self.select(&AttrPath::parse(path)?)
+  }
+
+  /// Follow a parsed attribute path, forcing each component.
+  ///
+  /// The empty path returns this value, forced. Intermediate components
+  /// are forced as they are reached and the result is forced last.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::AttrNotFound`] naming the first missing attribute,
+  /// with suggestions taken from the attribute names at that point, and
+  /// [`Error::IndexOutOfBounds`] for a missing list element. Returns
+  /// [`Error::Conversion`] carrying the path so far if a component is not
+  /// an attribute set or list as the path expects, and any error raised
+  /// while forcing.
+  pub fn select(&self, path: &AttrPath) -> Result<Value<'a>> {
+    self.force_shared()?;
+    let mut current = self.clone();
+    for (i, segment) in path.0.iter().enumerate() {
+      let at = || AttrPath(path.0[..i].to_vec()).to_string();
+      let not_here = |e| {
+        match e {
+          Error::InvalidType { expected, actual } => {
+            Error::Conversion {
+              path:    at(),
+              message: format!("expected {expected}, got {actual}"),
+            }
+          },
+          e => e,
+        }
+      };
+      let name = match segment {
+        AttrPathSegment::Attr(name) => name,
+        AttrPathSegment::Index(index) => {
+          let mut items = current.list_items().map_err(not_here)?;
+          if *index >= items.len() {
+            return Err(Error::IndexOutOfBounds {
+              index:  *index,
+              length: items.len(),
+            });
+          }
+          current = items.swap_remove(*index);
+          continue;
+        },
+      };
+      let child = current.get_attr_lazy(name).map_err(not_here)?;
+      let Some(child) = child else {
+        let names = current.attr_keys()?;
+        return Err(Error::AttrNotFound {
+          path:        at(),
+          attr:        name.clone(),
+          suggestions: suggestions(name, &names),
+        });
+      };
+      current = child;
+    }
+    current.force_shared()?;
+    Ok(current)
+  }
+}
+
+/// Up to [`MAX_SUGGESTIONS`] of `names` within
+/// [`MAX_SUGGESTION_DISTANCE`] edits of `query`, closest first.
+fn suggestions(query: &str, names: &[String]) -> Vec<String> {
+  let mut scored: Vec<_> = names
+    .iter()
+    .map(|name| (levenshtein(query, name), name))
+    .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+    .collect();
+  scored.sort();
+  scored
+    .into_iter()
+    .take(MAX_SUGGESTIONS)
+    .map(|(_, name)| name.clone())
+    .collect()
+}
+
+/// Edit distance between `a` and `b`, counting characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, &cb) in b.iter().enumerate() {
+      let above = row[j + 1];
+      row[j + 1] = if ca == cb {
+        diagonal
+      } else {
+        1 + diagonal.min(above).min(row[j])
+      };
+      diagonal = above;
+    }
+  }
+  row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  fn test_attr_path_parse() {
+    let path = AttrPath::parse("a.\"b.c\".d").unwrap();
+    assert_eq!(path.segments(), ["a", "b.c", "d"]);
+    assert_eq!(path.to_string(), "a.\"b.c\".d");
+
+    let path = AttrPath::parse("\"\".\"x\\\"y\".if").unwrap();
+    assert_eq!(path.segments(), ["", "x\"y", "if"]);
+    assert_eq!(AttrPath::parse(&path.to_string()).unwrap(), path);
+
+    let path = AttrPath::parse("[0].a[1][2].\"0\"").unwrap();
+    assert_eq!(path.segments(), [
+      AttrPathSegment::Index(0),
+      "a".into(),
+      AttrPathSegment::Index(1),
+      AttrPathSegment::Index(2),
+      "0".into(),
+    ]);
+    assert_eq!(path.to_string(), "[0].a[1][2].\"0\"");
+
+    assert!(AttrPath::parse("").unwrap().is_empty());
+    for bad in [
+      "a..b", "a.", ".a", "\"a", "\"a\"b", "a\"b\"", "a.[0]", "a[", "a[x]",
+      "a[]", "a[0]b",
+    ] {
+      assert!(
+        matches!(AttrPath::parse(bad), Err(Error::InvalidAttrPath { .. })),
+        "{bad}"
+      );
+    }
+  }
+
+  #[test]
+  fn test_suggestions() {
+    let names: Vec<String> =
+      ["requests", "requests-oauthlib", "request", "rich"]
+        .map(String::from)
+        .into();
+    assert_eq!(suggestions("reqests", &names), ["requests", "request"]);
+    assert!(suggestions("zzz", &names).is_empty());
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+  }
+
+  #[test]
+  #[serial]
+  fn test_get_attr_path() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ pkgs.python3Packages = { requests = 1; request = 2; }; \"b.c\".d = \
+         3; n = 4; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+
+    let found = value
+      .get_attr_path("pkgs.python3Packages.requests")
+      .expect("Failed to select");
+    assert_eq!(found.as_int().unwrap(), 1);
+    let found = value.get_attr_path("\"b.c\".d").expect("Failed to select");
+    assert_eq!(found.as_int().unwrap(), 3);
+
+    let err = value
+      .get_attr_path("pkgs.python3Packages.reqests.x")
+      .expect_err("attribute should be missing");
+    assert_eq!(
+      err.to_string(),
+      "attribute 'reqests' missing at 'pkgs.python3Packages'; did you mean \
+       one of requests or request?"
+    );
+
+    let err = value.get_attr_path("n.x").expect_err("n is not attrs");
+    assert_eq!(err.to_string(), "n: expected attrs, got int");
+
+    let list = state
+      .eval_from_string("{ l = [ { a = 1; } [ 2 ] ]; }", "<eval>")
+      .expect("Failed to evaluate");
+    let found = list.get_attr_path("l[1][0]").expect("Failed to select");
+    assert_eq!(found.as_int().unwrap(), 2);
+    let found = list.get_attr_path("l[0].a").expect("Failed to select");
+    assert_eq!(found.as_int().unwrap(), 1);
+    assert!(matches!(
+      list.get_attr_path("l[2]"),
+      Err(Error::IndexOutOfBounds {
+        index:  2,
+        length: 2,
+      })
+    ));
+    let err = list
+      .get_attr_path("l[0][0]")
+      .expect_err("l[0] is not a list");
+    assert_eq!(err.to_string(), "l[0]: expected list, got attrs");
+  }
+}
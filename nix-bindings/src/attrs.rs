@@ -1,8 +1,21 @@
-use std::{ffi::CString, ptr::NonNull};
-
-use crate::{Error, Result, Value};
-
-impl Value<'_> {
+use std::{
+  borrow::{Borrow, Cow},
+  collections::HashSet,
+  ffi::CString,
+  ptr::NonNull,
+};
+
+#[cfg(feature = "shim")] use crate::SourcePos;
+use crate::{
+  Error,
+  EvalState,
+  Result,
+  Value,
+  error::{check_err, check_ptr},
+  sys,
+};
+
+impl<'a> Value<'a> {
   /// Get an attribute by name.
   ///
   /// Returns the value associated with the given attribute name.
@@ -161,7 +174,7 @@ impl Value<'_> {
   /// # Errors
   ///
   /// Returns an error if the value is not an attribute set.
-  pub fn attrs(&self) -> Result<AttrIterator<'_>> {
+  pub fn attrs(&self) -> Result<AttrIterator<'a>> {
     if self.value_type() != crate::ValueType::Attrs {
       return Err(Error::InvalidType {
         expected: "attrs",
@@ -178,7 +191,7 @@ impl Value<'_> {
     };
 
     Ok(AttrIterator {
-      value: self,
+      value: self.clone(),
       index: 0,
       count: count as usize,
     })
@@ -191,7 +204,7 @@ impl Value<'_> {
 /// in a Nix attribute set, yielding both the key and value for
 /// each attribute.
 pub struct AttrIterator<'a> {
-  value: &'a Value<'a>,
+  value: Value<'a>,
   index: usize,
   count: usize,
 }
@@ -273,6 +286,239 @@ impl<'a> Iterator for AttrIterator<'a> {
 
 impl ExactSizeIterator for AttrIterator<'_> {}
 
+#[cfg(feature = "shim")]
+impl<'a> AttrIterator<'a> {
+  /// Yield where each attribute was defined along with its name and value.
+  ///
+  /// See [`Value::attr_pos`].
+  pub fn with_positions(self) -> AttrPosIterator<'a> {
+    AttrPosIterator(self)
+  }
+}
+
+/// Iterator over attribute names, definition positions and values.
+///
+/// Created by [`AttrIterator::with_positions`].
+#[cfg(feature = "shim")]
+pub struct AttrPosIterator<'a>(AttrIterator<'a>);
+
+#[cfg(feature = "shim")]
+impl<'a> Iterator for AttrPosIterator<'a> {
+  type Item = Result<(String, Option<SourcePos>, Value<'a>)>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let entry = self.0.next()?;
+    Some(entry.and_then(|(name, value)| {
+      let pos = self.0.value.attr_pos(&name)?;
+      Ok((name, pos, value))
+    }))
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    self.0.size_hint()
+  }
+}
+
+#[cfg(feature = "shim")]
+impl ExactSizeIterator for AttrPosIterator<'_> {}
+
+/// Incremental builder for an attribute set.
+///
+/// Created with [`EvalState::attrs_builder`]. Each inserted value is held by
+/// the set under construction, so the caller's handle can be dropped right
+/// away. The capacity is fixed up front; [`finish`](Self::finish) may be
+/// called with fewer attributes than that.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use nix_bindings::{Context, EvalStateBuilder, Store};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let ctx = Arc::new(Context::new()?);
+/// # let store = Arc::new(Store::open(&ctx, None)?);
+/// # let state = EvalStateBuilder::new(&store)?.build()?;
+/// let mut builder = state.attrs_builder(1000)?;
+/// for i in 0..1000 {
+///   builder.insert(&format!("pkg{i}"), &state.make_int(i)?)?;
+/// }
+/// let attrs = builder.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AttrsBuilder<'s> {
+  state:    &'s EvalState,
+  inner:    NonNull<sys::BindingsBuilder>,
+  capacity: usize,
+  names:    HashSet<String>,
+}
+
+impl<'s> AttrsBuilder<'s> {
+  pub(crate) fn with_capacity(
+    state: &'s EvalState,
+    capacity: usize,
+  ) -> Result<Self> {
+    // SAFETY: context and state are valid
+    let builder = unsafe {
+      sys::nix_make_bindings_builder(
+        state.context.as_ptr(),
+        state.as_ptr(),
+        capacity,
+      )
+    };
+    Ok(AttrsBuilder {
+      state,
+      inner: check_ptr(unsafe { state.context.as_ptr() }, builder)?,
+      capacity,
+      names: HashSet::with_capacity(capacity),
+    })
+  }
+
+  /// Add the attribute `name`.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::IndexOutOfBounds`] if the builder is full, an error if
+  /// `name` was already inserted or contains a NUL byte, or if insertion
+  /// fails.
+  pub fn insert(&mut self, name: &str, value: &Value<'_>) -> Result<()> {
+    if self.names.len() >= self.capacity {
+      return Err(Error::IndexOutOfBounds {
+        index:  self.names.len(),
+        length: self.capacity,
+      });
+    }
+    if self.names.contains(name) {
+      return Err(Error::Unknown(format!("attribute '{name}' inserted twice")));
+    }
+    let name_c = CString::new(name)?;
+    // SAFETY: context, builder, name and value are valid
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        sys::nix_bindings_builder_insert(
+          ctx,
+          self.inner.as_ptr(),
+          name_c.as_ptr(),
+          value.inner.as_ptr(),
+        ),
+      )?;
+    }
+    self.names.insert(name.to_owned());
+    Ok(())
+  }
+
+  /// Insert every pair from `iter`.
+  ///
+  /// # Errors
+  ///
+  /// See [`insert`](Self::insert); pairs before the failing one stay
+  /// inserted.
+  pub fn extend<'v, K, V>(
+    &mut self,
+    iter: impl IntoIterator<Item = (K, V)>,
+  ) -> Result<()>
+  where
+    K: AsRef<str>,
+    V: Borrow<Value<'v>>,
+  {
+    for (name, value) in iter {
+      self.insert(name.as_ref(), value.borrow())?;
+    }
+    Ok(())
+  }
+
+  /// Number of attributes inserted so far.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.names.len()
+  }
+
+  /// Whether nothing has been inserted yet.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.names.is_empty()
+  }
+
+  /// Maximum number of attributes this builder accepts.
+  #[must_use]
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Build the attribute set.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if value allocation or construction fails.
+  pub fn finish(self) -> Result<Value<'s>> {
+    let result = self.state.alloc_value()?;
+    // SAFETY: context, builder, and result value are valid
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        sys::nix_make_attrs(ctx, result.inner.as_ptr(), self.inner.as_ptr()),
+      )?;
+    }
+    Ok(result)
+  }
+}
+
+impl Drop for AttrsBuilder<'_> {
+  fn drop(&mut self) {
+    // SAFETY: we own the builder
+    unsafe { sys::nix_bindings_builder_free(self.inner.as_ptr()) };
+  }
+}
+
+/// Keywords that cannot appear unquoted as an attribute name.
+pub(crate) const KEYWORDS: &[&str] = &[
+  "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Whether `name` can be written as a bare Nix identifier.
+pub(crate) fn is_identifier(name: &str) -> bool {
+  let mut chars = name.chars();
+  let starts_ok =
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_');
+  starts_ok
+    && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+    && !KEYWORDS.contains(&name)
+}
+
+/// Render an attribute name the way it would be written in Nix source:
+/// bare when it is a plain identifier, double-quoted and escaped otherwise.
+pub(crate) fn format_attr_name(name: &str) -> Cow<'_, str> {
+  if is_identifier(name) {
+    Cow::Borrowed(name)
+  } else {
+    Cow::Owned(quote_string(name))
+  }
+}
+
+/// Write `s` as a double-quoted Nix string literal, escaping `${` so that
+/// it does not start an interpolation.
+pub(crate) fn quote_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  let mut chars = s.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
 #[cfg(test)]
 mod tests {
   use std::sync::Arc;
@@ -370,4 +616,63 @@ mod tests {
     let has = attrs.has_attr("foo").expect("Failed to check attr");
     assert!(!has);
   }
+
+  #[test]
+  #[serial]
+  fn test_attrs_builder() {
+    let state = setup().build().expect("Failed to build state");
+    let mut builder = state.attrs_builder(3).expect("Failed to create builder");
+    for i in 0..2 {
+      let value = state.make_int(i).expect("Failed to make int");
+      builder
+        .insert(&format!("n{i}"), &value)
+        .expect("Failed to insert");
+    }
+    let one = state.make_int(1).expect("Failed to make int");
+    assert!(builder.insert("n0", &one).is_err());
+    assert_eq!(builder.len(), 2);
+    let attrs = builder.finish().expect("Failed to finish");
+    assert_eq!(attrs.attr_keys().expect("Failed to get keys"), ["n0", "n1"]);
+    let n1 = attrs.get_attr("n1").expect("Failed to get n1");
+    assert_eq!(n1.as_int().expect("Failed to get int"), 1);
+
+    let attrs = state
+      .make_attrs_from_iter(
+        (0..1000).map(|i| Ok((format!("p{i}"), state.make_int(i)?))),
+      )
+      .expect("Failed to build attrs");
+    let p999 = attrs.get_attr("p999").expect("Failed to get p999");
+    assert_eq!(p999.as_int().expect("Failed to get int"), 999);
+
+    let mut full = state.attrs_builder(0).expect("Failed to create builder");
+    assert!(matches!(
+      full.insert("x", &one),
+      Err(crate::Error::IndexOutOfBounds {
+        index:  0,
+        length: 0,
+      })
+    ));
+  }
+
+  #[test]
+  #[serial]
+  #[cfg(feature = "shim")]
+  fn test_attr_iterator_with_positions() {
+    let state = setup().build().expect("Failed to build state");
+    let attrs = state
+      .eval_from_string("{\n  b = 2;\n  a = 1;\n}", "<eval>")
+      .expect("Failed to evaluate attrs");
+
+    let mut lines = attrs
+      .attrs()
+      .expect("Failed to iterate")
+      .with_positions()
+      .map(|entry| {
+        let (name, pos, _) = entry.expect("Failed to read attribute");
+        (name, pos.expect("attribute should have a position").line)
+      })
+      .collect::<Vec<_>>();
+    lines.sort();
+    assert_eq!(lines, [("a".to_string(), 3), ("b".to_string(), 2)]);
+  }
 }
@@ -0,0 +1,120 @@
+//! Equality and ordering between values, with the evaluator's semantics.
+
+#![cfg(feature = "shim")]
+
+use std::cmp::Ordering;
+
+use crate::{Error, Result, Value, error::check_err, sys};
+
+impl Value<'_> {
+  /// Compare with `other` using Nix's `==`.
+  ///
+  /// Equality is deep: attribute sets and lists are compared element by
+  /// element, forcing as much as needed. Derivations compare by
+  /// `outPath`, ints and floats compare numerically, string context is
+  /// ignored, and functions are never equal.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the values belong to different evaluators or
+  /// forcing fails.
+  pub fn nix_eq(&self, other: &Value<'_>) -> Result<bool> {
+    self.compare_with(other, sys::nix_value_equal)
+  }
+
+  /// Order against `other` using Nix's `<`.
+  ///
+  /// Numbers, strings, paths and lists (lexicographically) can be ordered.
+  /// Values where neither is less than the other, such as equal values or
+  /// a NaN, are reported as [`Ordering::Equal`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the values belong to different evaluators, cannot
+  /// be compared (e.g. an attribute set, or a string and an int), or
+  /// forcing fails.
+  pub fn nix_cmp(&self, other: &Value<'_>) -> Result<Ordering> {
+    if self.compare_with(other, sys::nix_value_less_than)? {
+      Ok(Ordering::Less)
+    } else if other.compare_with(self, sys::nix_value_less_than)? {
+      Ok(Ordering::Greater)
+    } else {
+      Ok(Ordering::Equal)
+    }
+  }
+
+  fn compare_with(
+    &self,
+    other: &Value<'_>,
+    compare: unsafe extern "C" fn(
+      *mut sys::nix_c_context,
+      *mut sys::EvalState,
+      *mut sys::nix_value,
+      *mut sys::nix_value,
+      *mut bool,
+    ) -> sys::nix_err,
+  ) -> Result<bool> {
+    if !std::ptr::eq(self.state, other.state) {
+      return Err(Error::Unknown(
+        "cannot compare values from different evaluators".into(),
+      ));
+    }
+    let mut result = false;
+    // SAFETY: context, state and both values are valid and belong to the
+    // same evaluator.
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        compare(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          other.inner.as_ptr(),
+          &mut result,
+        ),
+      )?;
+    }
+    Ok(result)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_nix_eq() {
+    let state = setup();
+    let eval = |expr| state.eval_from_string(expr, "<eval>").unwrap();
+    let eq = |a, b| eval(a).nix_eq(&eval(b)).expect("Failed to compare");
+
+    assert!(eq("{ a = [ 1 2.0 ]; }", "{ a = [ 1.0 2 ]; }"));
+    assert!(!eq("{ a = 1; }", "{ a = 1; b = 2; }"));
+    assert!(!eq("x: x", "x: x"));
+    assert!(eq(
+      "{ type = \"derivation\"; outPath = \"/a\"; x = 1; }",
+      "{ type = \"derivation\"; outPath = \"/a\"; x = 2; }"
+    ));
+  }
+
+  #[test]
+  #[serial]
+  fn test_nix_cmp() {
+    let state = setup();
+    let eval = |expr| state.eval_from_string(expr, "<eval>").unwrap();
+    let cmp = |a, b| eval(a).nix_cmp(&eval(b));
+
+    assert_eq!(cmp("1", "2.5").unwrap(), Ordering::Less);
+    assert_eq!(cmp("\"b\"", "\"a\"").unwrap(), Ordering::Greater);
+    assert_eq!(cmp("[ 1 2 ]", "[ 1 2 ]").unwrap(), Ordering::Equal);
+    assert_eq!(cmp("[ 1 2 ]", "[ 1 3 ]").unwrap(), Ordering::Less);
+    assert_eq!(cmp("/a", "/b").unwrap(), Ordering::Less);
+    assert!(cmp("{ }", "{ }").is_err());
+    assert!(cmp("1", "\"1\"").is_err());
+  }
+}
@@ -0,0 +1,716 @@
+//! Native conversion traits between Nix values and Rust types.
+//!
+//! [`FromNix`] reads a Rust value out of anything implementing
+//! [`NixValueOps`], so the same impl works on a [`Value`] and on the
+//! callback-scoped wrappers inside a primop. [`IntoNix`] builds a Nix value
+//! through a [`NixAlloc`]: an [`EvalState`] outside primops, or the
+//! [`PrimOpRet`](crate::primop::PrimOpRet) slot inside one.
+//!
+//! With the `derive` feature, `#[derive(FromNix, IntoNix)]` generates both
+//! for structs:
+//!
+//! ```ignore
+//! use nix_bindings::{FromNix, IntoNix, NixValueOps, Value};
+//!
+//! #[derive(FromNix, IntoNix)]
+//! struct Service<'a> {
+//!   port:    u16,
+//!   #[nix(default)]
+//!   enable:  bool,
+//!   #[nix(rename = "extra-args")]
+//!   args:    Vec<String>,
+//!   /// Kept as an unforced value.
+//!   #[nix(lazy)]
+//!   package: Value<'a>,
+//! }
+//!
+//! let service: Service = value.extract()?;
+//! let back = service.to_nix(&state)?;
+//! ```
+//!
+//! Conversion errors raised below the root carry the attribute path to the
+//! offending value, e.g. `services.foo.port: expected int, got string`.
+
+use std::{
+  collections::{BTreeMap, HashMap},
+  hash::BuildHasher,
+  path::{Path, PathBuf},
+};
+
+use crate::{
+  Error,
+  EvalState,
+  NixValueOps,
+  Result,
+  Value,
+  ValueType,
+  check_err,
+  sys,
+  value_ops::sealed,
+};
+
+/// Conversion from a Nix value.
+///
+/// The value type `V` is a type parameter rather than a method generic so
+/// that impls can put bounds on the values they fetch out of it
+/// (`V::Child`); `#[nix(lazy)]` fields rely on this. Most impls are generic
+/// over every `V: NixValueOps`.
+pub trait FromNix<V: NixValueOps>: Sized {
+  /// Convert `value`, forcing it (and any nested values) as needed.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the value does not match the
+  /// shape of `Self`.
+  fn from_nix(value: &V) -> Result<Self>;
+
+  /// Value to use when an attribute of this type is absent from an
+  /// attribute set.
+  ///
+  /// The default of `None` makes a missing attribute an error; [`Option`]
+  /// overrides this to read a missing attribute as `None`.
+  #[must_use]
+  fn default_if_missing() -> Option<Self> {
+    None
+  }
+}
+
+/// Conversion into a Nix value.
+pub trait IntoNix {
+  /// Build a Nix value from `self` using `alloc`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocating or initialising any value fails.
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>>;
+}
+
+/// Allocator for new Nix values.
+///
+/// Implemented by [`EvalState`] and, inside a primop callback, by
+/// [`PrimOpRet`](crate::primop::PrimOpRet). This trait is sealed.
+pub trait NixAlloc: sealed::Sealed {
+  /// Owned value type produced by this allocator.
+  type Value<'s>: NixValueOps
+  where
+    Self: 's;
+
+  /// Allocate an integer.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails.
+  fn make_int(&self, i: i64) -> Result<Self::Value<'_>>;
+
+  /// Allocate a float.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails.
+  fn make_float(&self, f: f64) -> Result<Self::Value<'_>>;
+
+  /// Allocate a boolean.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails.
+  fn make_bool(&self, b: bool) -> Result<Self::Value<'_>>;
+
+  /// Allocate `null`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails.
+  fn make_null(&self) -> Result<Self::Value<'_>>;
+
+  /// Allocate a string.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails, or `s`
+  /// contains a NUL byte.
+  fn make_string(&self, s: &str) -> Result<Self::Value<'_>>;
+
+  /// Allocate a path.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or initialisation fails, or `path` is
+  /// not valid UTF-8.
+  fn make_path(&self, path: &Path) -> Result<Self::Value<'_>>;
+
+  /// Allocate a list holding `items`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or construction fails.
+  fn make_list<'s>(
+    &'s self,
+    items: &[&Self::Value<'s>],
+  ) -> Result<Self::Value<'s>>;
+
+  /// Allocate an attribute set holding `pairs`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or construction fails.
+  fn make_attrs<'s>(
+    &'s self,
+    pairs: &[(&str, &Self::Value<'s>)],
+  ) -> Result<Self::Value<'s>>;
+
+  /// Allocate a shallow copy of `value`. Thunks are copied unforced.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or the copy fails.
+  fn copy_value<V: NixValueOps>(&self, value: &V) -> Result<Self::Value<'_>>;
+}
+
+impl sealed::Sealed for EvalState {}
+
+impl NixAlloc for EvalState {
+  type Value<'s> = Value<'s>;
+
+  fn make_int(&self, i: i64) -> Result<Value<'_>> {
+    EvalState::make_int(self, i)
+  }
+
+  fn make_float(&self, f: f64) -> Result<Value<'_>> {
+    EvalState::make_float(self, f)
+  }
+
+  fn make_bool(&self, b: bool) -> Result<Value<'_>> {
+    EvalState::make_bool(self, b)
+  }
+
+  fn make_null(&self) -> Result<Value<'_>> {
+    EvalState::make_null(self)
+  }
+
+  fn make_string(&self, s: &str) -> Result<Value<'_>> {
+    EvalState::make_string(self, s)
+  }
+
+  fn make_path(&self, path: &Path) -> Result<Value<'_>> {
+    EvalState::make_path(self, path)
+  }
+
+  fn make_list<'s>(&'s self, items: &[&Value<'s>]) -> Result<Value<'s>> {
+    EvalState::make_list(self, items)
+  }
+
+  fn make_attrs<'s>(
+    &'s self,
+    pairs: &[(&str, &Value<'s>)],
+  ) -> Result<Value<'s>> {
+    EvalState::make_attrs(self, pairs)
+  }
+
+  fn copy_value<V: NixValueOps>(&self, value: &V) -> Result<Value<'_>> {
+    let result = self.alloc_value()?;
+    // SAFETY: context and both value pointers are valid.
+    unsafe {
+      check_err(
+        self.context.as_ptr(),
+        sys::nix_copy_value(
+          self.context.as_ptr(),
+          result.inner.as_ptr(),
+          value.raw_inner(),
+        ),
+      )?;
+    }
+    Ok(result)
+  }
+}
+
+fn conversion(message: String) -> Error {
+  Error::Conversion {
+    path: String::new(),
+    message,
+  }
+}
+
+macro_rules! impl_int {
+  ($($ty:ty),*) => {$(
+    impl<V: NixValueOps> FromNix<V> for $ty {
+      fn from_nix(value: &V) -> Result<Self> {
+        let i = value.as_int()?;
+        <$ty>::try_from(i).map_err(|_| {
+          conversion(format!("{i} is out of range for {}", stringify!($ty)))
+        })
+      }
+    }
+
+    impl IntoNix for $ty {
+      fn to_nix<'s, A: NixAlloc>(
+        &self,
+        alloc: &'s A,
+      ) -> Result<A::Value<'s>> {
+        let i = i64::try_from(*self).map_err(|_| {
+          conversion(format!("{self} is out of range for a Nix integer"))
+        })?;
+        alloc.make_int(i)
+      }
+    }
+  )*};
+}
+
+impl_int!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl<V: NixValueOps> FromNix<V> for f64 {
+  fn from_nix(value: &V) -> Result<Self> {
+    value.force()?;
+    // Nix freely mixes ints and floats in arithmetic; accept both.
+    if value.value_type() == ValueType::Int {
+      return Ok(value.as_int()? as f64);
+    }
+    value.as_float()
+  }
+}
+
+impl IntoNix for f64 {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_float(*self)
+  }
+}
+
+impl<V: NixValueOps> FromNix<V> for f32 {
+  fn from_nix(value: &V) -> Result<Self> {
+    f64::from_nix(value).map(|f| f as f32)
+  }
+}
+
+impl IntoNix for f32 {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_float(f64::from(*self))
+  }
+}
+
+impl<V: NixValueOps> FromNix<V> for bool {
+  fn from_nix(value: &V) -> Result<Self> {
+    value.as_bool()
+  }
+}
+
+impl IntoNix for bool {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_bool(*self)
+  }
+}
+
+impl<V: NixValueOps> FromNix<V> for () {
+  fn from_nix(value: &V) -> Result<Self> {
+    value.force()?;
+    if value.value_type() != ValueType::Null {
+      return Err(Error::InvalidType {
+        expected: "null",
+        actual:   value.value_type().to_string(),
+      });
+    }
+    Ok(())
+  }
+}
+
+impl IntoNix for () {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_null()
+  }
+}
+
+impl<V: NixValueOps> FromNix<V> for String {
+  fn from_nix(value: &V) -> Result<Self> {
+    value.as_string()
+  }
+}
+
+impl IntoNix for String {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_string(self)
+  }
+}
+
+impl IntoNix for str {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_string(self)
+  }
+}
+
+impl<V: NixValueOps> FromNix<V> for PathBuf {
+  /// Accepts both path values and strings, since paths are routinely
+  /// passed around as strings (e.g. store paths).
+  fn from_nix(value: &V) -> Result<Self> {
+    value.force()?;
+    match value.value_type() {
+      ValueType::Path => value.as_path().map(PathBuf::from),
+      ValueType::String => value.as_string().map(PathBuf::from),
+      other => {
+        Err(Error::InvalidType {
+          expected: "path",
+          actual:   other.to_string(),
+        })
+      },
+    }
+  }
+}
+
+impl IntoNix for PathBuf {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_path(self)
+  }
+}
+
+impl IntoNix for Path {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.make_path(self)
+  }
+}
+
+impl<V: NixValueOps, T: FromNix<V>> FromNix<V> for Option<T> {
+  /// `null` converts to `None`; anything else is converted as `T`.
+  fn from_nix(value: &V) -> Result<Self> {
+    value.force()?;
+    if value.value_type() == ValueType::Null {
+      return Ok(None);
+    }
+    T::from_nix(value).map(Some)
+  }
+
+  fn default_if_missing() -> Option<Self> {
+    Some(None)
+  }
+}
+
+impl<T: IntoNix> IntoNix for Option<T> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    match self {
+      Some(value) => value.to_nix(alloc),
+      None => alloc.make_null(),
+    }
+  }
+}
+
+impl<T: IntoNix + ?Sized> IntoNix for &T {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    (**self).to_nix(alloc)
+  }
+}
+
+impl<T: IntoNix + ?Sized> IntoNix for Box<T> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    (**self).to_nix(alloc)
+  }
+}
+
+impl<V: NixValueOps, T: FromNix<V::Child>> FromNix<V> for Vec<T> {
+  fn from_nix(value: &V) -> Result<Self> {
+    value
+      .list_items()?
+      .iter()
+      .enumerate()
+      .map(|(i, item)| T::from_nix(item).map_err(|e| e.in_index(i)))
+      .collect()
+  }
+}
+
+impl<T: IntoNix> IntoNix for [T] {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    let items = self
+      .iter()
+      .enumerate()
+      .map(|(i, item)| item.to_nix(alloc).map_err(|e| e.in_index(i)))
+      .collect::<Result<Vec<_>>>()?;
+    let refs: Vec<_> = items.iter().collect();
+    alloc.make_list(&refs)
+  }
+}
+
+impl<T: IntoNix> IntoNix for Vec<T> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    self.as_slice().to_nix(alloc)
+  }
+}
+
+/// Convert every attribute of `value` with `T`, prefixing errors with the
+/// attribute name.
+fn attrs_from_nix<V, T>(
+  value: &V,
+) -> Result<impl Iterator<Item = Result<(String, T)>>>
+where
+  V: NixValueOps,
+  T: FromNix<V::Child>,
+{
+  let names = value.attr_names()?;
+  Ok(names.into_iter().map(move |name| {
+    let child = value
+      .get_attr_lazy(&name)?
+      .ok_or_else(|| Error::KeyNotFound(name.clone()))?;
+    let converted = T::from_nix(&child).map_err(|e| e.in_attr(&name))?;
+    Ok((name, converted))
+  }))
+}
+
+/// Build an attribute set from `(name, value)` pairs.
+fn attrs_to_nix<'s, 'k, A, T>(
+  alloc: &'s A,
+  pairs: impl Iterator<Item = (&'k String, &'k T)>,
+) -> Result<A::Value<'s>>
+where
+  A: NixAlloc,
+  T: IntoNix + 'k,
+{
+  let values = pairs
+    .map(|(name, value)| {
+      let value = value.to_nix(alloc).map_err(|e| e.in_attr(name))?;
+      Ok((name.as_str(), value))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  let refs: Vec<_> =
+    values.iter().map(|(name, value)| (*name, value)).collect();
+  alloc.make_attrs(&refs)
+}
+
+impl<V, T, S> FromNix<V> for HashMap<String, T, S>
+where
+  V: NixValueOps,
+  T: FromNix<V::Child>,
+  S: BuildHasher + Default,
+{
+  fn from_nix(value: &V) -> Result<Self> {
+    attrs_from_nix(value)?.collect()
+  }
+}
+
+impl<T: IntoNix, S> IntoNix for HashMap<String, T, S> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    attrs_to_nix(alloc, self.iter())
+  }
+}
+
+impl<V: NixValueOps, T: FromNix<V::Child>> FromNix<V> for BTreeMap<String, T> {
+  fn from_nix(value: &V) -> Result<Self> {
+    attrs_from_nix(value)?.collect()
+  }
+}
+
+impl<T: IntoNix> IntoNix for BTreeMap<String, T> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    attrs_to_nix(alloc, self.iter())
+  }
+}
+
+macro_rules! impl_tuple {
+  ($len:literal => $($name:ident $idx:tt),+) => {
+    /// Tuples convert from lists of exactly the tuple's length.
+    impl<V: NixValueOps, $($name: FromNix<V::Child>),+> FromNix<V>
+      for ($($name,)+)
+    {
+      fn from_nix(value: &V) -> Result<Self> {
+        let items = value.list_items()?;
+        if items.len() != $len {
+          return Err(conversion(format!(
+            "expected a list of length {}, got length {}",
+            $len,
+            items.len()
+          )));
+        }
+        Ok(($(
+          $name::from_nix(&items[$idx]).map_err(|e| e.in_index($idx))?,
+        )+))
+      }
+    }
+
+    impl<$($name: IntoNix),+> IntoNix for ($($name,)+) {
+      fn to_nix<'s, A: NixAlloc>(
+        &self,
+        alloc: &'s A,
+      ) -> Result<A::Value<'s>> {
+        let items = [$(
+          self.$idx.to_nix(alloc).map_err(|e| e.in_index($idx))?,
+        )+];
+        let refs: Vec<_> = items.iter().collect();
+        alloc.make_list(&refs)
+      }
+    }
+  };
+}
+
+impl_tuple!(1 => T0 0);
+impl_tuple!(2 => T0 0, T1 1);
+impl_tuple!(3 => T0 0, T1 1, T2 2);
+impl_tuple!(4 => T0 0, T1 1, T2 2, T3 3);
+impl_tuple!(5 => T0 0, T1 1, T2 2, T3 3, T4 4);
+impl_tuple!(6 => T0 0, T1 1, T2 2, T3 3, T4 4, T5 5);
+
+impl<'a> FromNix<Value<'a>> for Value<'a> {
+  /// Forces the value and returns a new handle to it. Use `#[nix(lazy)]`
+  /// on a derived field to keep it unforced instead.
+  fn from_nix(value: &Value<'a>) -> Result<Self> {
+    value.force()?;
+    Ok(value.clone())
+  }
+}
+
+impl IntoNix for Value<'_> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.copy_value(self)
+  }
+}
+
+#[cfg(feature = "primop")]
+impl<'a> FromNix<crate::primop::PrimOpValue<'a>>
+  for crate::primop::PrimOpValue<'a>
+{
+  /// Forces the value and returns a new handle to it. Use `#[nix(lazy)]`
+  /// on a derived field to keep it unforced instead.
+  fn from_nix(value: &crate::primop::PrimOpValue<'a>) -> Result<Self> {
+    use crate::value_ops::NixValueRaw;
+
+    value.force()?;
+    // SAFETY: ctx and inner are valid; the new reference is owned by the
+    // returned handle and released on its drop.
+    unsafe {
+      check_err(
+        value.raw_ctx(),
+        sys::nix_value_incref(value.raw_ctx(), value.raw_inner()),
+      )?;
+      Ok(value.wrap_child(std::ptr::NonNull::new_unchecked(value.raw_inner())))
+    }
+  }
+}
+
+#[cfg(feature = "primop")]
+impl IntoNix for crate::primop::PrimOpValue<'_> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.copy_value(self)
+  }
+}
+
+#[cfg(feature = "primop")]
+impl IntoNix for crate::primop::PrimOpArg<'_> {
+  fn to_nix<'s, A: NixAlloc>(&self, alloc: &'s A) -> Result<A::Value<'s>> {
+    alloc.copy_value(self)
+  }
+}
+
+/// Support code for `#[derive(FromNix, IntoNix)]`. Not public API.
+#[doc(hidden)]
+pub mod __private {
+  use super::{FromNix, NixValueOps};
+  use crate::{Error, Result};
+
+  fn missing(name: &str) -> Error {
+    super::conversion("missing attribute".to_string()).in_attr(name)
+  }
+
+  /// Convert the attribute `name` of `value`.
+  pub fn field<V, T>(value: &V, name: &str) -> Result<T>
+  where
+    V: NixValueOps,
+    T: FromNix<V::Child>,
+  {
+    match value.get_attr_lazy(name)? {
+      Some(child) => T::from_nix(&child).map_err(|e| e.in_attr(name)),
+      None => T::default_if_missing().ok_or_else(|| missing(name)),
+    }
+  }
+
+  /// Convert the attribute `name` of `value`, or `T::default()` if absent.
+  pub fn field_or_default<V, T>(value: &V, name: &str) -> Result<T>
+  where
+    V: NixValueOps,
+    T: FromNix<V::Child> + Default,
+  {
+    match value.get_attr_lazy(name)? {
+      Some(child) => T::from_nix(&child).map_err(|e| e.in_attr(name)),
+      None => Ok(T::default()),
+    }
+  }
+
+  /// Take the attribute `name` of `value` without forcing it.
+  pub fn lazy_field<V, T>(value: &V, name: &str) -> Result<T>
+  where
+    V: NixValueOps,
+    V::Child: Into<T>,
+  {
+    value
+      .get_attr_lazy(name)?
+      .map(Into::into)
+      .ok_or_else(|| missing(name))
+  }
+
+  /// Take the attribute `name` of `value` without forcing it, or
+  /// `T::default()` if absent.
+  pub fn lazy_field_or_default<V, T>(value: &V, name: &str) -> Result<T>
+  where
+    V: NixValueOps,
+    V::Child: Into<T>,
+    T: Default,
+  {
+    Ok(
+      value
+        .get_attr_lazy(name)?
+        .map(Into::into)
+        .unwrap_or_default(),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_std_types_round_trip() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ a = [ 1 2 3 ]; b = { x = \"y\"; }; c = null; d = [ 1 \"two\" ]; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+
+    let a: Vec<i64> = value.get_attr("a").unwrap().extract().unwrap();
+    assert_eq!(a, [1, 2, 3]);
+    let b: HashMap<String, String> =
+      value.get_attr("b").unwrap().extract().unwrap();
+    assert_eq!(b["x"], "y");
+    let c: Option<u8> = value.get_attr("c").unwrap().extract().unwrap();
+    assert_eq!(c, None);
+    let d: (u8, String) = value.get_attr("d").unwrap().extract().unwrap();
+    assert_eq!(d, (1, "two".to_string()));
+
+    let map = BTreeMap::from([("k".to_string(), vec![(1u8, true)])]);
+    let built = map.to_nix(&state).expect("Failed to convert");
+    assert_eq!(
+      built
+        .extract::<BTreeMap<String, Vec<(u8, bool)>>>()
+        .unwrap(),
+      map
+    );
+  }
+
+  #[test]
+  #[serial]
+  fn test_error_path() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{ xs = [ 1 \"two\" ]; }", "<eval>")
+      .expect("Failed to evaluate");
+    let err = value
+      .extract::<HashMap<String, Vec<i64>>>()
+      .expect_err("string element should fail");
+    assert_eq!(err.to_string(), "xs[1]: expected int, got string");
+
+    let err = state
+      .make_int(300)
+      .unwrap()
+      .extract::<u8>()
+      .expect_err("300 does not fit in u8");
+    assert_eq!(err.to_string(), "300 is out of range for u8");
+  }
+}
@@ -0,0 +1,356 @@
+//! Owned snapshots of evaluated values.
+//!
+//! [`NixData`] is plain Rust data: it does not borrow the [`EvalState`], so
+//! it can outlive the evaluator, cross threads, be compared, and (with the
+//! `serde` feature) be serialized. [`Value::to_data`] takes a snapshot and
+//! [`EvalState::from_data`] turns one back into a value.
+//!
+//! String context is carried along as a [`StringContext`] without being
+//! realised, so snapshotting never builds anything.
+
+#![cfg(feature = "shim")]
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use crate::{Error, EvalState, Result, StringContext, Value, ValueType};
+
+/// An owned, fully evaluated Nix value.
+///
+/// Produced by [`Value::to_data`]; convert back with
+/// [`EvalState::from_data`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NixData {
+  /// Integer.
+  Int(i64),
+  /// Float.
+  Float(f64),
+  /// Boolean.
+  Bool(bool),
+  /// String with its context.
+  String {
+    /// String contents.
+    value:   String,
+    /// Context elements; empty for plain strings. Serialized in Nix's
+    /// encoding, e.g. `!out!/nix/store/....drv`.
+    context: StringContext,
+  },
+  /// Path.
+  Path(PathBuf),
+  /// Null.
+  Null,
+  /// Attribute set.
+  Attrs(BTreeMap<String, NixData>),
+  /// List.
+  List(Vec<NixData>),
+  /// A value that cannot be captured as data.
+  Opaque(OpaqueKind),
+}
+
+/// What an [`NixData::Opaque`] stands in for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpaqueKind {
+  /// A function.
+  Function,
+  /// An external value.
+  External,
+  /// An attribute set or list beyond the depth limit.
+  Truncated,
+}
+
+impl Value<'_> {
+  /// Take an owned snapshot of this value, forcing it deeply.
+  ///
+  /// Attribute sets and lists nested more than `depth_limit` levels below
+  /// this value are replaced with [`OpaqueKind::Truncated`]; the value
+  /// itself is always expanded. With `None` there is no limit, so large
+  /// package sets may take a long time.
+  ///
+  /// String context is read without realising it.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing any part of the value fails. Within the
+  /// depth limit, an attribute set or list that contains itself (e.g.
+  /// `rec { self = { inherit self; }; }`) or nests more than
+  /// [`MAX_DATA_DEPTH`] deep is a conversion error. Conversion errors carry
+  /// the attribute path to the offending value.
+  pub fn to_data(&self, depth_limit: Option<usize>) -> Result<NixData> {
+    to_data(self, depth_limit, &mut Vec::new())
+  }
+}
+
+/// How deeply attribute sets and lists may nest in a snapshot. Taking one
+/// recurses per level, so deeper values are rejected instead of overflowing
+/// the stack.
+pub const MAX_DATA_DEPTH: usize = 1024;
+
+/// Snapshot `value`, below the attribute sets and lists with identities
+/// `ancestors`.
+fn to_data(
+  value: &Value<'_>,
+  limit: Option<usize>,
+  ancestors: &mut Vec<usize>,
+) -> Result<NixData> {
+  value.force_shared()?;
+  let expand = limit.is_none_or(|limit| ancestors.len() <= limit);
+  Ok(match value.value_type() {
+    ValueType::Int => NixData::Int(value.as_int()?),
+    ValueType::Float => NixData::Float(value.as_float()?),
+    ValueType::Bool => NixData::Bool(value.as_bool()?),
+    ValueType::String => {
+      NixData::String {
+        value:   value.as_string()?,
+        context: value.string_context()?,
+      }
+    },
+    ValueType::Path => NixData::Path(value.as_path()?),
+    ValueType::Null => NixData::Null,
+    ValueType::Attrs | ValueType::List if !expand => {
+      NixData::Opaque(OpaqueKind::Truncated)
+    },
+    ValueType::Attrs | ValueType::List => container(value, limit, ancestors)?,
+    ValueType::Function => NixData::Opaque(OpaqueKind::Function),
+    ValueType::External => NixData::Opaque(OpaqueKind::External),
+    ValueType::Thunk => {
+      return Err(Error::Unknown(
+        "value is still a thunk after forcing".into(),
+      ));
+    },
+  })
+}
+
+/// Snapshot an attribute set or list, unless it contains itself or is too
+/// deep.
+fn container(
+  value: &Value<'_>,
+  limit: Option<usize>,
+  ancestors: &mut Vec<usize>,
+) -> Result<NixData> {
+  let id = value.identity();
+  if id != 0 && ancestors.contains(&id) {
+    return Err(conversion(
+      "cannot snapshot a value that contains itself".into(),
+    ));
+  }
+  if ancestors.len() == MAX_DATA_DEPTH {
+    return Err(conversion(format!(
+      "nesting exceeds the maximum depth of {MAX_DATA_DEPTH}"
+    )));
+  }
+  ancestors.push(id);
+  let result = if value.value_type() == ValueType::Attrs {
+    attrs(value, limit, ancestors)
+  } else {
+    list(value, limit, ancestors)
+  };
+  ancestors.pop();
+  result
+}
+
+fn attrs(
+  value: &Value<'_>,
+  limit: Option<usize>,
+  ancestors: &mut Vec<usize>,
+) -> Result<NixData> {
+  let mut attrs = BTreeMap::new();
+  for entry in value.attrs()? {
+    let (name, child) = entry?;
+    let data =
+      to_data(&child, limit, ancestors).map_err(|e| e.in_attr(&name))?;
+    attrs.insert(name, data);
+  }
+  Ok(NixData::Attrs(attrs))
+}
+
+fn list(
+  value: &Value<'_>,
+  limit: Option<usize>,
+  ancestors: &mut Vec<usize>,
+) -> Result<NixData> {
+  let items = value
+    .list_iter()?
+    .enumerate()
+    .map(|(i, child)| {
+      child
+        .and_then(|child| to_data(&child, limit, ancestors))
+        .map_err(|e| e.in_index(i))
+    })
+    .collect::<Result<_>>()?;
+  Ok(NixData::List(items))
+}
+
+fn conversion(message: String) -> Error {
+  Error::Conversion {
+    path: String::new(),
+    message,
+  }
+}
+
+impl EvalState {
+  /// Build a value from an owned snapshot.
+  ///
+  /// Strings get back their context, so a snapshot of a derivation's
+  /// `outPath` still depends on the derivation.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `data` contains an [`NixData::Opaque`], a string
+  /// context element that is not a valid store path, or if value
+  /// construction fails.
+  pub fn from_data(&self, data: &NixData) -> Result<Value<'_>> {
+    match data {
+      NixData::Int(i) => self.make_int(*i),
+      NixData::Float(f) => self.make_float(*f),
+      NixData::Bool(b) => self.make_bool(*b),
+      NixData::String { value, context } if context.is_empty() => {
+        self.make_string(value)
+      },
+      NixData::String { value, context } => {
+        self.make_string_with_context(value, context)
+      },
+      NixData::Path(path) => self.make_path(path),
+      NixData::Null => self.make_null(),
+      NixData::Attrs(attrs) => {
+        let mut builder = self.attrs_builder(attrs.len())?;
+        for (name, data) in attrs {
+          let value = self.from_data(data).map_err(|e| e.in_attr(name))?;
+          builder.insert(name, &value)?;
+        }
+        builder.finish()
+      },
+      NixData::List(items) => {
+        let mut builder = self.list_builder(items.len())?;
+        for (i, data) in items.iter().enumerate() {
+          builder.push(&self.from_data(data).map_err(|e| e.in_index(i))?)?;
+        }
+        builder.finish()
+      },
+      NixData::Opaque(kind) => {
+        Err(Error::Conversion {
+          path:    String::new(),
+          message: format!("cannot rebuild an opaque value ({kind:?})"),
+        })
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::{ContextElem, test_util::setup};
+
+  #[test]
+  #[serial]
+  fn test_to_data_round_trip() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ a = 1; b = [ 2.5 true null \"s\" ]; c.d = /tmp/x; f = x: x; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+    let data = value.to_data(None).expect("Failed to snapshot");
+
+    let NixData::Attrs(attrs) = &data else {
+      panic!("expected attrs, got {data:?}");
+    };
+    assert_eq!(attrs["a"], NixData::Int(1));
+    assert_eq!(attrs["f"], NixData::Opaque(OpaqueKind::Function));
+    assert_eq!(
+      attrs["b"],
+      NixData::List(vec![
+        NixData::Float(2.5),
+        NixData::Bool(true),
+        NixData::Null,
+        NixData::String {
+          value:   "s".into(),
+          context: StringContext::new(),
+        },
+      ])
+    );
+
+    let mut without_fn = attrs.clone();
+    without_fn.remove("f");
+    let without_fn = NixData::Attrs(without_fn);
+    let rebuilt = state.from_data(&without_fn).expect("Failed to rebuild");
+    assert_eq!(rebuilt.to_data(None).unwrap(), without_fn);
+
+    let err = state.from_data(&data).expect_err("functions are opaque");
+    assert!(err.to_string().starts_with("f: "), "{err}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_data_depth_limit() {
+    let state = setup();
+    let value = state
+      .eval_from_string("rec { self = { inherit self; n = 1; }; }", "<eval>")
+      .expect("Failed to evaluate");
+    let data = value.to_data(Some(1)).expect("Failed to snapshot");
+    let expected = NixData::Attrs(BTreeMap::from([(
+      "self".into(),
+      NixData::Attrs(BTreeMap::from([
+        ("n".into(), NixData::Int(1)),
+        ("self".into(), NixData::Opaque(OpaqueKind::Truncated)),
+      ])),
+    )]));
+    assert_eq!(data, expected);
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_data_unbounded() {
+    let state = setup();
+    let cyclic = state
+      .eval_from_string("rec { self = { inherit self; }; }", "<eval>")
+      .expect("Failed to evaluate");
+    let err = cyclic.to_data(None).expect_err("cycles should fail");
+    assert_eq!(
+      err.to_string(),
+      "self.self: cannot snapshot a value that contains itself"
+    );
+
+    let deep = state
+      .eval_from_string("let f = n: { next = f (n + 1); }; in f 0", "<eval>")
+      .expect("Failed to evaluate");
+    let err = deep
+      .to_data(None)
+      .expect_err("unbounded nesting should fail");
+    assert!(matches!(err, Error::Conversion { .. }), "{err}");
+    assert!(deep.to_data(Some(3)).is_ok());
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_data_keeps_string_context() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "let d = derivation { name = \"d\"; system = \"x\"; builder = \"b\"; \
+         }; in \"${d}/bin\"",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+    let data = value.to_data(None).expect("Failed to snapshot");
+    let NixData::String { value: s, context } = &data else {
+      panic!("expected string, got {data:?}");
+    };
+    assert!(s.ends_with("-d/bin"), "{s}");
+    assert_eq!(context.len(), 1);
+    assert!(
+      matches!(
+        context.iter().next(),
+        Some(ContextElem::Built { output, .. }) if output == "out"
+      ),
+      "{context:?}"
+    );
+
+    let rebuilt = state.from_data(&data).expect("Failed to rebuild");
+    assert_eq!(rebuilt.to_data(None).unwrap(), data);
+  }
+}
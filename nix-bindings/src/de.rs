@@ -0,0 +1,609 @@
+//! Serde [`Deserializer`] over Nix [`Value`]s.
+//!
+//! Attribute sets deserialize as structs and maps, lists as sequences, and
+//! scalars as the matching Rust primitives. Values are forced lazily: an
+//! attribute is only evaluated when the target type asks for it, so fields
+//! the target ignores are never forced.
+//!
+//! Conversion errors raised below the root carry the attribute path to the
+//! offending value, e.g. `services.foo.port: expected int, got string`.
+
+#![cfg(feature = "serde")]
+
+use std::fmt;
+
+use serde::de::{
+  self,
+  DeserializeOwned,
+  DeserializeSeed,
+  IntoDeserializer,
+  Visitor,
+};
+
+use crate::{
+  Error,
+  Result,
+  Value,
+  ValueType,
+  attrs::AttrIterator,
+  lists::ListIterator,
+};
+
+/// Deserialize a Rust value from a Nix [`Value`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use nix_bindings::{Context, EvalStateBuilder, Store};
+/// #[derive(serde::Deserialize)]
+/// struct Service {
+///   port:   u16,
+///   #[serde(default)]
+///   enable: bool,
+/// }
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let ctx = Arc::new(Context::new()?);
+/// # let store = Arc::new(Store::open(&ctx, None)?);
+/// # let state = EvalStateBuilder::new(&store)?.build()?;
+/// let value = state.eval_from_string("{ port = 8080; }", "<eval>")?;
+/// let service: Service = nix_bindings::from_value(&value)?;
+/// assert_eq!(service.port, 8080);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// Returns an error if forcing any visited value fails or the value does not
+/// match the shape of `T`.
+pub fn from_value<T: DeserializeOwned>(value: &Value<'_>) -> Result<T> {
+  T::deserialize(Deserializer::new(value))
+}
+
+impl de::Error for Error {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    Error::Conversion {
+      path:    String::new(),
+      message: msg.to_string(),
+    }
+  }
+}
+
+/// Serde deserializer reading from a borrowed Nix [`Value`].
+///
+/// Most callers want [`from_value`]; construct this directly to drive a
+/// [`DeserializeSeed`] or a hand-written [`serde::Deserialize`] impl.
+pub struct Deserializer<'v, 'a> {
+  value: &'v Value<'a>,
+}
+
+impl<'v, 'a> Deserializer<'v, 'a> {
+  /// Create a deserializer over `value`.
+  #[must_use]
+  pub fn new(value: &'v Value<'a>) -> Self {
+    Deserializer { value }
+  }
+
+  /// Force the value and return its resolved type.
+  fn force(&self) -> Result<ValueType> {
+    self.value.force_shared()?;
+    Ok(self.value.value_type())
+  }
+
+  /// Force the value and fail unless it has the `expected` type.
+  fn expect(&self, expected: ValueType, name: &'static str) -> Result<()> {
+    let actual = self.force()?;
+    if actual != expected {
+      return Err(Error::InvalidType {
+        expected: name,
+        actual:   actual.to_string(),
+      });
+    }
+    Ok(())
+  }
+}
+
+/// Deserializer for an attribute name used as a map key or variant tag.
+fn name_deserializer(name: &str) -> de::value::StrDeserializer<'_, Error> {
+  name.into_deserializer()
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'_, '_> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    match self.force()? {
+      ValueType::Int => visitor.visit_i64(self.value.as_int()?),
+      ValueType::Float => visitor.visit_f64(self.value.as_float()?),
+      ValueType::Bool => visitor.visit_bool(self.value.as_bool()?),
+      ValueType::String => visitor.visit_string(self.value.as_string()?),
+      ValueType::Path => self.deserialize_string(visitor),
+      ValueType::Null => visitor.visit_unit(),
+      ValueType::Attrs => visitor.visit_map(AttrsAccess::new(self.value)?),
+      ValueType::List => visitor.visit_seq(ListAccess::new(self.value)?),
+      other
+      @ (ValueType::Function | ValueType::External | ValueType::Thunk) => {
+        Err(Error::Conversion {
+          path:    String::new(),
+          message: format!("cannot deserialize a {other}"),
+        })
+      },
+    }
+  }
+
+  fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    visitor.visit_bool(self.value.as_bool()?)
+  }
+
+  fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    visitor.visit_i64(self.value.as_int()?)
+  }
+
+  fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_i64(visitor)
+  }
+
+  fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_f64(visitor)
+  }
+
+  fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    // Nix freely mixes ints and floats in arithmetic, so accept an int where
+    // a float is expected rather than forcing callers to write `1.0`.
+    match self.force()? {
+      ValueType::Float => visitor.visit_f64(self.value.as_float()?),
+      ValueType::Int => visitor.visit_f64(self.value.as_int()? as f64),
+      other => {
+        Err(Error::InvalidType {
+          expected: "float",
+          actual:   other.to_string(),
+        })
+      },
+    }
+  }
+
+  fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_string(visitor)
+  }
+
+  fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_string(visitor)
+  }
+
+  fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    // Paths are accepted wherever a string is, matching how Nix coerces
+    // them in string contexts (without copying them to the store).
+    if self.force()? == ValueType::Path {
+      let path = self.value.as_path()?;
+      return match path.into_os_string().into_string() {
+        Ok(s) => visitor.visit_string(s),
+        Err(_) => {
+          Err(Error::Conversion {
+            path:    String::new(),
+            message: "path is not valid UTF-8".to_string(),
+          })
+        },
+      };
+    }
+    visitor.visit_string(self.value.as_string()?)
+  }
+
+  fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.deserialize_byte_buf(visitor)
+  }
+
+  fn deserialize_byte_buf<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value> {
+    // The serializer writes bytes as a list of ints; strings are accepted
+    // too, since that is how Nix itself usually carries binary data.
+    if self.force()? != ValueType::List {
+      return visitor.visit_byte_buf(self.value.as_bytes()?);
+    }
+    let bytes = self
+      .value
+      .list_iter()?
+      .enumerate()
+      .map(|(index, item)| {
+        let n = item?.as_int().map_err(|e| e.in_index(index))?;
+        u8::try_from(n).map_err(|_| {
+          Error::Conversion {
+            path:    String::new(),
+            message: format!("byte out of range: {n}"),
+          }
+          .in_index(index)
+        })
+      })
+      .collect::<Result<Vec<u8>>>()?;
+    visitor.visit_byte_buf(bytes)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    if self.force()? == ValueType::Null {
+      visitor.visit_none()
+    } else {
+      visitor.visit_some(self)
+    }
+  }
+
+  fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.expect(ValueType::Null, "null")?;
+    visitor.visit_unit()
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value> {
+    self.deserialize_unit(visitor)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.expect(ValueType::List, "list")?;
+    visitor.visit_seq(ListAccess::new(self.value)?)
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+    self.expect(ValueType::Attrs, "attrs")?;
+    visitor.visit_map(AttrsAccess::new(self.value)?)
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value> {
+    self.deserialize_map(visitor)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value> {
+    match self.force()? {
+      // Unit variants are plain strings: `"red"`.
+      ValueType::String => {
+        let variant: de::value::StringDeserializer<Error> =
+          self.value.as_string()?.into_deserializer();
+        visitor.visit_enum(variant)
+      },
+      // Everything else is externally tagged: `{ rgb = [ 1 2 3 ]; }`.
+      ValueType::Attrs => {
+        let mut iter = self.value.attrs()?;
+        match (iter.next(), iter.next()) {
+          (Some(entry), None) => {
+            let (name, value) = entry?;
+            visitor.visit_enum(VariantAccess { name, value })
+          },
+          _ => {
+            Err(Error::Conversion {
+              path:    String::new(),
+              message: "expected an attribute set with exactly one attribute \
+                        naming the enum variant"
+                .to_string(),
+            })
+          },
+        }
+      },
+      other => {
+        Err(Error::InvalidType {
+          expected: "string or attrs",
+          actual:   other.to_string(),
+        })
+      },
+    }
+  }
+
+  fn deserialize_identifier<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value> {
+    self.deserialize_string(visitor)
+  }
+
+  fn deserialize_ignored_any<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value> {
+    // Deliberately does not force: ignored attributes stay unevaluated.
+    visitor.visit_unit()
+  }
+}
+
+/// [`de::MapAccess`] over the attributes of an attribute set.
+///
+/// Attribute values are fetched lazily and only forced when their
+/// deserializer asks for them.
+struct AttrsAccess<'v> {
+  iter:    AttrIterator<'v>,
+  pending: Option<(String, Value<'v>)>,
+}
+
+impl<'v> AttrsAccess<'v> {
+  fn new(value: &'v Value<'_>) -> Result<Self> {
+    Ok(AttrsAccess {
+      iter:    value.attrs()?,
+      pending: None,
+    })
+  }
+}
+
+impl<'de> de::MapAccess<'de> for AttrsAccess<'_> {
+  type Error = Error;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>> {
+    let Some(entry) = self.iter.next() else {
+      return Ok(None);
+    };
+    let (name, value) = entry?;
+    let key = seed.deserialize(name_deserializer(&name))?;
+    self.pending = Some((name, value));
+    Ok(Some(key))
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(
+    &mut self,
+    seed: V,
+  ) -> Result<V::Value> {
+    let (name, value) = self.pending.take().ok_or_else(|| {
+      Error::Unknown("next_value_seed called before next_key_seed".to_string())
+    })?;
+    seed
+      .deserialize(Deserializer::new(&value))
+      .map_err(|e| e.in_attr(&name))
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.iter.len())
+  }
+}
+
+/// [`de::SeqAccess`] over the elements of a list.
+struct ListAccess<'v> {
+  iter:  ListIterator<'v>,
+  index: usize,
+}
+
+impl<'v> ListAccess<'v> {
+  fn new(value: &'v Value<'_>) -> Result<Self> {
+    Ok(ListAccess {
+      iter:  value.list_iter()?,
+      index: 0,
+    })
+  }
+}
+
+impl<'de> de::SeqAccess<'de> for ListAccess<'_> {
+  type Error = Error;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>> {
+    let Some(item) = self.iter.next() else {
+      return Ok(None);
+    };
+    let index = self.index;
+    self.index += 1;
+    let value = item.map_err(|e| e.in_index(index))?;
+    seed
+      .deserialize(Deserializer::new(&value))
+      .map(Some)
+      .map_err(|e| e.in_index(index))
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.iter.len())
+  }
+}
+
+/// [`de::EnumAccess`] for an externally tagged `{ variant = payload; }` set.
+struct VariantAccess<'v> {
+  name:  String,
+  value: Value<'v>,
+}
+
+impl<'de, 'v> de::EnumAccess<'de> for VariantAccess<'v> {
+  type Error = Error;
+  type Variant = Self;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(
+    self,
+    seed: V,
+  ) -> Result<(V::Value, Self)> {
+    let variant = seed.deserialize(name_deserializer(&self.name))?;
+    Ok((variant, self))
+  }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess<'_> {
+  type Error = Error;
+
+  fn unit_variant(self) -> Result<()> {
+    de::Deserialize::deserialize(Deserializer::new(&self.value))
+      .map_err(|e| e.in_attr(&self.name))
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+    self,
+    seed: T,
+  ) -> Result<T::Value> {
+    seed
+      .deserialize(Deserializer::new(&self.value))
+      .map_err(|e| e.in_attr(&self.name))
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value> {
+    de::Deserializer::deserialize_seq(Deserializer::new(&self.value), visitor)
+      .map_err(|e| e.in_attr(&self.name))
+  }
+
+  fn struct_variant<V: Visitor<'de>>(
+    self,
+    _fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value> {
+    de::Deserializer::deserialize_map(Deserializer::new(&self.value), visitor)
+      .map_err(|e| e.in_attr(&self.name))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use serde::Deserialize;
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[derive(Debug, Deserialize, PartialEq)]
+  struct Service {
+    port:    u16,
+    #[serde(default)]
+    enable:  bool,
+    tags:    Vec<String>,
+    comment: Option<String>,
+  }
+
+  #[derive(Debug, Deserialize, PartialEq)]
+  enum Color {
+    Red,
+    Rgb(u8, u8, u8),
+  }
+
+  #[test]
+  #[serial]
+  fn test_from_value_struct() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ port = 8080; tags = [ \"a\" \"b\" ]; comment = null; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate attrs");
+
+    let service: Service = from_value(&value).expect("Failed to deserialize");
+    assert_eq!(service, Service {
+      port:    8080,
+      enable:  false,
+      tags:    vec!["a".to_string(), "b".to_string()],
+      comment: None,
+    });
+  }
+
+  #[test]
+  #[serial]
+  fn test_from_value_is_lazy() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ port = 1; tags = [ ]; unused = throw \"forced\"; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate attrs");
+
+    let service: Service =
+      from_value(&value).expect("Ignored attributes should not be forced");
+    assert_eq!(service.port, 1);
+  }
+
+  #[test]
+  #[serial]
+  fn test_from_value_error_path() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "{ services.foo = { port = \"http\"; tags = [ ]; }; }",
+        "<eval>",
+      )
+      .expect("Failed to evaluate attrs");
+
+    let err = from_value::<BTreeMap<String, BTreeMap<String, Service>>>(&value)
+      .expect_err("Type mismatch should fail");
+    assert_eq!(
+      err.to_string(),
+      "services.foo.port: expected int, got string"
+    );
+  }
+
+  #[test]
+  #[serial]
+  fn test_from_value_enum() {
+    let state = setup();
+
+    let red = state
+      .eval_from_string("\"Red\"", "<eval>")
+      .expect("Failed to evaluate string");
+    assert_eq!(from_value::<Color>(&red).unwrap(), Color::Red);
+
+    let rgb = state
+      .eval_from_string("{ Rgb = [ 1 2 3 ]; }", "<eval>")
+      .expect("Failed to evaluate attrs");
+    assert_eq!(from_value::<Color>(&rgb).unwrap(), Color::Rgb(1, 2, 3));
+  }
+}
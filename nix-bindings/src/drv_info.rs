@@ -0,0 +1,707 @@
+//! A typed view of derivation values, as `nix-env` sees them.
+//!
+//! [`DrvInfo`] reads the attributes of a derivation one by one on demand:
+//! building one forces only the value and its `name`, so package sets can be
+//! listed without evaluating `meta`, instantiating `.drvPath` or building
+//! anything. [`Packages`] walks a package set the way `nix-env -qa` does.
+
+#![cfg(feature = "shim")]
+
+use std::collections::HashSet;
+
+use crate::{
+  AttrPath,
+  AttrPathSegment,
+  Error,
+  EvalState,
+  NixValueOps,
+  Result,
+  Value,
+  ValueType,
+  error::{bytes_from_callback, check_err},
+  sys,
+};
+
+/// A derivation value.
+///
+/// Created by [`EvalState::drv_info`]. Accessors force only the attributes
+/// they read.
+#[derive(Clone)]
+pub struct DrvInfo<'a> {
+  value: Value<'a>,
+  name:  String,
+}
+
+impl EvalState {
+  /// View `value` as a derivation, if it is one.
+  ///
+  /// Forces `value` and its `name` attribute. A value is a derivation if it
+  /// is an attribute set with `type = "derivation"`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, including failed assertions, or
+  /// if the derivation's name is not valid UTF-8.
+  pub fn drv_info<'a>(&self, value: &Value<'a>) -> Result<Option<DrvInfo<'a>>> {
+    let mut err = sys::nix_err_NIX_OK;
+    // SAFETY: context, state, and value are valid for the call duration;
+    // the callback only runs during the call.
+    let name = unsafe {
+      bytes_from_callback(|callback, user_data| {
+        err = sys::nix_get_derivation_name(
+          self.context.as_ptr(),
+          self.inner.as_ptr(),
+          value.inner.as_ptr(),
+          false,
+          callback,
+          user_data,
+        );
+      })
+    };
+    // SAFETY: context is valid for the lifetime of self.
+    unsafe { check_err(self.context.as_ptr(), err)? };
+    let Some(name) = name else {
+      return Ok(None);
+    };
+    let name = String::from_utf8(name).map_err(|_| {
+      Error::Conversion {
+        path:    String::new(),
+        message: "derivation name is not valid UTF-8".to_string(),
+      }
+      .in_attr("name")
+    })?;
+    Ok(Some(DrvInfo {
+      value: value.clone(),
+      name,
+    }))
+  }
+}
+
+impl<'a> DrvInfo<'a> {
+  /// The underlying attribute set.
+  #[must_use]
+  pub fn value(&self) -> &Value<'a> {
+    &self.value
+  }
+
+  /// The `name` attribute, e.g. `hello-2.12.1`.
+  #[must_use]
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  /// The package name part of [`name`](Self::name), e.g. `hello`.
+  ///
+  /// The name is split at the first `-` not followed by a letter, as
+  /// `nix-env` and `builtins.parseDrvName` do.
+  #[must_use]
+  pub fn pname(&self) -> &str {
+    split_name(&self.name).0
+  }
+
+  /// The version part of [`name`](Self::name), e.g. `2.12.1`, or an empty
+  /// string if there is none.
+  #[must_use]
+  pub fn version(&self) -> &str {
+    split_name(&self.name).1
+  }
+
+  /// The `system` attribute, or `unknown` if it is missing.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or `system` is not a string.
+  pub fn system(&self) -> Result<String> {
+    match self.attr("system")? {
+      Some(system) => system.as_string(),
+      None => Ok("unknown".to_string()),
+    }
+  }
+
+  /// The `outPath` attribute, if present.
+  ///
+  /// The path's string context is dropped and nothing is built.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or `outPath` is not a string or
+  /// path.
+  pub fn out_path(&self) -> Result<Option<String>> {
+    self
+      .attr("outPath")?
+      .map(|path| path_string(&path))
+      .transpose()
+  }
+
+  /// The `drvPath` attribute, if present.
+  ///
+  /// Forcing `drvPath` instantiates the derivation, writing its `.drv`
+  /// file (and those of its dependencies) to the store, but builds
+  /// nothing.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if instantiation fails or `drvPath` is not a string.
+  pub fn drv_path(&self) -> Result<Option<String>> {
+    self
+      .attr("drvPath")?
+      .map(|path| path_string(&path))
+      .transpose()
+  }
+
+  /// The derivation's outputs and their paths, in the order of the
+  /// `outputs` attribute.
+  ///
+  /// Without an `outputs` attribute there is a single output `out` at
+  /// [`out_path`](Self::out_path).
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, `outputs` is not a list of strings,
+  /// or a listed output has no `outPath`.
+  pub fn outputs(&self) -> Result<Vec<(String, String)>> {
+    let Some(names) = self.output_names()? else {
+      let out_path = self
+        .out_path()?
+        .ok_or_else(|| Error::KeyNotFound("outPath".into()))?;
+      return Ok(vec![("out".to_string(), out_path)]);
+    };
+    names
+      .into_iter()
+      .map(|name| {
+        let path = self.output_path(&name)?;
+        Ok((name, path))
+      })
+      .collect()
+  }
+
+  /// The path of output `name`, or `None` if the derivation has no such
+  /// output.
+  ///
+  /// Forces only the `outputs` list and that output.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the output has no `outPath`.
+  pub fn query_output(&self, name: &str) -> Result<Option<String>> {
+    match self.output_names()? {
+      Some(names) if names.iter().any(|n| n == name) => {
+        self.output_path(name).map(Some)
+      },
+      Some(_) => Ok(None),
+      None if name == "out" => self.out_path(),
+      None => Ok(None),
+    }
+  }
+
+  /// The `meta` attribute set, unforced.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if looking up the attribute fails.
+  pub fn meta(&self) -> Result<Option<Value<'a>>> {
+    self.value.get_attr_lazy("meta")
+  }
+
+  fn attr(&self, name: &str) -> Result<Option<Value<'a>>> {
+    let Some(value) = self.value.get_attr_lazy(name)? else {
+      return Ok(None);
+    };
+    value.force_shared().map_err(|e| e.in_attr(name))?;
+    Ok(Some(value))
+  }
+
+  fn output_names(&self) -> Result<Option<Vec<String>>> {
+    let Some(outputs) = self.attr("outputs")? else {
+      return Ok(None);
+    };
+    outputs
+      .list_iter()
+      .map_err(|e| e.in_attr("outputs"))?
+      .enumerate()
+      .map(|(i, name)| {
+        name
+          .and_then(|name| name.as_string())
+          .map_err(|e| e.in_index(i).in_attr("outputs"))
+      })
+      .collect::<Result<_>>()
+      .map(Some)
+  }
+
+  fn output_path(&self, name: &str) -> Result<String> {
+    let output = self
+      .attr(name)?
+      .ok_or_else(|| Error::KeyNotFound(name.into()))?;
+    let path = output
+      .get_attr_lazy("outPath")
+      .map_err(|e| e.in_attr(name))?
+      .ok_or_else(|| Error::KeyNotFound(format!("{name}.outPath")))?;
+    path_string(&path).map_err(|e| e.in_attr("outPath").in_attr(name))
+  }
+}
+
+/// Split a derivation name into package name and version, like Nix's
+/// `DrvName`.
+fn split_name(name: &str) -> (&str, &str) {
+  let bytes = name.as_bytes();
+  for (i, &b) in bytes.iter().enumerate() {
+    if b == b'-' && bytes.get(i + 1).is_some_and(|c| !c.is_ascii_alphabetic()) {
+      return (&name[..i], &name[i + 1..]);
+    }
+  }
+  (name, "")
+}
+
+fn path_string(value: &Value<'_>) -> Result<String> {
+  value.force_shared()?;
+  match value.value_type() {
+    ValueType::Path => Ok(value.as_path()?.to_string_lossy().into_owned()),
+    _ => value.as_string(),
+  }
+}
+
+/// A derivation found by [`Packages`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+  /// The `name` attribute.
+  pub name:     String,
+  /// The `system` attribute, or `unknown`.
+  pub system:   String,
+  /// The `drvPath` attribute; `None` unless requested with
+  /// [`Packages::drv_paths`].
+  pub drv_path: Option<String>,
+  /// Output names and paths, in the order of the `outputs` attribute.
+  pub outputs:  Vec<(String, String)>,
+}
+
+/// Depth-first traversal of a package set, as `nix-env -qa` performs it.
+///
+/// Created by [`Value::packages`]. If the root is a function it is called
+/// with no arguments (so formals need defaults), then:
+///
+/// - a derivation is yielded on its own;
+/// - an attribute set is searched for derivations in its attributes, in name
+///   order, descending into nested sets only when they have
+///   `recurseForDerivations = true`;
+/// - a list is searched the same way, with each element's index as the last
+///   path segment, printed as `[0]`. Sets and lists among its elements are
+///   descended into whether or not they set `recurseForDerivations`; any other
+///   element is yielded as an error.
+///
+/// Each item is the attribute path and either the package or the error
+/// raised while evaluating it; an error does not stop the traversal. A
+/// derivation reachable under several names is yielded once.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use nix_bindings::{Context, EvalStateBuilder, Store};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let ctx = Arc::new(Context::new()?);
+/// # let store = Arc::new(Store::open(&ctx, None)?);
+/// # let state = EvalStateBuilder::new(&store)?.build()?;
+/// let pkgs = state.eval_from_string("import <nixpkgs> { }", "<eval>")?;
+/// for (path, package) in pkgs.packages().max_depth(Some(1)) {
+///   match package {
+///     Ok(package) => println!("{path}\t{}", package.name),
+///     Err(e) => eprintln!("{path}: {e}"),
+///   }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Packages<'a> {
+  root:      Option<Value<'a>>,
+  stack:     Vec<Frame<'a>>,
+  seen:      HashSet<usize>,
+  max_depth: Option<usize>,
+  drv_paths: bool,
+  prune:     Option<PruneFn<'a>>,
+}
+
+type PruneFn<'a> = Box<dyn FnMut(&AttrPath) -> bool + 'a>;
+
+/// A set or list whose children are being searched.
+struct Frame<'a> {
+  value:    Value<'a>,
+  path:     AttrPath,
+  depth:    usize,
+  /// Attribute names in order, or list indices.
+  children: Vec<AttrPathSegment>,
+  /// The elements, for a list.
+  items:    Option<Vec<Value<'a>>>,
+  next:     usize,
+}
+
+impl<'a> Value<'a> {
+  /// Find the derivations in this package set, like `nix-env -qa`.
+  ///
+  /// Nothing is evaluated until the iterator is advanced; see [`Packages`].
+  #[must_use]
+  pub fn packages(&self) -> Packages<'a> {
+    Packages {
+      root:      Some(self.clone()),
+      stack:     Vec::new(),
+      seen:      HashSet::new(),
+      max_depth: None,
+      drv_paths: false,
+      prune:     None,
+    }
+  }
+}
+
+impl<'a> Packages<'a> {
+  /// Descend at most this many levels of `recurseForDerivations` sets
+  /// below the root; `Some(0)` only looks at the root's own attributes.
+  /// Default: no limit.
+  #[must_use]
+  pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+    self.max_depth = max_depth;
+    self
+  }
+
+  /// Also report each package's `drvPath`, instantiating every derivation
+  /// found. Default: `false`.
+  #[must_use]
+  pub fn drv_paths(mut self, drv_paths: bool) -> Self {
+    self.drv_paths = drv_paths;
+    self
+  }
+
+  /// Skip every attribute whose path `prune` returns `true` for, along with
+  /// everything below it. The attribute is not evaluated.
+  #[must_use]
+  pub fn prune(mut self, prune: impl FnMut(&AttrPath) -> bool + 'a) -> Self {
+    self.prune = Some(Box::new(prune));
+    self
+  }
+
+  /// Evaluate the root and set up the first frame, or return the root as
+  /// the only item.
+  fn start(&mut self, root: Value<'a>) -> Option<(AttrPath, Result<Package>)> {
+    let path = AttrPath::new();
+    let root = match root.state.auto_call_function(None, &root) {
+      Ok(root) => root,
+      Err(e) => return Some((path, Err(e))),
+    };
+    match root.state.drv_info(&root) {
+      Ok(Some(drv)) => return Some((path, self.package(&drv))),
+      Ok(None) => {},
+      Err(e) => return Some((path, Err(e))),
+    }
+    match self.frame(root, path.clone(), 0) {
+      Ok(Some(frame)) => {
+        self.stack.push(frame);
+        None
+      },
+      Ok(None) => Some((path, Err(not_a_package_set()))),
+      Err(e) => Some((path, Err(e))),
+    }
+  }
+
+  /// A frame for `value` if it is an attribute set or list.
+  fn frame(
+    &self,
+    value: Value<'a>,
+    path: AttrPath,
+    depth: usize,
+  ) -> Result<Option<Frame<'a>>> {
+    value.force_shared()?;
+    let (children, items) = match value.value_type() {
+      ValueType::Attrs => {
+        let mut names = value.attr_keys()?;
+        names.sort();
+        (names.into_iter().map(AttrPathSegment::Attr).collect(), None)
+      },
+      ValueType::List => {
+        let items = value.list_items()?;
+        (
+          (0..items.len()).map(AttrPathSegment::Index).collect(),
+          Some(items),
+        )
+      },
+      _ => return Ok(None),
+    };
+    Ok(Some(Frame {
+      value,
+      path,
+      depth,
+      children,
+      items,
+      next: 0,
+    }))
+  }
+
+  /// Look at one child: a package, a set or list to descend into, or
+  /// nothing.
+  fn visit(
+    &mut self,
+    frame: &Frame<'a>,
+    child: Value<'a>,
+    path: &AttrPath,
+  ) -> Result<Option<Package>> {
+    if let Some(drv) = child.state.drv_info(&child)? {
+      let id = child.identity();
+      if id != 0 && !self.seen.insert(id) {
+        return Ok(None);
+      }
+      return self.package(&drv).map(Some);
+    }
+    // List elements are always descended into, attributes only when they
+    // ask for it.
+    let descend = if frame.items.is_some() {
+      true
+    } else if child.value_type() == ValueType::Attrs {
+      match child.get_attr_lazy("recurseForDerivations")? {
+        Some(recurse) => {
+          recurse
+            .as_bool()
+            .map_err(|e| e.in_attr("recurseForDerivations"))?
+        },
+        None => false,
+      }
+    } else {
+      false
+    };
+    if !descend || self.max_depth.is_some_and(|max| frame.depth >= max) {
+      return Ok(None);
+    }
+    match self.frame(child, path.clone(), frame.depth + 1)? {
+      Some(frame) => self.stack.push(frame),
+      None => return Err(not_a_package_set()),
+    }
+    Ok(None)
+  }
+
+  fn package(&self, drv: &DrvInfo<'_>) -> Result<Package> {
+    Ok(Package {
+      name:     drv.name().to_string(),
+      system:   drv.system()?,
+      drv_path: if self.drv_paths {
+        drv.drv_path()?
+      } else {
+        None
+      },
+      outputs:  drv.outputs()?,
+    })
+  }
+}
+
+impl Iterator for Packages<'_> {
+  type Item = (AttrPath, Result<Package>);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if let Some(root) = self.root.take()
+      && let Some(item) = self.start(root)
+    {
+      return Some(item);
+    }
+    loop {
+      let mut frame = self.stack.pop()?;
+      let Some(segment) = frame.children.get(frame.next).cloned() else {
+        continue;
+      };
+      frame.next += 1;
+      let mut path = frame.path.clone();
+      path.push(segment.clone());
+      if let Some(prune) = &mut self.prune
+        && prune(&path)
+      {
+        self.stack.push(frame);
+        continue;
+      }
+      let child = match &segment {
+        AttrPathSegment::Index(index) => {
+          let items = frame.items.as_deref().unwrap_or_default();
+          items.get(*index).cloned().ok_or(Error::IndexOutOfBounds {
+            index:  *index,
+            length: items.len(),
+          })
+        },
+        AttrPathSegment::Attr(name) => {
+          frame.value.get_attr_lazy(name).and_then(|child| {
+            child.ok_or_else(|| Error::KeyNotFound(name.clone()))
+          })
+        },
+      };
+      // Children found while visiting go above this frame, so they are
+      // searched before its remaining siblings.
+      let depth = self.stack.len();
+      let result = child.and_then(|child| self.visit(&frame, child, &path));
+      self.stack.insert(depth, frame);
+      match result {
+        Ok(Some(package)) => return Some((path, Ok(package))),
+        Ok(None) => {},
+        Err(e) => return Some((path, Err(e))),
+      }
+    }
+  }
+}
+
+fn not_a_package_set() -> Error {
+  Error::Unknown(
+    "expression does not evaluate to a derivation (or a set or list of those)"
+      .into(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  fn test_split_name() {
+    assert_eq!(split_name("hello-2.12.1"), ("hello", "2.12.1"));
+    assert_eq!(split_name("nix-index-0.1"), ("nix-index", "0.1"));
+    assert_eq!(split_name("firefox"), ("firefox", ""));
+    assert_eq!(split_name("foo-"), ("foo-", ""));
+  }
+
+  #[test]
+  #[serial]
+  fn test_drv_info() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        r#"{
+          type = "derivation";
+          name = "hello-2.12.1";
+          system = "x86_64-linux";
+          outPath = "/nix/store/aaaa-hello-2.12.1";
+          outputs = [ "out" "man" ];
+          out.outPath = "/nix/store/aaaa-hello-2.12.1";
+          man.outPath = "/nix/store/bbbb-hello-2.12.1-man";
+          meta = throw "meta is not forced";
+          drvPath = throw "drvPath is not forced";
+        }"#,
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+    let drv = state
+      .drv_info(&value)
+      .expect("Failed to inspect")
+      .expect("value should be a derivation");
+
+    assert_eq!((drv.pname(), drv.version()), ("hello", "2.12.1"));
+    assert_eq!(drv.system().unwrap(), "x86_64-linux");
+    assert_eq!(
+      drv.out_path().unwrap().as_deref(),
+      Some("/nix/store/aaaa-hello-2.12.1")
+    );
+    assert_eq!(drv.outputs().unwrap(), [
+      (
+        "out".to_string(),
+        "/nix/store/aaaa-hello-2.12.1".to_string()
+      ),
+      (
+        "man".to_string(),
+        "/nix/store/bbbb-hello-2.12.1-man".to_string()
+      ),
+    ]);
+    assert_eq!(
+      drv.query_output("man").unwrap().as_deref(),
+      Some("/nix/store/bbbb-hello-2.12.1-man")
+    );
+    assert_eq!(drv.query_output("dev").unwrap(), None);
+    let meta = drv.meta().unwrap().expect("meta should be present");
+    assert!(meta.force_shared().is_err());
+
+    let plain = state
+      .eval_from_string("{ name = \"x\"; }", "<eval>")
+      .unwrap();
+    assert!(state.drv_info(&plain).unwrap().is_none());
+
+    // A name that is not UTF-8 is an error, not "not a derivation".
+    let latin1 = state
+      .make_attrs_from_iter([
+        Ok(("type", state.make_string("derivation").unwrap())),
+        Ok(("name", state.make_string_bytes(b"caf\xe9-1.0").unwrap())),
+      ])
+      .unwrap();
+    match state.drv_info(&latin1) {
+      Err(Error::Conversion { path, .. }) => assert_eq!(path, "name"),
+      Err(e) => panic!("expected a conversion error, got {e}"),
+      Ok(_) => panic!("expected a conversion error"),
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_packages() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        r#"{ }: let
+          drv = name: {
+            type = "derivation";
+            inherit name;
+            system = "x86_64-linux";
+            outPath = "/nix/store/aaaa-${name}";
+          };
+          hello = drv "hello-1.0";
+        in {
+          inherit hello;
+          alias = hello;
+          broken = throw "broken package";
+          hidden = { inner = drv "inner-1"; };
+          python = {
+            recurseForDerivations = true;
+            requests = drv "requests-2.0";
+            deep = { recurseForDerivations = true; x = drv "x-1"; };
+          };
+          skipped = { recurseForDerivations = true; y = drv "y-1"; };
+        }"#,
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+
+    let found = |packages: Packages<'_>| {
+      packages
+        .map(|(path, package)| {
+          (
+            path.to_string(),
+            package.map(|p| p.name).map_err(|e| e.to_string()),
+          )
+        })
+        .collect::<Vec<_>>()
+    };
+
+    let items = found(
+      value
+        .packages()
+        .max_depth(Some(1))
+        .prune(|path| path.to_string() == "skipped"),
+    );
+    assert_eq!(items.len(), 3, "{items:?}");
+    assert_eq!(items[0].0, "alias");
+    assert_eq!(items[0].1.as_deref(), Ok("hello-1.0"));
+    assert_eq!(items[1].0, "broken");
+    assert!(items[1].1.as_ref().unwrap_err().contains("broken package"));
+    assert_eq!(items[2].0, "python.requests");
+
+    let items = found(value.packages());
+    let paths: Vec<_> = items.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, [
+      "alias",
+      "broken",
+      "python.deep.x",
+      "python.requests",
+      "skipped.y"
+    ]);
+
+    let list = state
+      .eval_from_string(
+        "[ { type = \"derivation\"; name = \"a\"; outPath = \"/a\"; } 1 ]",
+        "<eval>",
+      )
+      .unwrap();
+    let items = found(list.packages());
+    assert_eq!(items[0], ("[0]".to_string(), Ok("a".to_string())));
+    assert_eq!(items[1].0, "[1]");
+    assert!(items[1].1.is_err());
+  }
+}
@@ -11,6 +11,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// Error types for Nix operations.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
   /// Unknown error from Nix C API.
   Unknown(String),
@@ -44,6 +45,59 @@ pub enum Error {
 
   /// String conversion error.
   StringConversion(std::ffi::NulError),
+
+  /// I/O error while reading or writing a serialized value.
+  Io(std::io::Error),
+
+  /// Malformed JSON input.
+  Json {
+    /// Byte offset into the input where the problem was detected.
+    offset:  usize,
+    /// Description of the problem.
+    message: String,
+  },
+
+  /// An attribute path did not parse.
+  InvalidAttrPath {
+    /// The input that was being parsed.
+    input:   String,
+    /// Description of the problem.
+    message: String,
+  },
+
+  /// An attribute along a path was missing.
+  AttrNotFound {
+    /// The path to the attribute set that lacks it, empty at the root.
+    path:        String,
+    /// The missing attribute name.
+    attr:        String,
+    /// Existing names close to `attr`, closest first.
+    suggestions: Vec<String>,
+  },
+
+  /// Conversion between a Nix value and a Rust type failed.
+  Conversion {
+    /// Attribute path to the offending value (e.g. `services.foo.port`),
+    /// empty when the failure is at the root.
+    path:    String,
+    /// Description of the failure.
+    message: String,
+  },
+
+  /// Nix source failed to parse.
+  Syntax {
+    /// File the source was read from, `«string»` for inline expressions.
+    file:    String,
+    /// Line number, starting at 1.
+    line:    u32,
+    /// Column number, starting at 1.
+    column:  u32,
+    /// The parser's message, without the position.
+    message: String,
+  },
+
+  /// Evaluation was cancelled or ran out of time.
+  Interrupted,
 }
 
 impl fmt::Display for Error {
@@ -61,22 +115,109 @@ impl fmt::Display for Error {
       },
       Error::NullPointer => write!(f, "Null pointer error"),
       Error::StringConversion(e) => write!(f, "String conversion error: {e}"),
+      Error::Io(e) => write!(f, "I/O error: {e}"),
+      Error::Json { offset, message } => {
+        write!(f, "JSON error at byte {offset}: {message}")
+      },
+      Error::InvalidAttrPath { input, message } => {
+        write!(f, "invalid attribute path '{input}': {message}")
+      },
+      Error::AttrNotFound {
+        path,
+        attr,
+        suggestions,
+      } => {
+        write!(f, "attribute '{attr}' missing")?;
+        if !path.is_empty() {
+          write!(f, " at '{path}'")?;
+        }
+        match suggestions.as_slice() {
+          [] => Ok(()),
+          [only] => write!(f, "; did you mean {only}?"),
+          [init @ .., last] => {
+            write!(f, "; did you mean one of {} or {last}?", init.join(", "))
+          },
+        }
+      },
+      Error::Conversion { path, message } => {
+        if path.is_empty() {
+          write!(f, "{message}")
+        } else {
+          write!(f, "{path}: {message}")
+        }
+      },
+      Error::Syntax {
+        file,
+        line,
+        column,
+        message,
+      } => write!(f, "syntax error at {file}:{line}:{column}: {message}"),
+      Error::Interrupted => write!(f, "evaluation interrupted"),
     }
   }
 }
 
 impl std::error::Error for Error {}
 
+#[cfg(feature = "expr")]
+impl Error {
+  /// Record that this error occurred below the attribute `name`.
+  ///
+  /// Conversion failures ([`Error::Conversion`] and [`Error::InvalidType`])
+  /// become an [`Error::Conversion`] with `name` prepended to its path,
+  /// quoted when it is not a plain identifier. Other errors, such as
+  /// evaluation failures or interruption, are returned unchanged.
+  /// Call this while unwinding out of a nested value so the path reads
+  /// from the root, e.g. `services.foo.port`.
+  #[must_use]
+  pub fn in_attr(self, name: &str) -> Self {
+    self.in_segment(&crate::attrs::format_attr_name(name))
+  }
+
+  /// Record that this error occurred below the list element at `index`.
+  ///
+  /// See [`in_attr`](Self::in_attr); indices render as `[index]`.
+  #[must_use]
+  pub fn in_index(self, index: usize) -> Self {
+    self.in_segment(&format!("[{index}]"))
+  }
+
+  fn in_segment(self, segment: &str) -> Self {
+    let (path, message) = match self {
+      Error::Conversion { path, message } => (path, message),
+      Error::InvalidType { expected, actual } => {
+        (String::new(), format!("expected {expected}, got {actual}"))
+      },
+      other => return other,
+    };
+    let path = if path.is_empty() {
+      segment.to_string()
+    } else if path.starts_with('[') {
+      format!("{segment}{path}")
+    } else {
+      format!("{segment}.{path}")
+    };
+    Error::Conversion { path, message }
+  }
+}
+
 impl From<std::ffi::NulError> for Error {
   fn from(e: std::ffi::NulError) -> Self {
     Error::StringConversion(e)
   }
 }
 
+impl From<std::io::Error> for Error {
+  fn from(e: std::io::Error) -> Self {
+    Error::Io(e)
+  }
+}
+
 /// Extract a string from a Nix context using a callback-based API.
 ///
 /// Many Nix C API functions return strings via callbacks. This helper
-/// makes that pattern ergonomic.
+/// makes that pattern ergonomic. Returns `None` for strings that are not
+/// valid UTF-8.
 ///
 /// # Safety
 ///
@@ -86,9 +227,23 @@ pub(crate) unsafe fn string_from_callback<F>(call: F) -> Option<String>
 where
   F: FnOnce(sys::nix_get_string_callback, *mut std::os::raw::c_void),
 {
-  let mut result: Option<String> = None;
+  unsafe { bytes_from_callback(call) }
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// Like [`string_from_callback`], but returns the bytes as they are.
+///
+/// # Safety
+///
+/// `call` must invoke `callback` with a valid string pointer and length.
+#[cfg(feature = "store")]
+pub(crate) unsafe fn bytes_from_callback<F>(call: F) -> Option<Vec<u8>>
+where
+  F: FnOnce(sys::nix_get_string_callback, *mut std::os::raw::c_void),
+{
+  let mut result: Option<Vec<u8>> = None;
   let user_data = &mut result as *mut _ as *mut std::os::raw::c_void;
-  call(Some(collect_string), user_data);
+  call(Some(collect_bytes), user_data);
   result
 }
 
@@ -109,9 +264,31 @@ where
     *mut std::os::raw::c_void,
   ) -> sys::nix_err,
 {
-  let mut result = None;
+  let bytes = unsafe { checked_bytes_from_callback(ctx, call) }?;
+  String::from_utf8(bytes)
+    .map_err(|_| Error::Unknown("Invalid UTF-8 in string".to_string()))
+}
+
+/// Extract a byte string from a fallible Nix callback API.
+///
+/// # Safety
+///
+/// `call` must invoke `callback` with a valid string pointer and length when
+/// it returns [`sys::nix_err_NIX_OK`].
+#[cfg(feature = "store")]
+pub(crate) unsafe fn checked_bytes_from_callback<F>(
+  ctx: *mut sys::nix_c_context,
+  call: F,
+) -> Result<Vec<u8>>
+where
+  F: FnOnce(
+    sys::nix_get_string_callback,
+    *mut std::os::raw::c_void,
+  ) -> sys::nix_err,
+{
+  let mut result: Option<Vec<u8>> = None;
   let user_data = &mut result as *mut _ as *mut std::os::raw::c_void;
-  let err = call(Some(collect_string), user_data);
+  let err = call(Some(collect_bytes), user_data);
   check_err(ctx, err)?;
   result.ok_or_else(|| {
     Error::Unknown("Nix string callback returned no string".to_string())
@@ -119,16 +296,16 @@ where
 }
 
 #[cfg(feature = "store")]
-unsafe extern "C" fn collect_string(
+unsafe extern "C" fn collect_bytes(
   start: *const std::os::raw::c_char,
   n: std::os::raw::c_uint,
   user_data: *mut std::os::raw::c_void,
 ) {
-  let result = unsafe { &mut *(user_data as *mut Option<String>) };
+  let result = unsafe { &mut *(user_data as *mut Option<Vec<u8>>) };
   if !start.is_null() {
     let bytes =
       unsafe { std::slice::from_raw_parts(start.cast::<u8>(), n as usize) };
-    *result = std::str::from_utf8(bytes).ok().map(str::to_owned);
+    *result = Some(bytes.to_vec());
   }
 }
 
@@ -3,11 +3,15 @@
 
 #![cfg(feature = "expr")]
 
+#[cfg(feature = "shim")] use std::time::Duration;
 use std::{ffi::CString, path::Path, ptr::NonNull, sync::Arc};
 
+#[cfg(feature = "shim")] use crate::CancelToken;
 use crate::{
+  AttrsBuilder,
   Context,
   Error,
+  ListBuilder,
   Result,
   Store,
   StorePath,
@@ -22,10 +26,55 @@ use crate::{
 /// This allows configuring the evaluation environment before creating
 /// the evaluation state.
 pub struct EvalStateBuilder {
-  inner:     NonNull<sys::nix_eval_state_builder>,
-  store:     Arc<Store>,
-  context:   Arc<Context>,
-  skip_load: bool,
+  inner:         NonNull<sys::nix_eval_state_builder>,
+  store:         Arc<Store>,
+  context:       Arc<Context>,
+  skip_load:     bool,
+  /// Evaluator settings, applied in order after the configuration is
+  /// loaded so that they win over it.
+  #[cfg(feature = "shim")]
+  settings:      Vec<(&'static str, String)>,
+  #[cfg(feature = "shim")]
+  allowed_paths: Vec<std::path::PathBuf>,
+  #[cfg(feature = "shim")]
+  allowed_uris:  Vec<String>,
+  /// Changes to `builtins`, in order; `None` removes the name, and `true`
+  /// also rebinds its top-level name.
+  #[cfg(feature = "shim")]
+  builtins:      Vec<(String, Option<Builtin>, bool)>,
+  #[cfg(feature = "shim")]
+  cancel:        Option<CancelToken>,
+  #[cfg(feature = "shim")]
+  timeout:       Option<Duration>,
+}
+
+/// A value [`EvalStateBuilder::with_builtin`] adds to `builtins`.
+///
+/// Values belong to one evaluator, so a builtin is described here and only
+/// created once the state is built.
+#[cfg(feature = "shim")]
+pub enum Builtin {
+  /// A primop, callable as `builtins.<name>`.
+  #[cfg(feature = "primop")]
+  PrimOp(crate::primop::PrimOp),
+  /// Plain data.
+  Data(crate::NixData),
+  /// A Nix expression, evaluated in the new state.
+  Expr(String),
+}
+
+#[cfg(all(feature = "shim", feature = "primop"))]
+impl From<crate::primop::PrimOp> for Builtin {
+  fn from(primop: crate::primop::PrimOp) -> Self {
+    Builtin::PrimOp(primop)
+  }
+}
+
+#[cfg(feature = "shim")]
+impl From<crate::NixData> for Builtin {
+  fn from(data: crate::NixData) -> Self {
+    Builtin::Data(data)
+  }
 }
 
 impl EvalStateBuilder {
@@ -51,6 +100,18 @@ impl EvalStateBuilder {
       store: Arc::clone(store),
       context: Arc::clone(&store._context),
       skip_load: false,
+      #[cfg(feature = "shim")]
+      settings: Vec::new(),
+      #[cfg(feature = "shim")]
+      allowed_paths: Vec::new(),
+      #[cfg(feature = "shim")]
+      allowed_uris: Vec::new(),
+      #[cfg(feature = "shim")]
+      builtins: Vec::new(),
+      #[cfg(feature = "shim")]
+      cancel: None,
+      #[cfg(feature = "shim")]
+      timeout: None,
     })
   }
 
@@ -127,12 +188,196 @@ impl EvalStateBuilder {
     self
   }
 
+  /// Ignore the environment: skip loading configuration (as
+  /// [`no_load_config`](Self::no_load_config)) and drop `NIX_PATH`, so the
+  /// lookup path is exactly what [`set_lookup_path`](Self::set_lookup_path)
+  /// sets.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn isolated(self) -> Self {
+    self.no_load_config().setting("nix-path", String::new())
+  }
+
+  /// Evaluate purely (`pure-eval`): no access to the filesystem, the
+  /// environment or `builtins.currentSystem`, and only locked fetches.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn pure_eval(self, enable: bool) -> Self {
+    self.setting("pure-eval", enable.to_string())
+  }
+
+  /// Restrict file and network access to the lookup path, the paths given
+  /// to [`allowed_paths`](Self::allowed_paths) and the URIs given to
+  /// [`allowed_uris`](Self::allowed_uris) (`restrict-eval`).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn restrict_eval(self, enable: bool) -> Self {
+    self.setting("restrict-eval", enable.to_string())
+  }
+
+  /// Allow access to these paths and everything below them under
+  /// [`restrict_eval`](Self::restrict_eval) or
+  /// [`pure_eval`](Self::pure_eval).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn allowed_paths<P: Into<std::path::PathBuf>>(
+    mut self,
+    paths: impl IntoIterator<Item = P>,
+  ) -> Self {
+    self.allowed_paths.extend(paths.into_iter().map(Into::into));
+    self
+  }
+
+  /// URI prefixes fetchers may access under
+  /// [`restrict_eval`](Self::restrict_eval) (`allowed-uris`).
+  ///
+  /// The setting separates URIs by whitespace, so [`build`](Self::build)
+  /// fails if one contains any.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn allowed_uris(
+    mut self,
+    uris: impl IntoIterator<Item = impl AsRef<str>>,
+  ) -> Self {
+    self
+      .allowed_uris
+      .extend(uris.into_iter().map(|uri| uri.as_ref().to_string()));
+    self
+  }
+
+  /// Allow importing from derivation outputs, building them during
+  /// evaluation (`allow-import-from-derivation`).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn allow_import_from_derivation(self, enable: bool) -> Self {
+    self.setting("allow-import-from-derivation", enable.to_string())
+  }
+
+  /// Maximum function call depth before evaluation fails with a stack
+  /// overflow error (`max-call-depth`).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn max_call_depth(self, depth: u32) -> Self {
+    self.setting("max-call-depth", depth.to_string())
+  }
+
+  /// The system `builtins.currentSystem` reports (`eval-system`).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn eval_system(self, system: &str) -> Self {
+    self.setting("eval-system", system.to_string())
+  }
+
+  /// Log the call stack with every `builtins.trace` (`trace-verbose`).
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn trace_verbose(self, enable: bool) -> Self {
+    self.setting("trace-verbose", enable.to_string())
+  }
+
+  /// Add `name` to `builtins` in the built state only, replacing any
+  /// builtin of that name, including primops registered globally with
+  /// [`PrimOp::register`](crate::primop::PrimOp::register).
+  ///
+  /// The builtin is reachable as `builtins.<name>`; a top-level name of the
+  /// same builtin, such as `map`, keeps its old value. Use
+  /// [`with_top_level_builtin`](Self::with_top_level_builtin) to rebind it.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn with_builtin(
+    mut self,
+    name: &str,
+    builtin: impl Into<Builtin>,
+  ) -> Self {
+    self
+      .builtins
+      .push((name.to_string(), Some(builtin.into()), false));
+    self
+  }
+
+  /// Like [`with_builtin`](Self::with_builtin), but also rebind the
+  /// builtin's top-level name, `name` or `__name`, such as `map` for
+  /// `builtins.map` or `__getEnv` for `builtins.getEnv`.
+  ///
+  /// Nix fixes the top-level scope when it creates the evaluator, so only
+  /// names it already binds there can be rebound; [`build`](Self::build)
+  /// fails with [`Error::KeyNotFound`] for any other name.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn with_top_level_builtin(
+    mut self,
+    name: &str,
+    builtin: impl Into<Builtin>,
+  ) -> Self {
+    self
+      .builtins
+      .push((name.to_string(), Some(builtin.into()), true));
+    self
+  }
+
+  /// Remove `name` from `builtins` in the built state only, along with its
+  /// top-level name, such as `map` for `builtins.map` or `__getEnv` for
+  /// `builtins.getEnv`.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn without_builtin(mut self, name: &str) -> Self {
+    self.builtins.push((name.to_string(), None, false));
+    self
+  }
+
+  /// Interrupt any single evaluation in the built state that runs longer
+  /// than `timeout`, failing it with [`Error::Interrupted`].
+  ///
+  /// The clock starts anew with each call into the evaluator, such as
+  /// [`EvalState::eval_from_string`] or [`Value::call`].
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Use `token` as the built state's [`CancelToken`], e.g. to cancel
+  /// several states at once. By default each state has its own.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn with_cancel_token(mut self, token: CancelToken) -> Self {
+    self.cancel = Some(token);
+    self
+  }
+
+  #[cfg(feature = "shim")]
+  fn setting(mut self, name: &'static str, value: String) -> Self {
+    self.settings.push((name, value));
+    self
+  }
+
+  /// The `allowed-uris` setting for the URIs given to
+  /// [`allowed_uris`](Self::allowed_uris), if any.
+  #[cfg(feature = "shim")]
+  fn allowed_uris_setting(&self) -> Result<Option<(&'static str, String)>> {
+    if self.allowed_uris.is_empty() {
+      return Ok(None);
+    }
+    if let Some(uri) = self
+      .allowed_uris
+      .iter()
+      .find(|uri| uri.contains(char::is_whitespace))
+    {
+      return Err(Error::Unknown(format!(
+        "allowed URI contains whitespace: {uri:?}"
+      )));
+    }
+    Ok(Some(("allowed-uris", self.allowed_uris.join(" "))))
+  }
+
   /// Build the evaluation state.
   ///
   /// # Errors
   ///
   /// Returns an error if the evaluation state cannot be built.
-  pub fn build(self) -> Result<EvalState> {
+  #[cfg_attr(not(feature = "shim"), allow(unused_mut))]
+  pub fn build(mut self) -> Result<EvalState> {
     if !self.skip_load {
       // SAFETY: context and builder are valid
       unsafe {
@@ -146,6 +391,26 @@ impl EvalStateBuilder {
       }
     }
 
+    #[cfg(feature = "shim")]
+    let allowed_uris = self.allowed_uris_setting()?;
+    #[cfg(feature = "shim")]
+    for (name, value) in self.settings.iter().chain(&allowed_uris) {
+      let name_c = CString::new(*name)?;
+      let value_c = CString::new(value.as_str())?;
+      // SAFETY: context, builder and strings are valid
+      unsafe {
+        check_err(
+          self.context.as_ptr(),
+          sys::nix_eval_state_builder_set_setting(
+            self.context.as_ptr(),
+            self.inner.as_ptr(),
+            name_c.as_ptr(),
+            value_c.as_ptr(),
+          ),
+        )?;
+      }
+    }
+
     // SAFETY: context and builder are valid
     let state_ptr = unsafe {
       sys::nix_eval_state_build(self.context.as_ptr(), self.inner.as_ptr())
@@ -153,11 +418,28 @@ impl EvalStateBuilder {
 
     let inner = NonNull::new(state_ptr).ok_or(Error::NullPointer)?;
 
-    Ok(EvalState {
+    let state = EvalState {
       inner,
       store: self.store.clone(),
       context: self.context.clone(),
-    })
+      #[cfg(feature = "shim")]
+      pure_eval: self
+        .settings
+        .iter()
+        .rfind(|(name, _)| *name == "pure-eval")
+        .map(|(_, value)| value == "true"),
+      #[cfg(feature = "shim")]
+      cancel: self.cancel.clone().unwrap_or_default(),
+      #[cfg(feature = "shim")]
+      timeout: self.timeout,
+    };
+    #[cfg(feature = "shim")]
+    for path in &self.allowed_paths {
+      state.allow_path_prefix(path)?;
+    }
+    #[cfg(feature = "shim")]
+    state.set_builtins(std::mem::take(&mut self.builtins))?;
+    Ok(state)
   }
 }
 
@@ -179,6 +461,14 @@ pub struct EvalState {
   #[expect(dead_code, reason = "keeps the Arc<Store> alive Drop side-effects")]
   store:              Arc<Store>,
   pub(crate) context: Arc<Context>,
+  /// `pure-eval` as set on the builder; `None` when it follows the global
+  /// setting.
+  #[cfg(feature = "shim")]
+  pure_eval:          Option<bool>,
+  #[cfg(feature = "shim")]
+  pub(crate) cancel:  CancelToken,
+  #[cfg(feature = "shim")]
+  pub(crate) timeout: Option<Duration>,
 }
 
 impl EvalState {
@@ -205,7 +495,7 @@ impl EvalState {
     }
 
     // SAFETY: all pointers are valid
-    unsafe {
+    self.interruptible(|| unsafe {
       check_err(
         self.context.as_ptr(),
         sys::nix_expr_eval_from_string(
@@ -215,8 +505,8 @@ impl EvalState {
           path_c.as_ptr(),
           value_ptr,
         ),
-      )?;
-    }
+      )
+    })?;
 
     let inner = NonNull::new(value_ptr).ok_or(Error::NullPointer)?;
 
@@ -350,6 +640,30 @@ impl EvalState {
     Ok(v)
   }
 
+  /// Create a Nix string value from raw bytes, which need not be valid
+  /// UTF-8.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `bytes` contains a NUL byte, which Nix strings
+  /// cannot hold, or value creation fails.
+  pub fn make_string_bytes(&self, bytes: &[u8]) -> Result<Value<'_>> {
+    let v = self.alloc_value()?;
+    let s_c = CString::new(bytes)?;
+    // SAFETY: context and value are valid
+    unsafe {
+      check_err(
+        self.context.as_ptr(),
+        sys::nix_init_string(
+          self.context.as_ptr(),
+          v.inner.as_ptr(),
+          s_c.as_ptr(),
+        ),
+      )?;
+    }
+    Ok(v)
+  }
+
   /// Create a Nix path value.
   ///
   /// # Pure Evaluation
@@ -379,7 +693,7 @@ impl EvalState {
     #[cfg(feature = "shim")]
     if path.as_ref().is_absolute()
       && path_str.starts_with("/nix/store/")
-      && is_pure_eval()
+      && self.pure_eval.unwrap_or_else(is_pure_eval)
     {
       self.allow_store_path(path_str)?;
     }
@@ -425,123 +739,195 @@ impl EvalState {
     }
   }
 
-  /// Create a Nix list value from a slice of values.
-  ///
-  /// # Errors
-  ///
-  /// Returns an error if value allocation or list construction fails.
-  pub fn make_list(&self, items: &[&Value<'_>]) -> Result<Value<'_>> {
-    // SAFETY: context and state are valid
-    let builder = unsafe {
-      sys::nix_make_list_builder(
-        self.context.as_ptr(),
-        self.inner.as_ptr(),
-        items.len(),
-      )
-    };
-    if builder.is_null() {
-      return Err(Error::NullPointer);
+  /// Create the values of `builtins` changes and apply them.
+  #[cfg(feature = "shim")]
+  fn set_builtins(
+    &self,
+    builtins: Vec<(String, Option<Builtin>, bool)>,
+  ) -> Result<()> {
+    if builtins.is_empty() {
+      return Ok(());
     }
-
-    struct ListBuilderGuard(*mut sys::ListBuilder);
-    impl Drop for ListBuilderGuard {
-      fn drop(&mut self) {
-        unsafe { sys::nix_list_builder_free(self.0) };
-      }
+    let mut names = Vec::with_capacity(builtins.len());
+    let mut values = Vec::with_capacity(builtins.len());
+    let mut top_level = Vec::with_capacity(builtins.len());
+    for (name, builtin, rebind) in builtins {
+      let value = match builtin {
+        None => None,
+        #[cfg(feature = "primop")]
+        Some(Builtin::PrimOp(primop)) => Some(primop.into_value(self)?),
+        Some(Builtin::Data(data)) => Some(self.from_data(&data)?),
+        Some(Builtin::Expr(expr)) => {
+          Some(self.eval_from_string(&expr, &format!("<builtins.{name}>"))?)
+        },
+      };
+      names.push(CString::new(name)?);
+      values.push(value);
+      top_level.push(rebind);
     }
-    let _guard = ListBuilderGuard(builder);
-
-    for (i, item) in items.iter().enumerate() {
-      // SAFETY: context, builder, and value are valid; index in bounds
-      unsafe {
-        check_err(
+    let mut name_ptrs: Vec<_> = names.iter().map(|n| n.as_ptr()).collect();
+    let mut value_ptrs: Vec<_> = values
+      .iter()
+      .map(|v| {
+        v.as_ref()
+          .map_or(std::ptr::null_mut(), |v| v.inner.as_ptr())
+      })
+      .collect();
+    // SAFETY: context and state are valid; the names and values outlive the
+    // call.
+    unsafe {
+      check_err(
+        self.context.as_ptr(),
+        sys::nix_eval_state_set_builtins(
           self.context.as_ptr(),
-          sys::nix_list_builder_insert(
-            self.context.as_ptr(),
-            builder,
-            i as std::os::raw::c_uint,
-            item.inner.as_ptr(),
-          ),
-        )?;
-      }
+          self.inner.as_ptr(),
+          name_ptrs.as_mut_ptr(),
+          value_ptrs.as_mut_ptr(),
+          top_level.as_ptr(),
+          names.len(),
+        ),
+      )
     }
+  }
 
-    let result = self.alloc_value()?;
-    // SAFETY: context, builder, and result value are valid
+  /// Allow access to `path` and everything below it under `restrict-eval`
+  /// or `pure-eval`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `path` is not valid UTF-8 or contains a NUL byte.
+  #[cfg(feature = "shim")]
+  pub fn allow_path_prefix(&self, path: impl AsRef<Path>) -> Result<()> {
+    let path_str = path
+      .as_ref()
+      .to_str()
+      .ok_or_else(|| Error::Unknown("Path is not valid UTF-8".to_string()))?;
+    let path_c = CString::new(path_str)?;
+    // SAFETY: context, state, and path are valid.
     unsafe {
       check_err(
         self.context.as_ptr(),
-        sys::nix_make_list(
+        sys::nix_eval_state_allow_path_prefix(
           self.context.as_ptr(),
-          builder,
-          result.inner.as_ptr(),
+          self.inner.as_ptr(),
+          path_c.as_ptr(),
         ),
-      )?;
+      )
     }
+  }
 
-    Ok(result)
+  /// Create a Nix list value from a slice of values.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if value allocation or list construction fails.
+  pub fn make_list(&self, items: &[&Value<'_>]) -> Result<Value<'_>> {
+    let mut builder = self.list_builder(items.len())?;
+    builder.extend(items.iter().copied())?;
+    builder.finish()
+  }
+
+  /// Create a Nix list value from an iterator of values.
+  ///
+  /// When the iterator reports an exact length the values are streamed into
+  /// the list as they are produced, so each can be dropped right after it
+  /// is added. Otherwise they are collected first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the iterator yields an error, or if value
+  /// allocation or list construction fails.
+  pub fn make_list_from_iter<'s, I>(&'s self, items: I) -> Result<Value<'s>>
+  where
+    I: IntoIterator<Item = Result<Value<'s>>>,
+  {
+    let mut items = items.into_iter();
+    let (lower, upper) = items.size_hint();
+    if upper == Some(lower) {
+      let mut builder = self.list_builder(lower)?;
+      for item in items.by_ref().take(lower) {
+        builder.push(&item?)?;
+      }
+      if builder.len() == lower && items.next().is_none() {
+        return builder.finish();
+      }
+      return Err(Error::Unknown(
+        "iterator length differs from its size hint".into(),
+      ));
+    }
+    let items = items.collect::<Result<Vec<_>>>()?;
+    let mut builder = self.list_builder(items.len())?;
+    builder.extend(&items)?;
+    builder.finish()
+  }
+
+  /// Start building a list of exactly `len` elements.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the builder cannot be allocated.
+  pub fn list_builder(&self, len: usize) -> Result<ListBuilder<'_>> {
+    ListBuilder::with_capacity(self, len)
   }
 
   /// Create a Nix attribute set from key-value pairs.
   ///
   /// # Errors
   ///
-  /// Returns an error if value allocation or attribute set construction
-  /// fails.
+  /// Returns an error if a name appears twice, or if value allocation or
+  /// attribute set construction fails.
   pub fn make_attrs<'s>(
     &'s self,
     pairs: &[(&str, &Value<'_>)],
   ) -> Result<Value<'s>> {
-    // SAFETY: context and state are valid
-    let builder = unsafe {
-      sys::nix_make_bindings_builder(
-        self.context.as_ptr(),
-        self.inner.as_ptr(),
-        pairs.len(),
-      )
-    };
-    if builder.is_null() {
-      return Err(Error::NullPointer);
-    }
+    let mut builder = self.attrs_builder(pairs.len())?;
+    builder.extend(pairs.iter().copied())?;
+    builder.finish()
+  }
 
-    struct BindingsBuilderGuard(*mut sys::BindingsBuilder);
-    impl Drop for BindingsBuilderGuard {
-      fn drop(&mut self) {
-        unsafe { sys::nix_bindings_builder_free(self.0) };
+  /// Create a Nix attribute set from an iterator of name-value pairs.
+  ///
+  /// Pairs are streamed into the set as they are produced, so each value can
+  /// be dropped right after it is added. When the iterator's length is not
+  /// known exactly, the pairs are collected first to size the set.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the iterator yields an error, a name appears
+  /// twice, or value allocation or attribute set construction fails.
+  pub fn make_attrs_from_iter<'s, K, I>(&'s self, pairs: I) -> Result<Value<'s>>
+  where
+    K: AsRef<str>,
+    I: IntoIterator<Item = Result<(K, Value<'s>)>>,
+  {
+    let mut pairs = pairs.into_iter();
+    let (lower, upper) = pairs.size_hint();
+    if upper == Some(lower) {
+      let mut builder = self.attrs_builder(lower)?;
+      for pair in pairs.by_ref().take(lower) {
+        let (name, value) = pair?;
+        builder.insert(name.as_ref(), &value)?;
       }
-    }
-    let _guard = BindingsBuilderGuard(builder);
-
-    for (key, value) in pairs {
-      let key_c = CString::new(*key)?;
-      // SAFETY: context, builder, key, and value are valid
-      unsafe {
-        check_err(
-          self.context.as_ptr(),
-          sys::nix_bindings_builder_insert(
-            self.context.as_ptr(),
-            builder,
-            key_c.as_ptr(),
-            value.inner.as_ptr(),
-          ),
-        )?;
+      if builder.len() == lower && pairs.next().is_none() {
+        return builder.finish();
       }
+      return Err(Error::Unknown(
+        "iterator length differs from its size hint".into(),
+      ));
     }
+    let pairs = pairs.collect::<Result<Vec<_>>>()?;
+    let mut builder = self.attrs_builder(pairs.len())?;
+    builder.extend(pairs)?;
+    builder.finish()
+  }
 
-    let result = self.alloc_value()?;
-    // SAFETY: context, builder, and result value are valid
-    unsafe {
-      check_err(
-        self.context.as_ptr(),
-        sys::nix_make_attrs(
-          self.context.as_ptr(),
-          result.inner.as_ptr(),
-          builder,
-        ),
-      )?;
-    }
-
-    Ok(result)
+  /// Start building an attribute set of at most `capacity` attributes.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the builder cannot be allocated.
+  pub fn attrs_builder(&self, capacity: usize) -> Result<AttrsBuilder<'_>> {
+    AttrsBuilder::with_capacity(self, capacity)
   }
 
   /// Determine whether a value is a derivation and return its store path.
@@ -638,6 +1024,17 @@ impl EvalState {
   }
 }
 
+#[cfg(not(feature = "shim"))]
+impl EvalState {
+  /// Without the shim evaluation cannot be interrupted; runs `f`.
+  pub(crate) fn interruptible<T>(
+    &self,
+    f: impl FnOnce() -> Result<T>,
+  ) -> Result<T> {
+    f()
+  }
+}
+
 impl Drop for EvalState {
   fn drop(&mut self) {
     // SAFETY: We own the state and it's valid until drop
@@ -79,6 +79,123 @@ pub trait NixExternal: Send + Sync + 'static {
   fn equal(&self, _other: &Self) -> bool {
     false
   }
+
+  /// Write the value into a `builtins.toXML` document.
+  ///
+  /// The default writes `<unevaluated />`, as Nix does for externals that
+  /// do not print themselves. Elements left open are closed afterwards.
+  ///
+  /// # Errors
+  ///
+  /// An error is ignored; whatever was written up to it is kept.
+  #[cfg(feature = "shim")]
+  fn write_xml(&self, xml: &mut XmlWriter<'_>) -> Result<()> {
+    xml.empty_element("unevaluated", &[])
+  }
+}
+
+/// Writes elements into the XML document an external value is printed into.
+///
+/// Passed to [`NixExternal::write_xml`].
+#[cfg(feature = "shim")]
+pub struct XmlWriter<'a> {
+  ctx:      *mut sys::nix_c_context,
+  doc:      *mut std::os::raw::c_void,
+  open:     usize,
+  _phantom: std::marker::PhantomData<&'a mut ()>,
+}
+
+#[cfg(feature = "shim")]
+impl XmlWriter<'_> {
+  /// Open element `name` with `attrs`; what is written next goes inside it
+  /// until [`close_element`](Self::close_element).
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a name or value contains a NUL byte or the write
+  /// fails.
+  pub fn open_element(
+    &mut self,
+    name: &str,
+    attrs: &[(&str, &str)],
+  ) -> Result<()> {
+    self.element(name, attrs, sys::nix_xml_open_element)?;
+    self.open += 1;
+    Ok(())
+  }
+
+  /// Close the innermost element opened with
+  /// [`open_element`](Self::open_element).
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if no element is open or the write fails.
+  pub fn close_element(&mut self) -> Result<()> {
+    if self.open == 0 {
+      return Err(Error::Unknown("no XML element is open".into()));
+    }
+    // SAFETY: ctx and doc are valid for the callback.
+    unsafe {
+      crate::check_err(self.ctx, sys::nix_xml_close_element(self.ctx, self.doc))
+    }?;
+    self.open -= 1;
+    Ok(())
+  }
+
+  /// Write element `name` with `attrs` and no children.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if a name or value contains a NUL byte or the write
+  /// fails.
+  pub fn empty_element(
+    &mut self,
+    name: &str,
+    attrs: &[(&str, &str)],
+  ) -> Result<()> {
+    self.element(name, attrs, sys::nix_xml_write_empty_element)
+  }
+
+  fn element(
+    &mut self,
+    name: &str,
+    attrs: &[(&str, &str)],
+    write: unsafe extern "C" fn(
+      *mut sys::nix_c_context,
+      *mut std::os::raw::c_void,
+      *const std::os::raw::c_char,
+      *mut *const std::os::raw::c_char,
+      *mut *const std::os::raw::c_char,
+      usize,
+    ) -> sys::nix_err,
+  ) -> Result<()> {
+    let name = CString::new(name)?;
+    let names = attrs
+      .iter()
+      .map(|(name, _)| CString::new(*name))
+      .collect::<std::result::Result<Vec<_>, _>>()?;
+    let values = attrs
+      .iter()
+      .map(|(_, value)| CString::new(*value))
+      .collect::<std::result::Result<Vec<_>, _>>()?;
+    let mut name_ptrs: Vec<_> = names.iter().map(|n| n.as_ptr()).collect();
+    let mut value_ptrs: Vec<_> = values.iter().map(|v| v.as_ptr()).collect();
+    // SAFETY: ctx and doc are valid for the callback; the strings outlive
+    // the call.
+    unsafe {
+      crate::check_err(
+        self.ctx,
+        write(
+          self.ctx,
+          self.doc,
+          name.as_ptr(),
+          name_ptrs.as_mut_ptr(),
+          value_ptrs.as_mut_ptr(),
+          attrs.len(),
+        ),
+      )
+    }
+  }
 }
 
 /// Magic sentinel that prefixes every [`ErasedPayload`].
@@ -117,6 +234,11 @@ struct ErasedPayload {
   // equal(other_data): compare two ErasedPayload.data pointers of the same T.
   equal_fn:
     unsafe fn(*const std::os::raw::c_void, *const std::os::raw::c_void) -> bool,
+
+  // write_xml(writer): print into a toXML document.
+  #[cfg(feature = "shim")]
+  xml_fn:
+    unsafe fn(*const std::os::raw::c_void, &mut XmlWriter<'_>) -> Result<()>,
 }
 
 unsafe fn drop_erased<T>(ptr: *mut std::os::raw::c_void) {
@@ -153,20 +275,31 @@ unsafe fn equal_erased<T: NixExternal>(
   a.equal(b)
 }
 
+#[cfg(feature = "shim")]
+unsafe fn xml_erased<T: NixExternal>(
+  ptr: *const std::os::raw::c_void,
+  xml: &mut XmlWriter<'_>,
+) -> Result<()> {
+  let t = unsafe { &*(ptr as *const T) };
+  t.write_xml(xml)
+}
+
 impl ErasedPayload {
   fn new<T: NixExternal>(value: T) -> *mut Self {
     let data_box = Box::new(value);
     let data_ptr = Box::into_raw(data_box) as *mut std::os::raw::c_void;
 
     Box::into_raw(Box::new(ErasedPayload {
-      magic:        ERASED_PAYLOAD_MAGIC,
-      type_id:      TypeId::of::<T>(),
-      data:         data_ptr,
-      drop_fn:      drop_erased::<T>,
-      display_fn:   display_erased::<T>,
-      type_name_fn: type_name_erased::<T>,
-      coerce_fn:    coerce_erased::<T>,
-      equal_fn:     equal_erased::<T>,
+      magic:                           ERASED_PAYLOAD_MAGIC,
+      type_id:                         TypeId::of::<T>(),
+      data:                            data_ptr,
+      drop_fn:                         drop_erased::<T>,
+      display_fn:                      display_erased::<T>,
+      type_name_fn:                    type_name_erased::<T>,
+      coerce_fn:                       coerce_erased::<T>,
+      equal_fn:                        equal_erased::<T>,
+      #[cfg(feature = "shim")]
+      xml_fn:                          xml_erased::<T>,
     }))
   }
 
@@ -292,15 +425,49 @@ static VTABLE: sys::NixCExternalValueDesc = {
     result.unwrap_or(0)
   }
 
-  // JSON and XML printing default to not-implemented (None).
+  #[cfg(feature = "shim")]
+  unsafe extern "C" fn print_value_as_xml(
+    self_: *mut std::os::raw::c_void,
+    _state: *mut sys::EvalState,
+    _strict: std::os::raw::c_int,
+    _location: std::os::raw::c_int,
+    doc: *mut std::os::raw::c_void,
+    _c: *mut sys::nix_string_context,
+    _drvs_seen: *mut std::os::raw::c_void,
+    _pos: std::os::raw::c_int,
+  ) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+      let payload = unsafe { ErasedPayload::from_void(self_) };
+      // A context of our own: the writer's errors have nowhere else to go.
+      let ctx = unsafe { sys::nix_c_context_create() };
+      if ctx.is_null() {
+        return;
+      }
+      let mut xml = XmlWriter {
+        ctx,
+        doc,
+        open: 0,
+        _phantom: std::marker::PhantomData,
+      };
+      let _ = unsafe { (payload.xml_fn)(payload.data, &mut xml) };
+      while xml.open > 0 && xml.close_element().is_ok() {}
+      unsafe { sys::nix_c_context_free(ctx) };
+    }));
+  }
+
+  // JSON printing defaults to not-implemented (None); XML printing needs
+  // the shim to write elements.
   sys::NixCExternalValueDesc {
-    print:            Some(print),
-    showType:         Some(show_type),
-    typeOf:           Some(type_of),
-    coerceToString:   Some(coerce_to_string),
-    equal:            Some(equal),
-    printValueAsJSON: None,
-    printValueAsXML:  None,
+    print:                                         Some(print),
+    showType:                                      Some(show_type),
+    typeOf:                                        Some(type_of),
+    coerceToString:                                Some(coerce_to_string),
+    equal:                                         Some(equal),
+    printValueAsJSON:                              None,
+    #[cfg(feature = "shim")]
+    printValueAsXML:                               Some(print_value_as_xml),
+    #[cfg(not(feature = "shim"))]
+    printValueAsXML:                               None,
   }
 };
 
@@ -508,4 +675,41 @@ mod tests {
     // External type
     assert_ne!(int_val.value_type(), ValueType::External);
   }
+
+  #[cfg(feature = "shim")]
+  #[test]
+  #[serial]
+  fn test_external_to_xml() {
+    let (_ctx, _store, state) = make_eval_state();
+
+    struct Point(i64, i64);
+    impl NixExternal for Point {
+      fn display(&self) -> String {
+        "Point".to_string()
+      }
+
+      fn type_name(&self) -> &'static str {
+        "Point"
+      }
+
+      fn write_xml(&self, xml: &mut XmlWriter<'_>) -> Result<()> {
+        // Left open on purpose: the caller closes it.
+        xml.open_element("point", &[])?;
+        xml.empty_element("int", &[("value", &self.0.to_string())])?;
+        xml.empty_element("int", &[("value", &self.1.to_string())])
+      }
+    }
+
+    let point = state.make_external(Point(1, 2)).expect("make_external");
+    let xml = point.to_xml().expect("Failed to render");
+    assert!(
+      xml.contains(
+        "<point>\n    <int value=\"1\" />\n    <int value=\"2\" />\n  </point>"
+      ),
+      "{xml}"
+    );
+
+    let plain = state.make_external(MyData(1)).expect("make_external");
+    assert!(plain.to_xml().unwrap().contains("<unevaluated />"));
+  }
 }
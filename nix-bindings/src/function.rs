@@ -0,0 +1,263 @@
+//! Introspection of function values: lambdas, primops and partial
+//! applications of primops.
+
+#![cfg(feature = "shim")]
+
+use std::collections::BTreeMap;
+
+use crate::{
+  Error,
+  Result,
+  SourcePos,
+  Value,
+  ValueType,
+  error::checked_string_from_callback,
+  json::JsonTree,
+  sys,
+};
+
+/// What a function value is and what it accepts.
+///
+/// Returned by [`Value::function_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FunctionInfo {
+  /// A function written in Nix.
+  Lambda {
+    /// Name the lambda was bound to, e.g. `f` in `let f = x: x;`.
+    name:     Option<String>,
+    /// Formal arguments of a `{ a, b ? 1 }: ...` lambda, in the order Nix
+    /// keeps them (sorted by name), or `None` for a plain `x: ...` lambda.
+    formals:  Option<Vec<Formal>>,
+    /// Whether the formals end in `...`.
+    ellipsis: bool,
+    /// Name the whole argument is bound to: `x` in `x: ...` or `args` in
+    /// `{ a }@args: ...`.
+    arg:      Option<String>,
+    /// Where the lambda was defined.
+    pos:      Option<SourcePos>,
+  },
+  /// A builtin function.
+  PrimOp {
+    /// Name of the builtin, e.g. `map`.
+    name:  String,
+    /// Number of arguments it takes.
+    arity: usize,
+    /// Documentation, as shown by `:doc` in `nix repl`.
+    doc:   Option<String>,
+  },
+  /// A builtin applied to some, but not all, of its arguments.
+  PrimOpApp {
+    /// Name of the builtin.
+    name:      String,
+    /// Number of arguments the builtin takes in total.
+    arity:     usize,
+    /// Number of arguments still missing.
+    remaining: usize,
+  },
+}
+
+/// A formal argument of a lambda taking an attribute set.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Formal {
+  /// Attribute name.
+  pub name:        String,
+  /// Whether the formal has a default (`name ? default`).
+  pub has_default: bool,
+}
+
+impl FunctionInfo {
+  /// The formals and whether each has a default, as
+  /// `builtins.functionArgs` returns them.
+  ///
+  /// Empty for plain lambdas, builtins and partial applications.
+  #[must_use]
+  pub fn function_args(&self) -> BTreeMap<String, bool> {
+    match self {
+      FunctionInfo::Lambda {
+        formals: Some(formals),
+        ..
+      } => {
+        formals
+          .iter()
+          .map(|formal| (formal.name.clone(), formal.has_default))
+          .collect()
+      },
+      _ => BTreeMap::new(),
+    }
+  }
+
+  fn from_json(tree: &JsonTree) -> Result<Self> {
+    let malformed =
+      || Error::Unknown("malformed function info from Nix".into());
+    let string = |key| tree.get(key).as_str().map(str::to_owned);
+    let count = |key| {
+      tree
+        .get(key)
+        .as_int()
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(malformed)
+    };
+    match tree.get("kind").as_str() {
+      Some("lambda") => {
+        let formals = match tree.get("formals") {
+          JsonTree::Null => None,
+          formals => {
+            let formals = formals.as_list().ok_or_else(malformed)?;
+            Some(
+              formals
+                .iter()
+                .map(|formal| {
+                  Ok(Formal {
+                    name:        formal
+                      .get("name")
+                      .as_str()
+                      .ok_or_else(malformed)?
+                      .to_owned(),
+                    has_default: formal
+                      .get("default")
+                      .as_bool()
+                      .ok_or_else(malformed)?,
+                  })
+                })
+                .collect::<Result<_>>()?,
+            )
+          },
+        };
+        Ok(FunctionInfo::Lambda {
+          name: string("name"),
+          formals,
+          ellipsis: tree.get("ellipsis").as_bool().unwrap_or(false),
+          arg: string("arg"),
+          pos: SourcePos::from_json(tree.get("pos"))?,
+        })
+      },
+      Some("primop") => {
+        Ok(FunctionInfo::PrimOp {
+          name:  string("name").ok_or_else(malformed)?,
+          arity: count("arity")?,
+          doc:   string("doc"),
+        })
+      },
+      Some("primop-app") => {
+        Ok(FunctionInfo::PrimOpApp {
+          name:      string("name").ok_or_else(malformed)?,
+          arity:     count("arity")?,
+          remaining: count("remaining")?,
+        })
+      },
+      _ => Err(malformed()),
+    }
+  }
+}
+
+impl Value<'_> {
+  /// Describe this function: its formals for a lambda, or its name and
+  /// arity for a builtin.
+  ///
+  /// Forces the value. Unlike `builtins.functionArgs`, this tells lambdas
+  /// from builtins and keeps the `...` and `@` binding.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::InvalidType`] if the value is not a function, or an
+  /// error if forcing fails.
+  pub fn function_info(&self) -> Result<FunctionInfo> {
+    self.force_shared()?;
+    if self.value_type() != ValueType::Function {
+      return Err(Error::InvalidType {
+        expected: "function",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    // SAFETY: context, state and value are valid; the callback only runs
+    // during the call.
+    let json = unsafe {
+      let ctx = self.state.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_get_function_info(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          callback,
+          user_data,
+        )
+      })?
+    };
+    FunctionInfo::from_json(&JsonTree::parse(&json)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_lambda_info() {
+    let state = setup();
+    let value = state
+      .eval_from_string("let f = { b, a ? 1, ... }@args: a; in f", "<eval>")
+      .expect("Failed to evaluate");
+    let info = value.function_info().expect("Failed to get info");
+    let FunctionInfo::Lambda {
+      name,
+      formals,
+      ellipsis,
+      arg,
+      pos,
+    } = &info
+    else {
+      panic!("expected lambda, got {info:?}");
+    };
+    assert_eq!(name.as_deref(), Some("f"));
+    assert!(ellipsis);
+    assert_eq!(arg.as_deref(), Some("args"));
+    assert_eq!(formals.as_ref().map(Vec::len), Some(2));
+    assert_eq!(
+      info.function_args(),
+      BTreeMap::from([("a".into(), true), ("b".into(), false)])
+    );
+    let pos = pos.as_ref().expect("lambda should have a position");
+    assert_eq!((pos.line, pos.column), (1, 9));
+
+    let value = state.eval_from_string("x: x", "<eval>").unwrap();
+    let info = value.function_info().expect("Failed to get info");
+    assert!(matches!(
+      info,
+      FunctionInfo::Lambda { formals: None, ellipsis: false, ref arg, .. }
+        if arg.as_deref() == Some("x")
+    ));
+  }
+
+  #[test]
+  #[serial]
+  fn test_primop_info() {
+    let state = setup();
+    let eval = |expr| state.eval_from_string(expr, "<eval>").unwrap();
+
+    let info = eval("builtins.map").function_info().unwrap();
+    let FunctionInfo::PrimOp { name, arity, doc } = info else {
+      panic!("expected primop, got {info:?}");
+    };
+    assert_eq!((name.as_str(), arity), ("map", 2));
+    assert!(doc.is_some());
+
+    let info = eval("builtins.map (x: x)").function_info().unwrap();
+    assert_eq!(info, FunctionInfo::PrimOpApp {
+      name:      "map".into(),
+      arity:     2,
+      remaining: 1,
+    });
+
+    assert!(matches!(
+      eval("1").function_info(),
+      Err(Error::InvalidType {
+        expected: "function",
+        ..
+      })
+    ));
+  }
+}
@@ -0,0 +1,285 @@
+//! Interrupting evaluation from outside: cancellation and timeouts.
+//!
+//! Every [`EvalState`] has a [`CancelToken`]. Cancelling it from any thread
+//! aborts the evaluation running in that state with [`Error::Interrupted`],
+//! and [`with_timeout`](crate::EvalStateBuilder::with_timeout) on the
+//! builder bounds how long a single evaluation may run. The evaluator restores
+//! the thunks it was forcing, so the state can be used again afterwards.
+//!
+//! Interruption covers [`EvalState::eval_from_string`] and
+//! [`eval_from_file`](EvalState::eval_from_file),
+//! [`ParsedExpr::eval`](crate::ParsedExpr::eval), and forcing and calling
+//! values ([`Value::force`](crate::Value::force), the `as_*` accessors,
+//! [`Value::call`](crate::Value::call)).
+
+#![cfg(feature = "shim")]
+
+use std::{
+  cell::{Cell, RefCell},
+  os::raw::c_void,
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+  time::Instant,
+};
+
+use crate::{Error, EvalState, Result, sys};
+
+/// Cancels evaluation in an [`EvalState`] from another thread.
+///
+/// Cheap to clone; clones share the same flag. Obtain the token of a
+/// state with [`EvalState::cancel_token`], or share one between states
+/// with [`with_cancel_token`](crate::EvalStateBuilder::with_cancel_token).
+///
+/// A cancelled token stays cancelled, failing every later evaluation
+/// immediately, until [`reset`](Self::reset).
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  /// Create a token that is not cancelled.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Interrupt running and future evaluations.
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  /// Whether [`cancel`](Self::cancel) was called since the last
+  /// [`reset`](Self::reset).
+  #[must_use]
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+
+  /// Allow evaluation again.
+  pub fn reset(&self) {
+    self.0.store(false, Ordering::Relaxed);
+  }
+}
+
+/// What the installed interrupt check consults.
+struct Interrupt {
+  token:    CancelToken,
+  deadline: Option<Instant>,
+  /// Whether the check has told Nix to stop because of this interrupt.
+  fired:    Cell<bool>,
+}
+
+unsafe extern "C" fn check_interrupt(_user_data: *mut c_void) -> bool {
+  ACTIVE.with_borrow(|active| {
+    let mut stop = false;
+    for &interrupt in active {
+      // SAFETY: entries belong to `interruptible` calls still running on
+      // this thread, which remove them before their Interrupt is dropped.
+      let interrupt = unsafe { &*interrupt };
+      if interrupt.token.is_cancelled()
+        || interrupt.deadline.is_some_and(|d| Instant::now() >= d)
+      {
+        interrupt.fired.set(true);
+        stop = true;
+      }
+    }
+    stop
+  })
+}
+
+/// An `interruptible` call's entry in [`ACTIVE`]; removed on drop, even
+/// when evaluation panics. The outermost entry installs the interrupt check
+/// on this thread and removes it again.
+struct Installed<'a> {
+  state:      &'a EvalState,
+  _interrupt: std::marker::PhantomData<&'a Interrupt>,
+}
+
+impl<'a> Installed<'a> {
+  fn new(state: &'a EvalState, interrupt: &'a Interrupt) -> Self {
+    let outermost = ACTIVE.with_borrow_mut(|active| {
+      active.push(interrupt);
+      active.len() == 1
+    });
+    if outermost {
+      // SAFETY: the context is valid; the check only reads ACTIVE.
+      unsafe {
+        sys::nix_set_interrupt_check(
+          state.context.as_ptr(),
+          Some(check_interrupt),
+          std::ptr::null_mut(),
+        );
+      }
+    }
+    Installed {
+      state,
+      _interrupt: std::marker::PhantomData,
+    }
+  }
+}
+
+impl Drop for Installed<'_> {
+  fn drop(&mut self) {
+    let outermost = ACTIVE.with_borrow_mut(|active| {
+      active.pop();
+      active.is_empty()
+    });
+    if outermost {
+      // SAFETY: the context is valid
+      unsafe {
+        sys::nix_set_interrupt_check(
+          self.state.context.as_ptr(),
+          None,
+          std::ptr::null_mut(),
+        );
+      }
+    }
+  }
+}
+
+thread_local! {
+  /// The interrupts of the `interruptible` calls running on this thread,
+  /// outermost first. Calls nest when a primop calls back into the
+  /// evaluator, through the same state or another one.
+  static ACTIVE: RefCell<Vec<*const Interrupt>> =
+    const { RefCell::new(Vec::new()) };
+}
+
+impl EvalState {
+  /// The token that cancels evaluation in this state.
+  #[must_use]
+  pub fn cancel_token(&self) -> CancelToken {
+    self.cancel.clone()
+  }
+
+  /// Run `f` so that cancelling the token or exceeding the timeout
+  /// interrupts it with [`Error::Interrupted`].
+  ///
+  /// A nested call, e.g. from a primop, is interrupted by its own state's
+  /// token and timeout as well as those of the calls it runs under.
+  pub(crate) fn interruptible<T>(
+    &self,
+    f: impl FnOnce() -> Result<T>,
+  ) -> Result<T> {
+    if self.cancel.is_cancelled() {
+      return Err(Error::Interrupted);
+    }
+
+    let interrupt = Interrupt {
+      token:    self.cancel.clone(),
+      deadline: self.timeout.map(|timeout| Instant::now() + timeout),
+      fired:    Cell::new(false),
+    };
+    let installed = Installed::new(self, &interrupt);
+    let result = f();
+    drop(installed);
+
+    match result {
+      Err(_) if interrupt.fired.get() => Err(Error::Interrupted),
+      result => result,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{thread, time::Duration};
+
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::builder;
+
+  // Runs until interrupted, without growing the stack.
+  const RUNAWAY: &str = "builtins.length (builtins.genericClosure { startSet \
+                         = [ { key = 0; } ]; operator = x: [ { key = x.key + \
+                         1; } ]; })";
+
+  #[test]
+  #[serial]
+  fn test_timeout() {
+    let state = builder()
+      .with_timeout(Duration::from_millis(200))
+      .build()
+      .expect("Failed to build state");
+
+    let result = state.eval_from_string(RUNAWAY, "<eval>");
+    assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+
+    // The state is still usable, with a fresh deadline per evaluation.
+    let value = state.eval_from_string("1 + 2", "<eval>").unwrap();
+    assert_eq!(value.as_int().unwrap(), 3);
+  }
+
+  #[test]
+  #[serial]
+  fn test_cancel_token() {
+    let state = builder().build().expect("Failed to build state");
+    let runaway = state
+      .eval_from_string(&format!("_: {RUNAWAY}"), "<eval>")
+      .unwrap();
+    let arg = state.make_int(0).unwrap();
+
+    let token = state.cancel_token();
+    let canceller = thread::spawn({
+      let token = token.clone();
+      move || {
+        thread::sleep(Duration::from_millis(200));
+        token.cancel();
+      }
+    });
+    let result = runaway.call(&arg);
+    canceller.join().unwrap();
+    assert!(matches!(result, Err(Error::Interrupted)), "{result:?}");
+
+    // Cancelled until reset.
+    assert!(matches!(
+      state.eval_from_string("1", "<eval>"),
+      Err(Error::Interrupted)
+    ));
+    token.reset();
+    assert_eq!(
+      state
+        .eval_from_string("1", "<eval>")
+        .unwrap()
+        .as_int()
+        .unwrap(),
+      1
+    );
+  }
+
+  #[cfg(feature = "primop")]
+  #[test]
+  #[serial]
+  fn test_nested_timeout() {
+    // A state called from a primop of another state keeps its own timeout.
+    let inner = std::sync::Mutex::new(
+      builder()
+        .with_timeout(Duration::from_millis(200))
+        .build()
+        .expect("Failed to build state"),
+    );
+    let ctx =
+      Arc::new(crate::Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(crate::Store::open(&ctx, None).expect("Failed to open store"));
+    let nested =
+      crate::primop::PrimOp::new(&ctx, "nested", 1, None, move |_, ret| {
+        let state = inner.lock().expect("Failed to lock state");
+        let result = state.eval_from_string(RUNAWAY, "<eval>");
+        ret.set_bool(matches!(result, Err(Error::Interrupted)))
+      })
+      .expect("Failed to create primop");
+    let outer = crate::EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .with_builtin("nested", nested)
+      .build()
+      .expect("Failed to build state");
+
+    let value = outer
+      .eval_from_string("builtins.nested null", "<eval>")
+      .unwrap();
+    assert!(value.as_bool().unwrap());
+  }
+}
@@ -0,0 +1,1189 @@
+//! JSON export and import of Nix values, following `builtins.toJSON` and
+//! `builtins.fromJSON`.
+//!
+//! The export is byte-for-byte what `builtins.toJSON` produces: attribute
+//! sets are written with their keys sorted, sets with `__toString` or
+//! `outPath` (derivations among them) are coerced to strings, paths are
+//! copied to the store, and functions are rejected. Import builds values
+//! directly, without evaluating `builtins.fromJSON`.
+
+#![cfg(feature = "expr")]
+
+use std::{
+  collections::BTreeMap,
+  io::{Read, Write},
+};
+
+use crate::{Error, EvalState, Result, Value, ValueType};
+
+/// Options for [`Value::to_json_with`] and [`Value::write_json_with`].
+///
+/// Default: identical to `builtins.toJSON`.
+#[derive(Debug, Clone, Copy)]
+pub struct JsonOptions {
+  /// Copy path values to the store and write the resulting store path, as
+  /// `builtins.toJSON` does. When `false`, paths are written as plain
+  /// strings and the store is never written to.
+  pub copy_paths: bool,
+}
+
+impl Default for JsonOptions {
+  fn default() -> Self {
+    JsonOptions { copy_paths: true }
+  }
+}
+
+impl Value<'_> {
+  /// Render this value as JSON, exactly like `builtins.toJSON`.
+  ///
+  /// Forces the value deeply. Path values are copied to the store; use
+  /// [`to_json_with`](Self::to_json_with) to avoid that.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, or the value contains a function
+  /// or anything else `builtins.toJSON` rejects. Attribute sets and lists
+  /// nested more than [`MAX_JSON_DEPTH`] deep, or containing themselves, are
+  /// rejected too. The error carries the attribute path to the offending
+  /// value.
+  pub fn to_json(&self) -> Result<String> {
+    self.to_json_with(JsonOptions::default())
+  }
+
+  /// Render this value as JSON with the given options.
+  ///
+  /// # Errors
+  ///
+  /// See [`to_json`](Self::to_json).
+  pub fn to_json_with(&self, options: JsonOptions) -> Result<String> {
+    let mut out = Vec::new();
+    self.write_json_with(&mut out, options)?;
+    // The writer only emits valid UTF-8.
+    String::from_utf8(out)
+      .map_err(|_| Error::Unknown("Invalid UTF-8 in JSON output".into()))
+  }
+
+  /// Stream this value to `out` as JSON, exactly like `builtins.toJSON`.
+  ///
+  /// Values are forced as they are written, so a failure part-way leaves
+  /// a truncated document in `out`.
+  ///
+  /// # Errors
+  ///
+  /// See [`to_json`](Self::to_json). Write failures surface as
+  /// [`Error::Io`].
+  pub fn write_json(&self, out: &mut impl Write) -> Result<()> {
+    self.write_json_with(out, JsonOptions::default())
+  }
+
+  /// Stream this value to `out` as JSON with the given options.
+  ///
+  /// # Errors
+  ///
+  /// See [`write_json`](Self::write_json).
+  pub fn write_json_with(
+    &self,
+    out: &mut impl Write,
+    options: JsonOptions,
+  ) -> Result<()> {
+    JsonWriter {
+      out,
+      options,
+      helpers: Helpers::new(self.state),
+      ancestors: Vec::new(),
+    }
+    .write_value(self)
+  }
+}
+
+/// Nix functions evaluated on first use.
+///
+/// Path copying and external values are delegated back to the evaluator so
+/// they behave exactly as they do under `builtins.toJSON`.
+struct Helpers<'a> {
+  state:           &'a EvalState,
+  /// `p: "${p}"`: interpolating a path copies it to the store.
+  copy_to_store:   Option<Value<'a>>,
+  /// `builtins.toJSON`, for external values with their own rendering.
+  builtin_to_json: Option<Value<'a>>,
+}
+
+impl<'a> Helpers<'a> {
+  fn new(state: &'a EvalState) -> Self {
+    Helpers {
+      state,
+      copy_to_store: None,
+      builtin_to_json: None,
+    }
+  }
+
+  fn get<'h>(
+    slot: &'h mut Option<Value<'a>>,
+    state: &'a EvalState,
+    expr: &str,
+  ) -> Result<&'h Value<'a>> {
+    let value = match slot.take() {
+      Some(value) => value,
+      None => state.eval_from_string(expr, "«json»")?,
+    };
+    Ok(slot.insert(value))
+  }
+
+  fn copy_to_store(&mut self) -> Result<&Value<'a>> {
+    Self::get(&mut self.copy_to_store, self.state, "p: \"${p}\"")
+  }
+
+  fn builtin_to_json(&mut self) -> Result<&Value<'a>> {
+    Self::get(&mut self.builtin_to_json, self.state, "builtins.toJSON")
+  }
+}
+
+struct JsonWriter<'w, 'a, W> {
+  out:       &'w mut W,
+  options:   JsonOptions,
+  helpers:   Helpers<'a>,
+  /// Identities of the attribute sets and lists being written.
+  ancestors: Vec<usize>,
+}
+
+impl<W: Write> JsonWriter<'_, '_, W> {
+  fn write_value(&mut self, value: &Value<'_>) -> Result<()> {
+    value.force_shared()?;
+    match value.value_type() {
+      ValueType::Int => write!(self.out, "{}", value.as_int()?)?,
+      ValueType::Float => write_float(self.out, value.as_float()?)?,
+      ValueType::Bool => write!(self.out, "{}", value.as_bool()?)?,
+      ValueType::Null => self.out.write_all(b"null")?,
+      ValueType::String => write_string(self.out, &value.as_string()?)?,
+      ValueType::Path => {
+        let path = self.path_string(value)?;
+        write_string(self.out, &path)?;
+      },
+      ValueType::Attrs | ValueType::List => self.write_container(value)?,
+      ValueType::External => {
+        // Externals may define their own JSON rendering, which only the
+        // evaluator can invoke.
+        let json = self.helpers.builtin_to_json()?.call(value)?.as_string()?;
+        self.out.write_all(json.as_bytes())?;
+      },
+      other @ (ValueType::Function | ValueType::Thunk) => {
+        return Err(conversion(format!(
+          "cannot convert {} to JSON",
+          show_type(other)
+        )));
+      },
+    }
+    Ok(())
+  }
+
+  /// Write an attribute set or list, failing on one that contains itself
+  /// or nests more than [`MAX_JSON_DEPTH`] deep instead of recursing
+  /// without bound.
+  fn write_container(&mut self, value: &Value<'_>) -> Result<()> {
+    let id = value.identity();
+    if id != 0 && self.ancestors.contains(&id) {
+      return Err(conversion(
+        "cannot convert a value that contains itself to JSON".into(),
+      ));
+    }
+    if self.ancestors.len() == MAX_JSON_DEPTH {
+      return Err(conversion(format!(
+        "nesting exceeds the maximum depth of {MAX_JSON_DEPTH}"
+      )));
+    }
+    self.ancestors.push(id);
+    let result = if value.value_type() == ValueType::Attrs {
+      self.write_attrs(value)
+    } else {
+      self.write_list(value)
+    };
+    self.ancestors.pop();
+    result
+  }
+
+  fn write_list(&mut self, value: &Value<'_>) -> Result<()> {
+    self.out.write_all(b"[")?;
+    for (i, item) in value.list_iter()?.enumerate() {
+      if i > 0 {
+        self.out.write_all(b",")?;
+      }
+      item
+        .and_then(|item| self.write_value(&item))
+        .map_err(|e| e.in_index(i))?;
+    }
+    self.out.write_all(b"]")?;
+    Ok(())
+  }
+
+  fn write_attrs(&mut self, value: &Value<'_>) -> Result<()> {
+    if let Some(s) = self.attrs_to_string(value)? {
+      return write_string(self.out, &s);
+    }
+    if value.has_attr("outPath")? {
+      return self.write_value(&value.get_attr("outPath")?);
+    }
+
+    let mut names = value.attr_keys()?;
+    names.sort_unstable();
+    self.out.write_all(b"{")?;
+    for (i, name) in names.iter().enumerate() {
+      if i > 0 {
+        self.out.write_all(b",")?;
+      }
+      write_string(self.out, name)?;
+      self.out.write_all(b":")?;
+      value
+        .get_attr(name)
+        .and_then(|attr| self.write_value(&attr))
+        .map_err(|e| e.in_attr(name))?;
+    }
+    self.out.write_all(b"}")?;
+    Ok(())
+  }
+
+  /// The string a path is written as: its store path when copying, the
+  /// plain path otherwise.
+  fn path_string(&mut self, value: &Value<'_>) -> Result<String> {
+    if self.options.copy_paths {
+      self.helpers.copy_to_store()?.call(value)?.as_string()
+    } else {
+      path_to_string(value)
+    }
+  }
+
+  /// Apply `__toString` if the set has one, like the evaluator's
+  /// `tryAttrsToString` (without copying paths to the store).
+  fn attrs_to_string(&mut self, value: &Value<'_>) -> Result<Option<String>> {
+    if !value.has_attr("__toString")? {
+      return Ok(None);
+    }
+    let to_string = value.get_attr("__toString")?;
+    let result = to_string.call(value)?;
+    self.coerce_to_string(&result).map(Some)
+  }
+
+  /// Coerce a `__toString` result to a string, like the evaluator's
+  /// `coerceToString` with `coerceMore` and `copyToStore` both off.
+  fn coerce_to_string(&mut self, value: &Value<'_>) -> Result<String> {
+    value.force_shared()?;
+    match value.value_type() {
+      ValueType::String => value.as_string(),
+      ValueType::Path => path_to_string(value),
+      ValueType::Attrs => {
+        if let Some(s) = self.attrs_to_string(value)? {
+          return Ok(s);
+        }
+        if !value.has_attr("outPath")? {
+          return Err(conversion("cannot coerce a set to a string".into()));
+        }
+        self.coerce_to_string(&value.get_attr("outPath")?)
+      },
+      other => {
+        Err(conversion(format!(
+          "cannot coerce {} to a string",
+          show_type(other)
+        )))
+      },
+    }
+  }
+}
+
+fn conversion(message: String) -> Error {
+  Error::Conversion {
+    path: String::new(),
+    message,
+  }
+}
+
+/// Describe a value type the way the evaluator's `showType` does.
+fn show_type(value_type: ValueType) -> &'static str {
+  match value_type {
+    ValueType::Int => "an integer",
+    ValueType::Float => "a float",
+    ValueType::Bool => "a Boolean",
+    ValueType::String => "a string",
+    ValueType::Path => "a path",
+    ValueType::Null => "null",
+    ValueType::Attrs => "a set",
+    ValueType::List => "a list",
+    ValueType::Function => "a function",
+    ValueType::External => "an external value",
+    ValueType::Thunk => "a thunk",
+  }
+}
+
+fn path_to_string(value: &Value<'_>) -> Result<String> {
+  value
+    .as_path()?
+    .into_os_string()
+    .into_string()
+    .map_err(|_| Error::Unknown("Invalid UTF-8 in path".into()))
+}
+
+/// Write `s` as a JSON string literal, escaping like nlohmann::json (which
+/// `builtins.toJSON` uses): only `"`, `\` and control characters.
+fn write_string(out: &mut impl Write, s: &str) -> Result<()> {
+  out.write_all(b"\"")?;
+  let mut start = 0;
+  for (i, b) in s.bytes().enumerate() {
+    let escaped: &[u8] = match b {
+      b'"' => b"\\\"",
+      b'\\' => b"\\\\",
+      b'\x08' => b"\\b",
+      b'\x0c' => b"\\f",
+      b'\n' => b"\\n",
+      b'\r' => b"\\r",
+      b'\t' => b"\\t",
+      0x00..=0x1F => &[],
+      _ => continue,
+    };
+    out.write_all(&s.as_bytes()[start..i])?;
+    if escaped.is_empty() {
+      write!(out, "\\u{b:04x}")?;
+    } else {
+      out.write_all(escaped)?;
+    }
+    start = i + 1;
+  }
+  out.write_all(&s.as_bytes()[start..])?;
+  out.write_all(b"\"")?;
+  Ok(())
+}
+
+/// Write `f` the way nlohmann::json does: the shortest round-tripping
+/// digits, in plain notation for decimal exponents in `-4..=15` and
+/// scientific notation (`1e+20`) outside it, always marked as a float
+/// (`1.0`). Non-finite values become `null`.
+fn write_float(out: &mut impl Write, f: f64) -> Result<()> {
+  if !f.is_finite() {
+    out.write_all(b"null")?;
+    return Ok(());
+  }
+  if f.is_sign_negative() {
+    out.write_all(b"-")?;
+  }
+  if f == 0.0 {
+    out.write_all(b"0.0")?;
+    return Ok(());
+  }
+
+  // `{:e}` yields the shortest round-tripping digits as `d.ddde±x`.
+  let sci = format!("{:e}", f.abs());
+  let (mantissa, exp) = sci.split_once('e').expect("`{:e}` has an exponent");
+  let digits = mantissa.replace('.', "");
+  let k = digits.len() as i32;
+  // The value is 0.DIGITS * 10^n.
+  let n = exp.parse::<i32>().expect("`{:e}` exponent is an integer") + 1;
+
+  if k <= n && n <= 15 {
+    write!(out, "{digits}{}.0", "0".repeat((n - k) as usize))?;
+  } else if 0 < n && n <= 15 {
+    let (int, frac) = digits.split_at(n as usize);
+    write!(out, "{int}.{frac}")?;
+  } else if -4 < n && n <= 0 {
+    write!(out, "0.{}{digits}", "0".repeat(-n as usize))?;
+  } else {
+    let (first, rest) = digits.split_at(1);
+    if rest.is_empty() {
+      write!(out, "{first}")?;
+    } else {
+      write!(out, "{first}.{rest}")?;
+    }
+    let e = n - 1;
+    let sign = if e < 0 { '-' } else { '+' };
+    write!(out, "e{sign}{:02}", e.abs())?;
+  }
+  Ok(())
+}
+
+impl EvalState {
+  /// Build a Nix value from a JSON document, like `builtins.fromJSON`.
+  ///
+  /// The value is constructed directly through the attribute set and list
+  /// builders; the evaluator is not involved. Numbers follow
+  /// `builtins.fromJSON`: integers that fit in 64 bits become Nix integers,
+  /// anything with a fraction or exponent (or too large for an integer)
+  /// becomes a float. When an object repeats a key, the last one wins.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use std::sync::Arc;
+  /// # use nix_bindings::{Context, EvalStateBuilder, Store};
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// # let ctx = Arc::new(Context::new()?);
+  /// # let store = Arc::new(Store::open(&ctx, None)?);
+  /// # let state = EvalStateBuilder::new(&store)?.build()?;
+  /// let value = state.value_from_json(r#"{ "port": 8080, "tags": ["a"] }"#)?;
+  /// assert_eq!(value.get_attr("port")?.as_int()?, 8080);
+  /// # Ok(())
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::Json`] with the byte offset of the problem if the
+  /// input is not valid JSON or nests arrays and objects more than
+  /// [`MAX_JSON_DEPTH`] deep, or an error if value construction fails.
+  pub fn value_from_json(&self, json: &str) -> Result<Value<'_>> {
+    parse_json(json, &mut ValueSink(self))
+  }
+
+  /// Build a Nix value from a JSON document read from `reader`.
+  ///
+  /// The whole document is read before any value is built.
+  ///
+  /// # Errors
+  ///
+  /// See [`value_from_json`](Self::value_from_json). Read failures surface
+  /// as [`Error::Io`], and input that is not UTF-8 as [`Error::Json`].
+  pub fn value_from_json_reader(
+    &self,
+    mut reader: impl Read,
+  ) -> Result<Value<'_>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let json = String::from_utf8(bytes).map_err(|e| {
+      Error::Json {
+        offset:  e.utf8_error().valid_up_to(),
+        message: "invalid UTF-8".into(),
+      }
+    })?;
+    self.value_from_json(&json)
+  }
+}
+
+/// Receives the values of a JSON document as it is parsed, innermost
+/// first.
+pub(crate) trait JsonSink {
+  type Value;
+
+  fn null(&mut self) -> Result<Self::Value>;
+  fn bool(&mut self, b: bool) -> Result<Self::Value>;
+  fn int(&mut self, i: i64) -> Result<Self::Value>;
+  fn float(&mut self, f: f64) -> Result<Self::Value>;
+  fn string(&mut self, s: String) -> Result<Self::Value>;
+  fn list(&mut self, items: Vec<Self::Value>) -> Result<Self::Value>;
+  /// Duplicate keys have already been resolved, last one wins.
+  fn object(
+    &mut self,
+    entries: BTreeMap<String, Self::Value>,
+  ) -> Result<Self::Value>;
+}
+
+/// Builds Nix values in an [`EvalState`].
+struct ValueSink<'s>(&'s EvalState);
+
+impl<'s> JsonSink for ValueSink<'s> {
+  type Value = Value<'s>;
+
+  fn null(&mut self) -> Result<Value<'s>> {
+    self.0.make_null()
+  }
+
+  fn bool(&mut self, b: bool) -> Result<Value<'s>> {
+    self.0.make_bool(b)
+  }
+
+  fn int(&mut self, i: i64) -> Result<Value<'s>> {
+    self.0.make_int(i)
+  }
+
+  fn float(&mut self, f: f64) -> Result<Value<'s>> {
+    self.0.make_float(f)
+  }
+
+  fn string(&mut self, s: String) -> Result<Value<'s>> {
+    self.0.make_string(&s)
+  }
+
+  fn list(&mut self, items: Vec<Value<'s>>) -> Result<Value<'s>> {
+    let mut builder = self.0.list_builder(items.len())?;
+    builder.extend(items)?;
+    builder.finish()
+  }
+
+  fn object(
+    &mut self,
+    entries: BTreeMap<String, Value<'s>>,
+  ) -> Result<Value<'s>> {
+    let mut builder = self.0.attrs_builder(entries.len())?;
+    builder.extend(entries)?;
+    builder.finish()
+  }
+}
+
+/// A parsed JSON document, used to decode the JSON replies of the shims.
+#[cfg(feature = "shim")]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum JsonTree {
+  Null,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  String(String),
+  List(Vec<JsonTree>),
+  Object(BTreeMap<String, JsonTree>),
+}
+
+#[cfg(feature = "shim")]
+impl JsonTree {
+  pub(crate) fn parse(input: &str) -> Result<Self> {
+    parse_json(input, &mut TreeSink)
+  }
+
+  /// Field `key` of an object, or [`JsonTree::Null`] if there is none.
+  pub(crate) fn get(&self, key: &str) -> &JsonTree {
+    match self {
+      JsonTree::Object(entries) => entries.get(key).unwrap_or(&JsonTree::Null),
+      _ => &JsonTree::Null,
+    }
+  }
+
+  pub(crate) fn as_bool(&self) -> Option<bool> {
+    match self {
+      JsonTree::Bool(b) => Some(*b),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn as_int(&self) -> Option<i64> {
+    match self {
+      JsonTree::Int(i) => Some(*i),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn as_str(&self) -> Option<&str> {
+    match self {
+      JsonTree::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn as_list(&self) -> Option<&[JsonTree]> {
+    match self {
+      JsonTree::List(items) => Some(items),
+      _ => None,
+    }
+  }
+
+  pub(crate) fn is_null(&self) -> bool {
+    matches!(self, JsonTree::Null)
+  }
+}
+
+#[cfg(feature = "shim")]
+struct TreeSink;
+
+#[cfg(feature = "shim")]
+impl JsonSink for TreeSink {
+  type Value = JsonTree;
+
+  fn null(&mut self) -> Result<JsonTree> {
+    Ok(JsonTree::Null)
+  }
+
+  fn bool(&mut self, b: bool) -> Result<JsonTree> {
+    Ok(JsonTree::Bool(b))
+  }
+
+  fn int(&mut self, i: i64) -> Result<JsonTree> {
+    Ok(JsonTree::Int(i))
+  }
+
+  fn float(&mut self, f: f64) -> Result<JsonTree> {
+    Ok(JsonTree::Float(f))
+  }
+
+  fn string(&mut self, s: String) -> Result<JsonTree> {
+    Ok(JsonTree::String(s))
+  }
+
+  fn list(&mut self, items: Vec<JsonTree>) -> Result<JsonTree> {
+    Ok(JsonTree::List(items))
+  }
+
+  fn object(
+    &mut self,
+    entries: BTreeMap<String, JsonTree>,
+  ) -> Result<JsonTree> {
+    Ok(JsonTree::Object(entries))
+  }
+}
+
+/// How deeply arrays and objects may nest in parsed JSON, and attribute
+/// sets and lists in exported values. Both directions recurse per level, so
+/// deeper input is rejected instead of overflowing the stack.
+pub const MAX_JSON_DEPTH: usize = 1024;
+
+/// Parse a complete JSON document into `sink`.
+///
+/// Accepts exactly what `builtins.fromJSON` accepts: strict RFC 8259 with
+/// no comments or trailing commas, surrounded by optional whitespace, and
+/// nesting at most [`MAX_JSON_DEPTH`] levels deep.
+pub(crate) fn parse_json<S: JsonSink>(
+  input: &str,
+  sink: &mut S,
+) -> Result<S::Value> {
+  let mut parser = JsonParser {
+    input: input.as_bytes(),
+    pos: 0,
+    depth: 0,
+    sink,
+  };
+  parser.skip_whitespace();
+  let value = parser.parse_value()?;
+  parser.skip_whitespace();
+  if parser.pos < parser.input.len() {
+    return Err(parser.error("unexpected trailing characters"));
+  }
+  Ok(value)
+}
+
+struct JsonParser<'i, 's, S> {
+  input: &'i [u8],
+  pos:   usize,
+  /// Arrays and objects currently open.
+  depth: usize,
+  sink:  &'s mut S,
+}
+
+impl<S: JsonSink> JsonParser<'_, '_, S> {
+  fn error(&self, message: impl Into<String>) -> Error {
+    self.error_at(self.pos, message)
+  }
+
+  fn error_at(&self, offset: usize, message: impl Into<String>) -> Error {
+    Error::Json {
+      offset,
+      message: message.into(),
+    }
+  }
+
+  fn peek(&self) -> Option<u8> {
+    self.input.get(self.pos).copied()
+  }
+
+  fn skip_whitespace(&mut self) {
+    while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+      self.pos += 1;
+    }
+  }
+
+  fn expect_byte(&mut self, byte: u8, what: &str) -> Result<()> {
+    if self.peek() == Some(byte) {
+      self.pos += 1;
+      Ok(())
+    } else {
+      Err(self.unexpected(what))
+    }
+  }
+
+  /// Error for an unexpected byte (or end of input) where `what` was
+  /// expected.
+  fn unexpected(&self, what: &str) -> Error {
+    match self.peek() {
+      None => self.error(format!("unexpected end of input, expected {what}")),
+      Some(_) => {
+        // Report the whole character, not a lone UTF-8 byte.
+        let rest = &self.input[self.pos..];
+        let c = std::str::from_utf8(&rest[..rest.len().min(4)])
+          .or_else(|e| std::str::from_utf8(&rest[..e.valid_up_to()]))
+          .ok()
+          .and_then(|s| s.chars().next())
+          .unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.error(format!("unexpected {c:?}, expected {what}"))
+      },
+    }
+  }
+
+  fn parse_value(&mut self) -> Result<S::Value> {
+    match self.peek() {
+      Some(open @ (b'{' | b'[')) => {
+        if self.depth == MAX_JSON_DEPTH {
+          return Err(self.error(format!(
+            "nesting exceeds the maximum depth of {MAX_JSON_DEPTH}"
+          )));
+        }
+        self.depth += 1;
+        let value = if open == b'{' {
+          self.parse_object()
+        } else {
+          self.parse_array()
+        };
+        self.depth -= 1;
+        value
+      },
+      Some(b'"') => {
+        let s = self.parse_string()?;
+        self.sink.string(s)
+      },
+      Some(b'-' | b'0'..=b'9') => self.parse_number(),
+      Some(b't') => {
+        self.parse_literal("true")?;
+        self.sink.bool(true)
+      },
+      Some(b'f') => {
+        self.parse_literal("false")?;
+        self.sink.bool(false)
+      },
+      Some(b'n') => {
+        self.parse_literal("null")?;
+        self.sink.null()
+      },
+      _ => Err(self.unexpected("a JSON value")),
+    }
+  }
+
+  fn parse_literal(&mut self, literal: &str) -> Result<()> {
+    if self.input[self.pos..].starts_with(literal.as_bytes()) {
+      self.pos += literal.len();
+      Ok(())
+    } else {
+      Err(self.error(format!("invalid literal, expected `{literal}`")))
+    }
+  }
+
+  fn parse_object(&mut self) -> Result<S::Value> {
+    self.pos += 1;
+    let mut entries = BTreeMap::new();
+    self.skip_whitespace();
+    if self.peek() == Some(b'}') {
+      self.pos += 1;
+      return self.sink.object(entries);
+    }
+    loop {
+      self.skip_whitespace();
+      if self.peek() != Some(b'"') {
+        return Err(self.unexpected("an object key"));
+      }
+      let key = self.parse_string()?;
+      self.skip_whitespace();
+      self.expect_byte(b':', "':'")?;
+      self.skip_whitespace();
+      let value = self.parse_value()?;
+      entries.insert(key, value);
+      self.skip_whitespace();
+      match self.peek() {
+        Some(b',') => self.pos += 1,
+        Some(b'}') => {
+          self.pos += 1;
+          return self.sink.object(entries);
+        },
+        _ => return Err(self.unexpected("',' or '}'")),
+      }
+    }
+  }
+
+  fn parse_array(&mut self) -> Result<S::Value> {
+    self.pos += 1;
+    let mut items = Vec::new();
+    self.skip_whitespace();
+    if self.peek() == Some(b']') {
+      self.pos += 1;
+      return self.sink.list(items);
+    }
+    loop {
+      self.skip_whitespace();
+      items.push(self.parse_value()?);
+      self.skip_whitespace();
+      match self.peek() {
+        Some(b',') => self.pos += 1,
+        Some(b']') => {
+          self.pos += 1;
+          return self.sink.list(items);
+        },
+        _ => return Err(self.unexpected("',' or ']'")),
+      }
+    }
+  }
+
+  fn skip_digits(&mut self) -> usize {
+    let start = self.pos;
+    while matches!(self.peek(), Some(b'0'..=b'9')) {
+      self.pos += 1;
+    }
+    self.pos - start
+  }
+
+  /// Numbers are classified like nlohmann::json, which backs
+  /// `builtins.fromJSON`: a plain integer is an int unless it overflows,
+  /// in which case it is read as a float. Non-negative integers above
+  /// `i64::MAX` that still fit in 64 bits are an error rather than a
+  /// float.
+  fn parse_number(&mut self) -> Result<S::Value> {
+    let start = self.pos;
+    if self.peek() == Some(b'-') {
+      self.pos += 1;
+    }
+    match self.peek() {
+      Some(b'0') => self.pos += 1,
+      Some(b'1'..=b'9') => {
+        self.skip_digits();
+      },
+      _ => return Err(self.unexpected("a digit")),
+    }
+    let mut is_float = false;
+    if self.peek() == Some(b'.') {
+      self.pos += 1;
+      if self.skip_digits() == 0 {
+        return Err(self.unexpected("a digit after '.'"));
+      }
+      is_float = true;
+    }
+    if matches!(self.peek(), Some(b'e' | b'E')) {
+      self.pos += 1;
+      if matches!(self.peek(), Some(b'+' | b'-')) {
+        self.pos += 1;
+      }
+      if self.skip_digits() == 0 {
+        return Err(self.unexpected("a digit in the exponent"));
+      }
+      is_float = true;
+    }
+
+    // The slice is ASCII digits and signs only.
+    let text = std::str::from_utf8(&self.input[start..self.pos])
+      .map_err(|_| self.error_at(start, "invalid number"))?;
+    if !is_float {
+      if text.starts_with('-') {
+        if let Ok(i) = text.parse::<i64>() {
+          return self.sink.int(i);
+        }
+      } else if let Ok(u) = text.parse::<u64>() {
+        return match i64::try_from(u) {
+          Ok(i) => self.sink.int(i),
+          Err(_) => {
+            Err(self.error_at(
+              start,
+              format!("unsigned json number {u} outside of Nix integer range"),
+            ))
+          },
+        };
+      }
+    }
+    let f = text
+      .parse::<f64>()
+      .map_err(|_| self.error_at(start, "invalid number"))?;
+    self.sink.float(f)
+  }
+
+  fn parse_string(&mut self) -> Result<String> {
+    self.pos += 1;
+    let mut out = Vec::new();
+    loop {
+      let run_start = self.pos;
+      while let Some(b) = self.peek() {
+        if b == b'"' || b == b'\\' || b < 0x20 {
+          break;
+        }
+        self.pos += 1;
+      }
+      out.extend_from_slice(&self.input[run_start..self.pos]);
+      match self.peek() {
+        Some(b'"') => {
+          self.pos += 1;
+          // Runs are split on ASCII bytes only and escapes push whole
+          // characters, so this is still the input's valid UTF-8.
+          return String::from_utf8(out)
+            .map_err(|_| self.error("invalid UTF-8 in string"));
+        },
+        Some(b'\\') => {
+          self.pos += 1;
+          let c = self.parse_escape()?;
+          let mut buf = [0; 4];
+          out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        },
+        Some(_) => {
+          return Err(self.error("unescaped control character in string"));
+        },
+        None => return Err(self.unexpected("'\"'")),
+      }
+    }
+  }
+
+  fn parse_escape(&mut self) -> Result<char> {
+    let start = self.pos - 1;
+    let c = match self.peek() {
+      Some(b'"') => '"',
+      Some(b'\\') => '\\',
+      Some(b'/') => '/',
+      Some(b'b') => '\u{8}',
+      Some(b'f') => '\u{c}',
+      Some(b'n') => '\n',
+      Some(b'r') => '\r',
+      Some(b't') => '\t',
+      Some(b'u') => {
+        self.pos += 1;
+        let unit = self.parse_hex4()?;
+        return match unit {
+          0xD800..=0xDBFF => {
+            if !self.input[self.pos..].starts_with(b"\\u") {
+              return Err(
+                self.error_at(start, "high surrogate without low surrogate"),
+              );
+            }
+            self.pos += 2;
+            let low = self.parse_hex4()?;
+            if !(0xDC00..=0xDFFF).contains(&low) {
+              return Err(
+                self.error_at(start, "high surrogate without low surrogate"),
+              );
+            }
+            let code = 0x10000
+              + ((u32::from(unit) - 0xD800) << 10)
+              + (u32::from(low) - 0xDC00);
+            // Always a valid scalar value after combining.
+            char::from_u32(code)
+              .ok_or_else(|| self.error_at(start, "invalid \\u escape"))
+          },
+          0xDC00..=0xDFFF => {
+            Err(self.error_at(start, "low surrogate without high surrogate"))
+          },
+          _ => {
+            char::from_u32(u32::from(unit))
+              .ok_or_else(|| self.error_at(start, "invalid \\u escape"))
+          },
+        };
+      },
+      _ => return Err(self.error_at(start, "invalid escape sequence")),
+    };
+    self.pos += 1;
+    Ok(c)
+  }
+
+  fn parse_hex4(&mut self) -> Result<u16> {
+    let digits = self
+      .input
+      .get(self.pos..self.pos + 4)
+      .and_then(|d| std::str::from_utf8(d).ok())
+      .filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit()))
+      .ok_or_else(|| self.error("expected four hex digits after \\u"))?;
+    let unit = u16::from_str_radix(digits, 16)
+      .map_err(|_| self.error("expected four hex digits after \\u"))?;
+    self.pos += 4;
+    Ok(unit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  /// Compare against the evaluator's own `builtins.toJSON`.
+  fn assert_matches_builtin(state: &EvalState, expr: &str) {
+    let value = state
+      .eval_from_string(expr, "<eval>")
+      .expect("Failed to evaluate");
+    let expected = state
+      .eval_from_string(&format!("builtins.toJSON ({expr})"), "<eval>")
+      .expect("Failed to evaluate toJSON")
+      .as_string()
+      .expect("Failed to read toJSON result");
+    assert_eq!(value.to_json().expect("Failed to render JSON"), expected);
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_json_matches_builtin() {
+    let state = setup();
+    for expr in [
+      "{ b = [ 1 2.5 true null ]; a = \"x\\ny\\u0001\"; \"\" = { }; }",
+      "[ 0.1 1.0 1.0e15 1.0e16 0.0001 0.00001 (-0.0) 123456.789 1.0e-300 ]",
+      "{ __toString = self: \"custom\"; }",
+      "{ outPath = \"/nix/store/foo\"; name = \"ignored\"; }",
+      "{ x = { __toString = self: { outPath = ./.; }; }; }",
+      "\"a\\\"b\\\\c\"",
+    ] {
+      assert_matches_builtin(&state, expr);
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_json_function_error() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{ a.b = [ (x: x) ]; }", "<eval>")
+      .expect("Failed to evaluate");
+    let err = value.to_json().expect_err("functions should be rejected");
+    assert_eq!(err.to_string(), "a.b[0]: cannot convert a function to JSON");
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_json_unbounded() {
+    let state = setup();
+    let deep = state
+      .eval_from_string("let f = n: { next = f (n + 1); }; in f 0", "<eval>")
+      .expect("Failed to evaluate");
+    let err = deep.to_json().expect_err("unbounded nesting should fail");
+    assert!(matches!(err, Error::Conversion { .. }), "{err}");
+
+    let cyclic = state
+      .eval_from_string("let x = { a = [ x ]; }; in x", "<eval>")
+      .expect("Failed to evaluate");
+    let err = cyclic.to_json().expect_err("cycles should fail");
+    #[cfg(feature = "shim")]
+    assert_eq!(
+      err.to_string(),
+      "a[0]: cannot convert a value that contains itself to JSON"
+    );
+    #[cfg(not(feature = "shim"))]
+    assert!(matches!(err, Error::Conversion { .. }), "{err}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_json_copies_paths() {
+    let state = setup();
+    let file = tempfile::NamedTempFile::new().expect("Failed to create file");
+    let expr = format!("{{ p = {}; }}", file.path().display());
+    assert_matches_builtin(&state, &expr);
+    let json = state
+      .eval_from_string(&expr, "<eval>")
+      .expect("Failed to evaluate")
+      .to_json()
+      .expect("Failed to render JSON");
+    assert!(json.starts_with("{\"p\":\"/nix/store/"), "{json}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_json_without_copying_paths() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{ p = /tmp/does-not-exist; }", "<eval>")
+      .expect("Failed to evaluate");
+    let json = value
+      .to_json_with(JsonOptions { copy_paths: false })
+      .expect("Failed to render JSON");
+    assert_eq!(json, "{\"p\":\"/tmp/does-not-exist\"}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_value_from_json_matches_builtin() {
+    let state = setup();
+    for json in [
+      r#"{"b":[1,2.5,true,null],"a":"x\ny\u0001\ud83d\ude00","":{}}"#,
+      "[0, -0, 1e2, 1.0, -9223372036854775808, 9223372036854775807]",
+      "[-9223372036854775809, 18446744073709551616]",
+      r#" { "k": 1, "k": 2 } "#,
+      r#""caf\u00e9 / \/ \"q\"""#,
+    ] {
+      let direct = state
+        .value_from_json(json)
+        .expect("Failed to parse JSON")
+        .to_json()
+        .expect("Failed to render JSON");
+      let builtin = state
+        .eval_from_string(
+          &format!("builtins.fromJSON {}", nix_string_literal(json)),
+          "<eval>",
+        )
+        .expect("Failed to evaluate fromJSON")
+        .to_json()
+        .expect("Failed to render JSON");
+      assert_eq!(direct, builtin, "{json}");
+    }
+
+    let value = state
+      .value_from_json("[1, 1.0]")
+      .expect("Failed to parse JSON");
+    let first = value.list_get(0).expect("Failed to get element");
+    let second = value.list_get(1).expect("Failed to get element");
+    assert_eq!(first.value_type(), ValueType::Int);
+    assert_eq!(second.value_type(), ValueType::Float);
+  }
+
+  /// Quote `s` as a Nix string literal.
+  fn nix_string_literal(s: &str) -> String {
+    format!(
+      "\"{}\"",
+      s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+    )
+  }
+
+  #[test]
+  #[serial]
+  fn test_value_from_json_errors() {
+    let state = setup();
+    for (json, offset) in [
+      ("", 0),
+      ("[1, 2,]", 6),
+      ("{\"a\" 1}", 5),
+      ("[01]", 2),
+      ("\"a\nb\"", 2),
+      ("\"\\ud800\"", 1),
+      ("true false", 5),
+      ("9223372036854775808", 0),
+    ] {
+      match state.value_from_json(json) {
+        Err(Error::Json { offset: got, .. }) => {
+          assert_eq!(got, offset, "{json:?}");
+        },
+        other => panic!("expected JSON error for {json:?}, got {other:?}"),
+      }
+    }
+
+    let err = state
+      .value_from_json_reader(&b"[\"\xff\"]"[..])
+      .expect_err("invalid UTF-8 should be rejected");
+    assert!(matches!(err, Error::Json { offset: 2, .. }), "{err}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_value_from_json_depth_limit() {
+    let state = setup();
+    let nested =
+      |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+    let value = state
+      .value_from_json(&nested(MAX_JSON_DEPTH))
+      .expect("Failed to parse the maximum depth");
+    assert_eq!(value.list_len().unwrap(), 1);
+
+    let err = state
+      .value_from_json(&nested(MAX_JSON_DEPTH + 1))
+      .expect_err("one level too deep");
+    assert!(
+      matches!(err, Error::Json { offset, .. } if offset == MAX_JSON_DEPTH),
+      "{err}"
+    );
+
+    // Pathological input fails cleanly instead of overflowing the stack.
+    let err = state
+      .value_from_json(&"[{\"a\":".repeat(100_000))
+      .expect_err("pathological nesting");
+    assert!(matches!(err, Error::Json { .. }), "{err}");
+  }
+
+  #[test]
+  fn test_write_float() {
+    let cases = [
+      (1.0, "1.0"),
+      (0.1, "0.1"),
+      (-2.5, "-2.5"),
+      (-0.0, "-0.0"),
+      (1e15, "1e+15"),
+      (123_456_789_012_345.0, "123456789012345.0"),
+      (0.0001, "0.0001"),
+      (0.00001, "1e-05"),
+      (1.5e300, "1.5e+300"),
+      (f64::NAN, "null"),
+    ];
+    for (f, expected) in cases {
+      let mut out = Vec::new();
+      write_float(&mut out, f).unwrap();
+      assert_eq!(String::from_utf8(out).unwrap(), expected, "{f}");
+    }
+  }
+}
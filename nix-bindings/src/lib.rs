@@ -147,21 +147,91 @@ pub use context::{Context, Verbosity, is_pure_eval, nix_version};
 #[cfg(feature = "store")]
 pub use store::{Derivation, Store, StorePath};
 
+#[cfg(feature = "shim")] mod analysis;
+#[cfg(feature = "shim")] mod ast;
+#[cfg(feature = "expr")] mod attr_path;
 #[cfg(feature = "expr")] mod attrs;
+#[cfg(feature = "shim")] mod compare;
+#[cfg(feature = "expr")] mod convert;
+#[cfg(feature = "shim")] mod data;
+#[cfg(feature = "shim")] mod drv_info;
 #[cfg(feature = "expr")] mod eval;
+#[cfg(feature = "shim")] mod function;
+#[cfg(feature = "shim")] mod interrupt;
+#[cfg(feature = "expr")] mod json;
 #[cfg(feature = "expr")] mod lists;
+#[cfg(feature = "shim")] mod parse;
+#[cfg(feature = "expr")] mod pos;
+#[cfg(feature = "expr")] mod print;
+#[cfg(feature = "shim")] mod string_context;
 #[cfg(feature = "expr")] mod value;
 #[cfg(feature = "expr")] mod value_ops;
-
+#[cfg(feature = "expr")] mod walk;
+#[cfg(feature = "shim")] mod xml;
+
+#[cfg(feature = "shim")]
+pub use analysis::{Diagnostic, DiagnosticKind};
+#[cfg(feature = "shim")]
+pub use ast::{
+  Ast,
+  AstFormal,
+  AstKind,
+  AttrName,
+  AttrSet,
+  BinaryOp,
+  Binding,
+  BindingValue,
+};
+#[cfg(feature = "expr")]
+pub use attr_path::{AttrPath, AttrPathSegment};
+#[cfg(feature = "shim")] pub use attrs::AttrPosIterator;
+#[cfg(feature = "expr")]
+pub use attrs::{AttrIterator, AttrsBuilder};
+#[cfg(feature = "expr")]
+#[doc(hidden)]
+pub use convert::__private;
+#[cfg(feature = "expr")]
+pub use convert::{FromNix, IntoNix, NixAlloc};
+#[cfg(feature = "shim")]
+pub use data::{MAX_DATA_DEPTH, NixData, OpaqueKind};
+#[cfg(feature = "shim")]
+pub use drv_info::{DrvInfo, Package, Packages};
+#[cfg(feature = "shim")] pub use eval::Builtin;
 #[cfg(feature = "expr")]
 pub use eval::{EvalState, EvalStateBuilder};
-#[cfg(feature = "expr")] pub use value::{Value, ValueType};
+#[cfg(feature = "shim")]
+pub use function::{Formal, FunctionInfo};
+#[cfg(feature = "shim")] pub use interrupt::CancelToken;
+#[cfg(feature = "expr")]
+pub use json::{JsonOptions, MAX_JSON_DEPTH};
+#[cfg(feature = "expr")] pub use lists::ListBuilder;
+#[cfg(feature = "derive")]
+pub use nix_bindings_derive::{FromNix, IntoNix};
+#[cfg(feature = "shim")] pub use parse::ParsedExpr;
+#[cfg(feature = "expr")] pub use pos::SourcePos;
+#[cfg(feature = "expr")] pub use print::NixPrinter;
+#[cfg(feature = "shim")]
+pub use string_context::{ContextElem, StringContext};
+#[cfg(feature = "expr")]
+pub use value::{StringReadMode, Value, ValueType};
 #[cfg(feature = "expr")] pub use value_ops::NixValueOps;
+#[cfg(feature = "expr")]
+pub use walk::{MAX_WALK_DEPTH, ValueVisitor, WalkControl, WalkOptions};
+#[cfg(feature = "shim")] pub use xml::XmlOptions;
+
+#[cfg(feature = "serde")] mod de;
+#[cfg(feature = "serde")]
+pub use de::{Deserializer, from_value};
+#[cfg(feature = "serde")] mod ser;
+#[cfg(feature = "serde")] pub use ser::Serializer;
 
 #[cfg(feature = "external")] pub mod external;
 #[cfg(feature = "flake")] pub mod flake;
 #[cfg(feature = "primop")] pub mod primop;
 
+#[cfg(all(test, feature = "expr"))]
+pub(crate) mod test_util;
+
 #[cfg(all(test, any(feature = "store", feature = "expr")))]
 mod tests {
   #[cfg(feature = "expr")] use std::sync::Arc;
@@ -385,6 +455,148 @@ mod tests {
     );
   }
 
+  #[cfg(feature = "shim")]
+  #[test]
+  #[serial]
+  fn test_eval_state_builder_settings() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let eval = |state: &EvalState, expr: &str| {
+      state
+        .eval_from_string(expr, "<eval>")
+        .and_then(|v| v.as_string())
+    };
+
+    // Two evaluators in one process, under different policies.
+    let pure = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .isolated()
+      .pure_eval(true)
+      .build()
+      .expect("Failed to build state");
+    let impure = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .isolated()
+      .eval_system("riscv64-linux")
+      .max_call_depth(10)
+      .build()
+      .expect("Failed to build state");
+    assert!(eval(&pure, "builtins.currentSystem").is_err());
+    assert_eq!(
+      eval(&impure, "builtins.currentSystem").unwrap(),
+      "riscv64-linux"
+    );
+    assert_eq!(
+      eval(&impure, "toString (builtins.length builtins.nixPath)").unwrap(),
+      "0"
+    );
+    assert!(
+      impure
+        .eval_from_string(
+          "let f = n: if n == 0 then 0 else f (n - 1); in f 100",
+          "<eval>",
+        )
+        .is_err()
+    );
+
+    let allowed = tempfile::tempdir().expect("Failed to create tempdir");
+    let denied = tempfile::tempdir().expect("Failed to create tempdir");
+    for dir in [&allowed, &denied] {
+      std::fs::write(dir.path().join("a.txt"), "hi").unwrap();
+    }
+    let restricted = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .isolated()
+      .restrict_eval(true)
+      .allowed_paths([allowed.path()])
+      .build()
+      .expect("Failed to build state");
+    let read = |dir: &tempfile::TempDir| {
+      eval(
+        &restricted,
+        &format!("builtins.readFile {}/a.txt", dir.path().display()),
+      )
+    };
+    assert_eq!(read(&allowed).unwrap(), "hi");
+    assert!(read(&denied).is_err());
+
+    assert!(
+      EvalStateBuilder::new(&store)
+        .expect("Failed to create builder")
+        .allowed_uris(["https://example.org/a b"])
+        .build()
+        .is_err()
+    );
+  }
+
+  #[cfg(feature = "expr")]
+  #[test]
+  #[serial]
+  fn test_string_bytes() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let state = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    let bytes = b"\x89PNG\r\n\x1a\n";
+    let val = state
+      .make_string_bytes(bytes)
+      .expect("Failed to make string");
+    assert_eq!(val.as_bytes().expect("Failed to read bytes"), bytes);
+    assert!(val.as_string().is_err());
+    #[cfg(unix)]
+    {
+      use std::os::unix::ffi::OsStrExt;
+      assert_eq!(val.as_os_string().unwrap().as_bytes(), bytes);
+    }
+    assert!(state.make_string_bytes(b"a\0b").is_err());
+  }
+
+  #[cfg(feature = "expr")]
+  #[test]
+  #[serial]
+  fn test_as_string_read_modes() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let state = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    let plain = state.make_string("hello").unwrap();
+    for mode in [StringReadMode::Realise, StringReadMode::NoRealise] {
+      assert_eq!(plain.as_string_with(mode).unwrap(), "hello");
+    }
+
+    // Realising this would try to build for a system that does not exist.
+    let out_path = state
+      .eval_from_string(
+        "(derivation { name = \"d\"; system = \"x\"; builder = \"b\"; \
+         }).outPath",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+    let path = out_path.as_string().expect("Failed to read outPath");
+    assert!(path.ends_with("-d"), "{path}");
+    assert_eq!(
+      NixValueOps::as_string(&out_path).expect("Failed to read outPath"),
+      path
+    );
+    let err = out_path
+      .as_string_with(StringReadMode::RejectContext)
+      .expect_err("outPath has context");
+    assert!(err.to_string().contains("has context"), "{err}");
+    assert_eq!(
+      plain.as_string_with(StringReadMode::RejectContext).unwrap(),
+      "hello"
+    );
+  }
+
   #[cfg(feature = "expr")]
   #[test]
   #[serial]
@@ -1,8 +1,16 @@
-use std::ptr::NonNull;
-
-use crate::{Error, Result, Value, ValueType, sys};
-
-impl Value<'_> {
+use std::{borrow::Borrow, os::raw::c_uint, ptr::NonNull};
+
+use crate::{
+  Error,
+  EvalState,
+  Result,
+  Value,
+  ValueType,
+  error::{check_err, check_ptr},
+  sys,
+};
+
+impl<'a> Value<'a> {
   /// Check if this value is a list.
   ///
   /// # Example
@@ -128,7 +136,7 @@ impl Value<'_> {
   /// # Errors
   ///
   /// Returns an error if this value is not a list.
-  pub fn list_iter(&self) -> Result<ListIterator<'_>> {
+  pub fn list_iter(&self) -> Result<ListIterator<'a>> {
     if !self.is_list() {
       return Err(Error::InvalidType {
         expected: "list",
@@ -138,11 +146,24 @@ impl Value<'_> {
 
     let len = self.list_len()?;
     Ok(ListIterator {
-      value:  self,
+      value:  self.clone(),
       index:  0,
       length: len,
+      force:  true,
     })
   }
+
+  /// Like [`list_iter`](Self::list_iter), but yields the elements without
+  /// forcing them.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if this value is not a list.
+  pub fn list_iter_lazy(&self) -> Result<ListIterator<'a>> {
+    let mut iter = self.list_iter()?;
+    iter.force = false;
+    Ok(iter)
+  }
 }
 
 /// Iterator over elements in a Nix list.
@@ -151,9 +172,10 @@ impl Value<'_> {
 /// over the elements of a Nix list.
 #[derive(Debug)]
 pub struct ListIterator<'a> {
-  value:  &'a Value<'a>,
+  value:  Value<'a>,
   index:  usize,
   length: usize,
+  force:  bool,
 }
 
 impl<'a> Iterator for ListIterator<'a> {
@@ -170,8 +192,14 @@ impl<'a> Iterator for ListIterator<'a> {
     // Bypass list_get's redundant type+length check; we already know the
     // value is a list and idx < length.
     // SAFETY: context, value, and state are valid; idx is bounds-checked.
+    // Both getters return a GC-reffed pointer that Value's Drop releases.
+    let getter = if self.force {
+      sys::nix_get_list_byidx
+    } else {
+      sys::nix_get_list_byidx_lazy
+    };
     let elem_ptr = unsafe {
-      sys::nix_get_list_byidx(
+      getter(
         self.value.state.context.as_ptr(),
         self.value.inner.as_ptr(),
         self.value.state.as_ptr(),
@@ -198,6 +226,138 @@ impl<'a> Iterator for ListIterator<'a> {
 
 impl ExactSizeIterator for ListIterator<'_> {}
 
+/// Incremental builder for a list.
+///
+/// Created with [`EvalState::list_builder`]. Each pushed value is held by
+/// the list under construction, so the caller's handle can be dropped right
+/// away. The length is fixed up front: [`finish`](Self::finish) fails
+/// unless exactly that many elements were pushed.
+pub struct ListBuilder<'s> {
+  state:    &'s EvalState,
+  inner:    NonNull<sys::ListBuilder>,
+  len:      usize,
+  capacity: usize,
+}
+
+impl<'s> ListBuilder<'s> {
+  pub(crate) fn with_capacity(
+    state: &'s EvalState,
+    capacity: usize,
+  ) -> Result<Self> {
+    // SAFETY: context and state are valid
+    let builder = unsafe {
+      sys::nix_make_list_builder(
+        state.context.as_ptr(),
+        state.as_ptr(),
+        capacity,
+      )
+    };
+    Ok(ListBuilder {
+      state,
+      inner: check_ptr(unsafe { state.context.as_ptr() }, builder)?,
+      len: 0,
+      capacity,
+    })
+  }
+
+  /// Append `value`.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::IndexOutOfBounds`] if the builder is full, or an
+  /// error if insertion fails.
+  pub fn push(&mut self, value: &Value<'_>) -> Result<()> {
+    if self.len >= self.capacity {
+      return Err(Error::IndexOutOfBounds {
+        index:  self.len,
+        length: self.capacity,
+      });
+    }
+    let index = c_uint::try_from(self.len).map_err(|_| Error::Overflow)?;
+    // SAFETY: context, builder and value are valid; index is in bounds
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        sys::nix_list_builder_insert(
+          ctx,
+          self.inner.as_ptr(),
+          index,
+          value.inner.as_ptr(),
+        ),
+      )?;
+    }
+    self.len += 1;
+    Ok(())
+  }
+
+  /// Append every value from `iter`.
+  ///
+  /// # Errors
+  ///
+  /// See [`push`](Self::push); values before the failing one stay pushed.
+  pub fn extend<'v, V: Borrow<Value<'v>>>(
+    &mut self,
+    iter: impl IntoIterator<Item = V>,
+  ) -> Result<()> {
+    for value in iter {
+      self.push(value.borrow())?;
+    }
+    Ok(())
+  }
+
+  /// Number of elements pushed so far.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  /// Whether nothing has been pushed yet.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Length of the list being built.
+  #[must_use]
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Build the list.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::IndexOutOfBounds`] if fewer elements than the
+  /// capacity were pushed, or an error if construction fails.
+  pub fn finish(self) -> Result<Value<'s>> {
+    if self.len != self.capacity {
+      return Err(Error::IndexOutOfBounds {
+        index:  self.len,
+        length: self.capacity,
+      });
+    }
+    let result = self.state.alloc_value()?;
+    // SAFETY: context, builder, and result value are valid; every slot is
+    // filled
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        sys::nix_make_list(ctx, self.inner.as_ptr(), result.inner.as_ptr()),
+      )?;
+    }
+    Ok(result)
+  }
+}
+
+impl Drop for ListBuilder<'_> {
+  fn drop(&mut self) {
+    // SAFETY: we own the builder
+    unsafe { sys::nix_list_builder_free(self.inner.as_ptr()) };
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use std::sync::Arc;
@@ -324,4 +484,47 @@ mod tests {
 
     assert!(iter.next().is_none());
   }
+
+  #[test]
+  #[serial]
+  fn test_list_builder() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let state = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    let mut builder = state.list_builder(2).expect("Failed to create builder");
+    let one = state.make_int(1).expect("Failed to make int");
+    builder.push(&one).expect("Failed to push");
+    assert!(builder.finish().is_err(), "unfilled list must not finish");
+
+    let mut builder = state.list_builder(2).expect("Failed to create builder");
+    builder.extend([&one, &one]).expect("Failed to extend");
+    assert!(matches!(
+      builder.push(&one),
+      Err(Error::IndexOutOfBounds {
+        index:  2,
+        length: 2,
+      })
+    ));
+    let list = builder.finish().expect("Failed to finish");
+    assert_eq!(list.list_len().expect("Failed to get length"), 2);
+
+    let list = state
+      .make_list_from_iter((0..1000).map(|i| state.make_int(i)))
+      .expect("Failed to build list");
+    let last = list.list_get(999).expect("Failed to get element");
+    assert_eq!(last.as_int().expect("Failed to get int"), 999);
+
+    // Without an exact size hint the values are collected first.
+    let list = state
+      .make_list_from_iter(
+        (0..10).filter(|i| i % 2 == 0).map(|i| state.make_int(i)),
+      )
+      .expect("Failed to build list");
+    assert_eq!(list.list_len().expect("Failed to get length"), 5);
+  }
 }
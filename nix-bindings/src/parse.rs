@@ -0,0 +1,190 @@
+//! Parsing an expression once and evaluating it many times.
+//!
+//! [`EvalState::eval_from_string`] parses its input on every call. When the
+//! same source is evaluated repeatedly, e.g. a template function applied to
+//! different arguments, parse it once with [`EvalState::parse`] and call
+//! [`ParsedExpr::eval`] as often as needed.
+
+#![cfg(feature = "shim")]
+
+use std::{ffi::CString, ptr::NonNull};
+
+use crate::{
+  Error,
+  EvalState,
+  Result,
+  SourcePos,
+  Value,
+  error::{check_err, check_ptr, string_from_callback},
+  json::JsonTree,
+  sys,
+};
+
+/// An expression parsed by [`EvalState::parse`].
+///
+/// Evaluating it again does not re-parse the source. Each
+/// [`eval`](Self::eval) produces a fresh value; thunks are not shared between
+/// evaluations.
+pub struct ParsedExpr<'s> {
+  pub(crate) inner: NonNull<sys::nix_parsed_expr>,
+  pub(crate) state: &'s EvalState,
+}
+
+impl EvalState {
+  /// Parse a Nix expression without evaluating it.
+  ///
+  /// `base_path` is the directory relative paths in `expr` resolve against,
+  /// as for [`eval_from_string`](Self::eval_from_string).
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::Syntax`] if `expr` does not parse, or another error if
+  /// the strings contain NUL bytes.
+  pub fn parse(&self, expr: &str, base_path: &str) -> Result<ParsedExpr<'_>> {
+    self.parse_with(expr, base_path, false)
+  }
+
+  /// Parse for static analysis: unbound variables are accepted instead of
+  /// rejected, and the result cannot be evaluated.
+  pub(crate) fn parse_unbound(
+    &self,
+    expr: &str,
+    base_path: &str,
+  ) -> Result<ParsedExpr<'_>> {
+    self.parse_with(expr, base_path, true)
+  }
+
+  fn parse_with(
+    &self,
+    expr: &str,
+    base_path: &str,
+    allow_unbound: bool,
+  ) -> Result<ParsedExpr<'_>> {
+    let parse = if allow_unbound {
+      sys::nix_parse_expr_unbound
+    } else {
+      sys::nix_parse_expr
+    };
+    let expr_c = CString::new(expr)?;
+    let path_c = CString::new(base_path)?;
+
+    // SAFETY: context and state are valid, the strings outlive the call
+    unsafe {
+      let ctx = self.context.as_ptr();
+      let mut ptr = std::ptr::null_mut();
+      let syntax = string_from_callback(|callback, user_data| {
+        ptr = parse(
+          ctx,
+          self.inner.as_ptr(),
+          expr_c.as_ptr(),
+          path_c.as_ptr(),
+          callback,
+          user_data,
+        );
+      });
+      if let Some(json) = syntax {
+        return Err(syntax_error(&json)?);
+      }
+
+      let inner = check_ptr(ctx, ptr)?;
+      Ok(ParsedExpr { inner, state: self })
+    }
+  }
+}
+
+impl<'s> ParsedExpr<'s> {
+  /// Evaluate the expression and force the result.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if evaluation fails.
+  pub fn eval(&self) -> Result<Value<'s>> {
+    let value = self.state.alloc_value()?;
+    // SAFETY: context, state, expression and value are valid
+    self.state.interruptible(|| unsafe {
+      let ctx = self.state.context.as_ptr();
+      check_err(
+        ctx,
+        sys::nix_parsed_expr_eval(
+          ctx,
+          self.state.inner.as_ptr(),
+          self.inner.as_ptr(),
+          value.inner.as_ptr(),
+        ),
+      )
+    })?;
+    Ok(value)
+  }
+}
+
+impl Drop for ParsedExpr<'_> {
+  fn drop(&mut self) {
+    // SAFETY: We own the handle and it's valid until drop
+    unsafe {
+      sys::nix_parsed_expr_free(self.inner.as_ptr());
+    }
+  }
+}
+
+/// Decode the `{"file", "line", "column", "message"}` object the shim emits
+/// for a syntax error.
+fn syntax_error(json: &str) -> Result<Error> {
+  let tree = JsonTree::parse(json)?;
+  let pos = SourcePos::from_json(&tree)?.ok_or(Error::NullPointer)?;
+  Ok(Error::Syntax {
+    file:    pos.file,
+    line:    pos.line,
+    column:  pos.column,
+    message: tree.get("message").as_str().unwrap_or_default().to_owned(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_parse_eval_many() {
+    let state = setup();
+    let parsed = state
+      .parse("{ n }: { doubled = n * 2; }", "/")
+      .expect("Failed to parse");
+
+    for n in 0..3 {
+      let template = parsed.eval().expect("Failed to evaluate");
+      let arg = state
+        .eval_from_string(&format!("{{ n = {n}; }}"), "<eval>")
+        .expect("Failed to evaluate");
+      let result = template.call(&arg).expect("Failed to call");
+      let doubled = result.get_attr("doubled").expect("Missing attribute");
+      assert_eq!(doubled.as_int().unwrap(), n * 2);
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_parse_syntax_error() {
+    let state = setup();
+    match state.parse("{\n  a = 1;\n  b = ;\n}", "/") {
+      Err(Error::Syntax {
+        line,
+        column,
+        message,
+        ..
+      }) => {
+        assert_eq!((line, column), (3, 7));
+        assert!(message.contains("syntax error"), "{message}");
+      },
+      Err(e) => panic!("expected a syntax error, got {e}"),
+      Ok(_) => panic!("expected a syntax error"),
+    }
+
+    // Evaluation errors are not syntax errors.
+    let parsed = state.parse("throw \"boom\"", "/").expect("Failed to parse");
+    assert!(matches!(parsed.eval(), Err(Error::EvalError(_))));
+  }
+}
@@ -0,0 +1,155 @@
+//! Source positions of expressions: where an attribute or lambda was
+//! defined.
+
+#![cfg(feature = "expr")]
+
+use std::fmt;
+
+#[cfg(feature = "shim")]
+use crate::{
+  Error,
+  FunctionInfo,
+  Result,
+  Value,
+  ValueType,
+  error::checked_string_from_callback,
+  json::JsonTree,
+  sys,
+};
+
+/// Where an expression was defined.
+///
+/// Expressions evaluated from a string have the file name `«string»`, and
+/// those read from standard input `«stdin»`, as in Nix's error messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SourcePos {
+  /// File the expression was read from.
+  pub file:   String,
+  /// Line number, starting at 1.
+  pub line:   u32,
+  /// Column number, starting at 1.
+  pub column: u32,
+}
+
+impl fmt::Display for SourcePos {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}:{}", self.file, self.line, self.column)
+  }
+}
+
+#[cfg(feature = "shim")]
+impl SourcePos {
+  /// Decode the `{"file", "line", "column"}` object the shims emit; `null`
+  /// means the expression has no position.
+  pub(crate) fn from_json(tree: &JsonTree) -> Result<Option<Self>> {
+    if tree.is_null() {
+      return Ok(None);
+    }
+    let field = |key| {
+      tree
+        .get(key)
+        .as_int()
+        .and_then(|n| u32::try_from(n).ok())
+        .ok_or_else(|| {
+          Error::Unknown("malformed source position from Nix".into())
+        })
+    };
+    Ok(Some(SourcePos {
+      file:   tree.get("file").as_str().unwrap_or_default().to_owned(),
+      line:   field("line")?,
+      column: field("column")?,
+    }))
+  }
+}
+
+#[cfg(feature = "shim")]
+impl Value<'_> {
+  /// Where attribute `name` of this set was defined, like
+  /// `builtins.unsafeGetAttrPos`.
+  ///
+  /// Forces the set but not the attribute. Returns `None` if the attribute
+  /// is missing or has no position, e.g. because it was added by a builtin.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::InvalidType`] if the value is not an attribute set,
+  /// or an error if forcing fails.
+  pub fn attr_pos(&self, name: &str) -> Result<Option<SourcePos>> {
+    self.force_shared()?;
+    if self.value_type() != ValueType::Attrs {
+      return Err(Error::InvalidType {
+        expected: "attrs",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    let name_c = std::ffi::CString::new(name)?;
+    // SAFETY: context, state, value and name are valid; the callback only
+    // runs during the call.
+    let json = unsafe {
+      let ctx = self.state.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_get_attr_pos(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          name_c.as_ptr(),
+          callback,
+          user_data,
+        )
+      })?
+    };
+    SourcePos::from_json(&JsonTree::parse(&json)?)
+  }
+
+  /// Where this lambda was defined.
+  ///
+  /// Returns `None` for builtins and their partial applications, which have
+  /// no source.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::InvalidType`] if the value is not a function, or an
+  /// error if forcing fails.
+  pub fn lambda_pos(&self) -> Result<Option<SourcePos>> {
+    Ok(match self.function_info()? {
+      FunctionInfo::Lambda { pos, .. } => pos,
+      _ => None,
+    })
+  }
+}
+
+#[cfg(all(test, feature = "shim"))]
+mod tests {
+  use serial_test::serial;
+
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_attr_pos() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{\n  a = 1;\n  f = x: x;\n}", "<eval>")
+      .expect("Failed to evaluate");
+
+    let pos = value
+      .attr_pos("a")
+      .expect("Failed to get position")
+      .expect("a should have a position");
+    assert_eq!((pos.line, pos.column), (2, 3));
+    assert_eq!(pos.to_string(), format!("{}:2:3", pos.file));
+    assert_eq!(value.attr_pos("missing").unwrap(), None);
+
+    let f = value.get_attr("f").expect("Failed to get f");
+    let pos = f
+      .lambda_pos()
+      .expect("Failed to get position")
+      .expect("lambda should have a position");
+    assert_eq!((pos.line, pos.column), (3, 7));
+
+    let map = state.eval_from_string("builtins.map", "<eval>").unwrap();
+    assert_eq!(map.lambda_pos().unwrap(), None);
+    assert!(map.attr_pos("a").is_err());
+  }
+}
@@ -14,6 +14,9 @@
 //! * **Value-embedded**: call [`PrimOp::into_value`] on an existing
 //!   [`EvalState`](crate::EvalState) to obtain a callable
 //!   [`Value`](crate::Value).
+//! * **Per-state builtin** (requires the `shim` feature): pass it to
+//!   `EvalStateBuilder::with_builtin`; only that state sees it in `builtins`,
+//!   and other states may use the name for something else.
 //!
 //! # Example
 //!
@@ -51,6 +54,7 @@ use std::{
   marker::PhantomData,
   os::raw::c_void,
   panic::{self, AssertUnwindSafe},
+  ptr::NonNull,
   sync::{Arc, Mutex, OnceLock},
 };
 
@@ -192,7 +196,9 @@ pub struct PrimOpArg<'a> {
 // argument into a thread that outlives the call and dereference a
 // dangling context.
 
-impl NixValueRaw for PrimOpArg<'_> {
+impl<'a> NixValueRaw for PrimOpArg<'a> {
+  type Child = PrimOpValue<'a>;
+
   fn raw_ctx(&self) -> *mut sys::nix_c_context {
     self.ctx
   }
@@ -204,12 +210,21 @@ impl NixValueRaw for PrimOpArg<'_> {
   fn raw_inner(&self) -> *mut sys::nix_value {
     self.inner
   }
+
+  unsafe fn wrap_child(&self, ptr: NonNull<sys::nix_value>) -> PrimOpValue<'a> {
+    PrimOpValue {
+      inner:    ptr.as_ptr(),
+      ctx:      self.ctx,
+      state:    self.state,
+      _phantom: PhantomData,
+    }
+  }
 }
 
 impl PrimOpArg<'_> {
   // Scalar accessors (value_type, force, as_int, as_float, as_bool,
-  // as_string, as_path) live on the NixValueOps trait; bring it into
-  // scope to use them.
+  // as_string, as_bytes, as_os_string, as_path) live on the NixValueOps
+  // trait; bring it into scope to use them.
 
   /// Interpret this argument as an attribute set.
   ///
@@ -358,6 +373,47 @@ impl<'a> PrimOpRet<'a> {
     Ok(())
   }
 
+  /// Write a string result from raw bytes, which need not be valid UTF-8.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `bytes` contains a NUL byte, which Nix strings
+  /// cannot hold, or the write fails.
+  pub fn set_string_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+    let s_c = CString::new(bytes)?;
+    unsafe {
+      check_err(
+        self.ctx,
+        sys::nix_init_string(self.ctx, self.inner, s_c.as_ptr()),
+      )
+    }?;
+    self.mark_written();
+    Ok(())
+  }
+
+  /// Write a string result carrying `context`, so that whatever uses it
+  /// depends on the store paths and derivations in it.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `s` or a context element contains an interior NUL
+  /// byte, an element is not a valid store path, or the write fails.
+  #[cfg(feature = "shim")]
+  pub fn set_string_with_context(
+    &mut self,
+    s: &str,
+    context: &crate::StringContext,
+  ) -> Result<()> {
+    // SAFETY: ctx, state and the return slot are valid for the call.
+    unsafe {
+      crate::string_context::init_string_with_context(
+        self.ctx, self.state, self.inner, s, context,
+      )
+    }?;
+    self.mark_written();
+    Ok(())
+  }
+
   /// Write a path result.
   ///
   /// The path string is interpreted by Nix the same way a `path` literal
@@ -479,6 +535,17 @@ impl<'a> PrimOpRet<'a> {
     Ok(())
   }
 
+  /// Write any [`IntoNix`](crate::IntoNix) value as the result.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the conversion or the write fails.
+  pub fn set<T: crate::IntoNix + ?Sized>(&mut self, value: &T) -> Result<()> {
+    let v = value.to_nix(&*self)?;
+    // SAFETY: v is a live value allocated in this callback's evaluator.
+    unsafe { self.copy_from_raw(v.inner) }
+  }
+
   /// Write an attribute set result.
   ///
   /// Builds an attribute set from the given key-value pairs and writes it
@@ -491,6 +558,32 @@ impl<'a> PrimOpRet<'a> {
   pub fn set_attrs(
     &mut self,
     pairs: &[(&str, &PrimOpValue<'_>)],
+  ) -> Result<()> {
+    self.init_attrs(self.inner, pairs)?;
+    self.mark_written();
+    Ok(())
+  }
+
+  /// Write a list result.
+  ///
+  /// Builds a list from the given values and writes it into the return
+  /// slot. Each value is a [`PrimOpValue`] obtained from a primop
+  /// argument or created via the `make_*` methods on this struct.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if construction fails.
+  pub fn set_list(&mut self, items: &[&PrimOpValue<'_>]) -> Result<()> {
+    self.init_list(self.inner, items)?;
+    self.mark_written();
+    Ok(())
+  }
+
+  /// Build an attribute set from `pairs` into `target`.
+  fn init_attrs(
+    &self,
+    target: *mut sys::nix_value,
+    pairs: &[(&str, &PrimOpValue<'_>)],
   ) -> Result<()> {
     let builder = unsafe {
       sys::nix_make_bindings_builder(self.ctx, self.state, pairs.len().max(1))
@@ -523,24 +616,18 @@ impl<'a> PrimOpRet<'a> {
       }
     }
 
-    // SAFETY: builder is valid, result slot is valid
+    // SAFETY: builder is valid, target slot is valid
     unsafe {
-      check_err(self.ctx, sys::nix_make_attrs(self.ctx, self.inner, builder))
-    }?;
-    self.mark_written();
-    Ok(())
+      check_err(self.ctx, sys::nix_make_attrs(self.ctx, target, builder))
+    }
   }
 
-  /// Write a list result.
-  ///
-  /// Builds a list from the given values and writes it into the return
-  /// slot. Each value is a [`PrimOpValue`] obtained from a primop
-  /// argument or created via the `make_*` methods on this struct.
-  ///
-  /// # Errors
-  ///
-  /// Returns an error if construction fails.
-  pub fn set_list(&mut self, items: &[&PrimOpValue<'_>]) -> Result<()> {
+  /// Build a list from `items` into `target`.
+  fn init_list(
+    &self,
+    target: *mut sys::nix_value,
+    items: &[&PrimOpValue<'_>],
+  ) -> Result<()> {
     let builder = unsafe {
       sys::nix_make_list_builder(self.ctx, self.state, items.len().max(1))
     };
@@ -571,10 +658,8 @@ impl<'a> PrimOpRet<'a> {
     }
 
     unsafe {
-      check_err(self.ctx, sys::nix_make_list(self.ctx, builder, self.inner))
-    }?;
-    self.mark_written();
-    Ok(())
+      check_err(self.ctx, sys::nix_make_list(self.ctx, builder, target))
+    }
   }
 
   /// Allocate and initialise an integer [`PrimOpValue`].
@@ -674,6 +759,40 @@ impl<'a> PrimOpRet<'a> {
     Ok(v)
   }
 
+  /// Allocate an attribute set [`PrimOpValue`] built from `pairs`.
+  ///
+  /// Like [`set_attrs`](Self::set_attrs), but returns the set so it can be
+  /// nested inside another attribute set or list.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or construction fails.
+  pub fn make_attrs(
+    &self,
+    pairs: &[(&str, &PrimOpValue<'_>)],
+  ) -> Result<PrimOpValue<'a>> {
+    let v = PrimOpValue::alloc(self.ctx, self.state)?;
+    self.init_attrs(v.inner, pairs)?;
+    Ok(v)
+  }
+
+  /// Allocate a list [`PrimOpValue`] built from `items`.
+  ///
+  /// Like [`set_list`](Self::set_list), but returns the list so it can be
+  /// nested inside another attribute set or list.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if allocation or construction fails.
+  pub fn make_list(
+    &self,
+    items: &[&PrimOpValue<'_>],
+  ) -> Result<PrimOpValue<'a>> {
+    let v = PrimOpValue::alloc(self.ctx, self.state)?;
+    self.init_list(v.inner, items)?;
+    Ok(v)
+  }
+
   /// Type-safe variant of [`make_store_path`](Self::make_store_path).
   ///
   /// Takes a parsed [`StorePath`](crate::store::StorePath) and renders it
@@ -728,6 +847,71 @@ impl<'a> PrimOpRet<'a> {
   }
 }
 
+impl crate::value_ops::sealed::Sealed for PrimOpRet<'_> {}
+
+/// Values built through [`IntoNix`](crate::IntoNix) inside a primop are
+/// allocated in the callback's evaluator; pass the result to one of the
+/// `set_*` methods (e.g. [`set_attrs`](PrimOpRet::set_attrs)) or use
+/// [`PrimOpRet::set`].
+impl<'a> crate::NixAlloc for PrimOpRet<'a> {
+  type Value<'s>
+    = PrimOpValue<'a>
+  where
+    Self: 's;
+
+  fn make_int(&self, i: i64) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_int(self, i)
+  }
+
+  fn make_float(&self, f: f64) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_float(self, f)
+  }
+
+  fn make_bool(&self, b: bool) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_bool(self, b)
+  }
+
+  fn make_null(&self) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_null(self)
+  }
+
+  fn make_string(&self, s: &str) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_string(self, s)
+  }
+
+  fn make_path(&self, path: &std::path::Path) -> Result<PrimOpValue<'a>> {
+    let p = path
+      .to_str()
+      .ok_or_else(|| Error::Unknown("Path is not valid UTF-8".into()))?;
+    PrimOpRet::make_path(self, p)
+  }
+
+  fn make_list<'s>(
+    &'s self,
+    items: &[&PrimOpValue<'a>],
+  ) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_list(self, items)
+  }
+
+  fn make_attrs<'s>(
+    &'s self,
+    pairs: &[(&str, &PrimOpValue<'a>)],
+  ) -> Result<PrimOpValue<'a>> {
+    PrimOpRet::make_attrs(self, pairs)
+  }
+
+  fn copy_value<V: NixValueOps>(&self, value: &V) -> Result<PrimOpValue<'a>> {
+    let v = PrimOpValue::alloc(self.ctx, self.state)?;
+    unsafe {
+      check_err(
+        self.ctx,
+        sys::nix_copy_value(self.ctx, v.inner, value.raw_inner()),
+      )?;
+    }
+    Ok(v)
+  }
+}
+
 /// An owned Nix value used within a primop callback.
 ///
 /// Unlike [`PrimOpArg`], this owns a GC reference to the underlying value
@@ -750,7 +934,9 @@ pub struct PrimOpValue<'a> {
 // auto-trait. The wrapper must not outlive the trampoline frame: doing
 // so would dereference a dangling `EvalState*` on the next force/decref.
 
-impl NixValueRaw for PrimOpValue<'_> {
+impl<'a> NixValueRaw for PrimOpValue<'a> {
+  type Child = PrimOpValue<'a>;
+
   fn raw_ctx(&self) -> *mut sys::nix_c_context {
     self.ctx
   }
@@ -762,6 +948,15 @@ impl NixValueRaw for PrimOpValue<'_> {
   fn raw_inner(&self) -> *mut sys::nix_value {
     self.inner
   }
+
+  unsafe fn wrap_child(&self, ptr: NonNull<sys::nix_value>) -> PrimOpValue<'a> {
+    PrimOpValue {
+      inner:    ptr.as_ptr(),
+      ctx:      self.ctx,
+      state:    self.state,
+      _phantom: PhantomData,
+    }
+  }
 }
 
 impl<'a> PrimOpValue<'a> {
@@ -782,8 +977,8 @@ impl<'a> PrimOpValue<'a> {
   }
 
   // Scalar accessors (value_type, force, as_int, as_float, as_bool,
-  // as_string, as_path) live on the NixValueOps trait; bring it into
-  // scope to use them.
+  // as_string, as_bytes, as_os_string, as_path) live on the NixValueOps
+  // trait; bring it into scope to use them.
 
   /// Interpret this value as an attribute set.
   ///
@@ -1144,7 +1339,9 @@ impl PrimOp {
   /// [`EvalState`](crate::EvalState) instances created **after** this call.
   ///
   /// This consumes `self`; the underlying pointer is transferred to the
-  /// global registry and is no longer accessible.
+  /// global registry and is no longer accessible. A name can be registered
+  /// only once per process; to scope a primop to one evaluator instead, use
+  /// `EvalStateBuilder::with_builtin` (requires the `shim` feature).
   ///
   /// # Errors
   ///
@@ -1284,6 +1481,35 @@ mod tests {
     assert_eq!(result.as_string().unwrap(), "hello from primop");
   }
 
+  #[test]
+  #[serial]
+  fn test_primop_string_bytes() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let state = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    // Reverses the bytes of its argument, which is not valid UTF-8.
+    let primop = PrimOp::new(&ctx, "reverse_bytes", 1, None, |args, ret| {
+      let mut bytes = args[0].as_bytes()?;
+      assert!(args[0].as_string().is_err());
+      bytes.reverse();
+      ret.set_string_bytes(&bytes)
+    })
+    .expect("Failed to create primop");
+
+    let func = primop
+      .into_value(&state)
+      .expect("Failed to embed primop as value");
+
+    let arg = state.make_string_bytes(b"a\xff").unwrap();
+    let result = func.call(&arg).expect("Failed to call primop");
+    assert_eq!(result.as_bytes().unwrap(), b"\xffa");
+  }
+
   #[test]
   #[serial]
   fn test_primop_path_roundtrip() {
@@ -1502,6 +1728,38 @@ mod tests {
     assert_eq!(y.as_string().unwrap(), "hi");
   }
 
+  #[test]
+  #[serial]
+  fn test_primop_from_nix_into_nix() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let state = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    // Sums each list in an attribute set of integer lists.
+    let primop = PrimOp::new(&ctx, "sum_each", 1, None, |args, ret| {
+      let input: std::collections::BTreeMap<String, Vec<i64>> =
+        args[0].extract()?;
+      let sums: std::collections::BTreeMap<String, i64> = input
+        .into_iter()
+        .map(|(k, v)| (k, v.iter().sum()))
+        .collect();
+      ret.set(&sums)
+    })
+    .expect("Failed to create primop");
+
+    let func = primop.into_value(&state).expect("Failed to embed primop");
+    let arg = state
+      .eval_from_string("{ a = [ 1 2 ]; b = [ ]; }", "<eval>")
+      .expect("Failed to evaluate argument");
+    let result = func.call(&arg).expect("Failed to call primop");
+    assert_eq!(result.get_attr("a").unwrap().as_int().unwrap(), 3);
+    assert_eq!(result.get_attr("b").unwrap().as_int().unwrap(), 0);
+  }
+
   #[test]
   #[serial]
   fn test_primop_ret_set_list() {
@@ -1645,4 +1903,70 @@ mod tests {
       result.map(|v| v.value_type()),
     );
   }
+
+  #[cfg(feature = "shim")]
+  #[test]
+  #[serial]
+  fn test_primop_scoped_builtin() {
+    let ctx = Arc::new(Context::new().expect("Failed to create context"));
+    let store =
+      Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+    let eval_int = |state: &crate::EvalState, expr: &str| {
+      state
+        .eval_from_string(expr, "<eval>")
+        .and_then(|v| v.as_int())
+        .expect("Failed to evaluate")
+    };
+
+    let triple = PrimOp::new(&ctx, "scoped_triple", 1, None, |args, ret| {
+      ret.set_int(args[0].as_int()? * 3)
+    })
+    .expect("Failed to create primop");
+    let scoped = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .with_builtin("scoped_triple", triple)
+      .with_builtin("answer", crate::NixData::Int(1))
+      .with_builtin("answer", crate::Builtin::Expr("6 * 7".into()))
+      .without_builtin("getEnv")
+      .with_top_level_builtin("map", crate::Builtin::Expr("f: l: 42".into()))
+      .build()
+      .expect("Failed to build state");
+    let plain = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .build()
+      .expect("Failed to build state");
+
+    assert_eq!(
+      eval_int(&scoped, "builtins.scoped_triple builtins.answer"),
+      126
+    );
+    let has = |state: &crate::EvalState, name: &str| {
+      state
+        .eval_from_string(&format!("builtins ? {name}"), "<eval>")
+        .and_then(|v| v.as_bool())
+        .expect("Failed to evaluate")
+    };
+    assert!(!has(&scoped, "getEnv"));
+    assert!(has(&scoped, "map"));
+    assert!(!has(&plain, "scoped_triple"));
+    assert!(has(&plain, "getEnv"));
+
+    assert_eq!(eval_int(&scoped, "map null null"), 42);
+    assert_eq!(eval_int(&scoped, "builtins.map null null"), 42);
+    assert_eq!(eval_int(&plain, "builtins.length (map (x: x) [ 1 ])"), 1);
+    assert!(scoped.eval_from_string("__getEnv", "<eval>").is_err());
+    assert!(
+      plain
+        .eval_from_string("__getEnv \"HOME\"", "<eval>")
+        .is_ok()
+    );
+
+    let err = EvalStateBuilder::new(&store)
+      .expect("Failed to create builder")
+      .with_top_level_builtin("scoped_answer", crate::NixData::Int(42))
+      .build()
+      .err()
+      .expect("New top-level names should be rejected");
+    assert!(matches!(err, crate::Error::KeyNotFound(_)));
+  }
 }
@@ -0,0 +1,513 @@
+//! Printing values as Nix source.
+//!
+//! [`NixPrinter`] renders a value in Nix syntax, optionally in the style of
+//! `nix repl`. Whatever cannot be written as an expression (functions,
+//! unforced thunks, elided or repeated parts) is rendered with a placeholder
+//! such as `<lambda>` or `«thunk»`, or as `{ ... }` / `[ ... ]`, none of
+//! which parse. Output that contains none of them re-parses to a value equal
+//! to the original.
+
+#![cfg(feature = "expr")]
+
+use std::collections::HashSet;
+
+use crate::{Error, NixValueOps, Result, Value, ValueType, attrs};
+
+/// Nesting depth at which printing stops with `«too deep»`, whatever
+/// [`NixPrinter::max_depth`] says. The printer recurses per level.
+const MAX_PRINT_DEPTH: usize = 256;
+
+/// Configurable printer producing Nix syntax.
+///
+/// The defaults print everything on one line, force every thunk, and never
+/// elide anything, which is what [`Value::to_nix_string`] uses.
+///
+/// Values that contain themselves print the inner occurrence as
+/// `«repeated»` (with the `shim` feature, which can tell values apart), and
+/// nesting deeper than 256 levels prints as `«too deep»`, so
+/// self-referential and infinitely deep values always terminate.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use nix_bindings::{Context, EvalStateBuilder, NixPrinter, Store};
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # let ctx = Arc::new(Context::new()?);
+/// # let store = Arc::new(Store::open(&ctx, None)?);
+/// # let state = EvalStateBuilder::new(&store)?.build()?;
+/// let value = state.eval_from_string("{ a.b = [ 1 2 ]; }", "<eval>")?;
+/// let printed = NixPrinter::new().multiline(2).max_depth(2).print(&value)?;
+/// assert_eq!(printed, "{\n  a = {\n    b = [ ... ];\n  };\n}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NixPrinter {
+  indent:           Option<usize>,
+  max_depth:        Option<usize>,
+  force:            bool,
+  track_repeated:   bool,
+  derivation_paths: bool,
+}
+
+impl Default for NixPrinter {
+  fn default() -> Self {
+    NixPrinter {
+      indent:           None,
+      max_depth:        None,
+      force:            true,
+      track_repeated:   false,
+      derivation_paths: false,
+    }
+  }
+}
+
+impl NixPrinter {
+  /// A printer with the default settings.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Put each attribute and list element on its own line, indented by
+  /// `indent` spaces per level.
+  #[must_use]
+  pub fn multiline(mut self, indent: usize) -> Self {
+    self.indent = Some(indent);
+    self
+  }
+
+  /// Print attribute sets and lists nested `depth` levels deep as
+  /// `{ ... }` and `[ ... ]`. A depth of 0 elides the value itself.
+  #[must_use]
+  pub fn max_depth(mut self, depth: usize) -> Self {
+    self.max_depth = Some(depth);
+    self
+  }
+
+  /// Whether to force thunks (the default) or print them as `«thunk»`.
+  #[must_use]
+  pub fn force(mut self, force: bool) -> Self {
+    self.force = force;
+    self
+  }
+
+  /// Print an attribute set or list that was already printed anywhere
+  /// else in the output as `«repeated»`, like `nix repl`. Without this,
+  /// only a value nested inside itself is printed as `«repeated»`.
+  #[cfg(feature = "shim")]
+  #[must_use]
+  pub fn track_repeated(mut self, track: bool) -> Self {
+    self.track_repeated = track;
+    self
+  }
+
+  /// Print derivations as `«derivation /nix/store/...drv»` instead of
+  /// their attributes, like `nix repl`. Only applies when forcing.
+  #[must_use]
+  pub fn derivation_paths(mut self, short: bool) -> Self {
+    self.derivation_paths = short;
+    self
+  }
+
+  /// Render `value`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing any part of the value fails.
+  pub fn print(&self, value: &Value<'_>) -> Result<String> {
+    let mut printer = Printer {
+      options:   self,
+      out:       String::new(),
+      seen:      HashSet::new(),
+      ancestors: Vec::new(),
+    };
+    printer.value(value, 0)?;
+    Ok(printer.out)
+  }
+}
+
+impl Value<'_> {
+  /// Format this value as Nix syntax.
+  ///
+  /// Forces the value deeply and renders it on one line with
+  /// [`NixPrinter`]'s defaults. Functions and external values render as
+  /// `<lambda>` and `<external>`, and cycles as `«repeated»`; otherwise the
+  /// output re-parses to an equal value.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or any nested value cannot be
+  /// rendered.
+  pub fn to_nix_string(&self) -> Result<String> {
+    NixPrinter::new().print(self)
+  }
+}
+
+struct Printer<'p> {
+  options:   &'p NixPrinter,
+  out:       String,
+  /// Identities of the attribute sets and lists printed so far.
+  seen:      HashSet<usize>,
+  /// Identities of the attribute sets and lists being printed.
+  ancestors: Vec<usize>,
+}
+
+impl Printer<'_> {
+  fn value(&mut self, value: &Value<'_>, depth: usize) -> Result<()> {
+    if value.value_type() == ValueType::Thunk {
+      if !self.options.force {
+        self.out.push_str("«thunk»");
+        return Ok(());
+      }
+      value.force_shared()?;
+    }
+    match value.value_type() {
+      ValueType::Int => write_int(&mut self.out, value.as_int()?),
+      ValueType::Float => write_float(&mut self.out, value.as_float()?),
+      ValueType::Bool => {
+        self
+          .out
+          .push_str(if value.as_bool()? { "true" } else { "false" });
+      },
+      ValueType::Null => self.out.push_str("null"),
+      ValueType::String => {
+        self.out.push_str(&attrs::quote_string(&value.as_string()?));
+      },
+      ValueType::Path => write_path(&mut self.out, &value.as_path()?),
+      ValueType::Attrs | ValueType::List => self.container(value, depth)?,
+      ValueType::Function => self.out.push_str("<lambda>"),
+      ValueType::External => self.out.push_str("<external>"),
+      ValueType::Thunk => {
+        return Err(Error::Unknown(
+          "value is still a thunk after forcing".into(),
+        ));
+      },
+    }
+    Ok(())
+  }
+
+  /// Print an attribute set or list, unless it is repeated or too deep.
+  fn container(&mut self, value: &Value<'_>, depth: usize) -> Result<()> {
+    let id = value.identity();
+    if self.repeated(id) {
+      self.out.push_str("«repeated»");
+      return Ok(());
+    }
+    if depth >= MAX_PRINT_DEPTH {
+      self.out.push_str("«too deep»");
+      return Ok(());
+    }
+    self.ancestors.push(id);
+    let result = if value.value_type() == ValueType::Attrs {
+      self.attrs(value, depth)
+    } else {
+      self.list(value, depth)
+    };
+    self.ancestors.pop();
+    result
+  }
+
+  /// Whether the value with identity `id` is being printed, or was already
+  /// printed when tracking repeats; records it as printed otherwise.
+  fn repeated(&mut self, id: usize) -> bool {
+    if id == 0 {
+      return false;
+    }
+    if self.ancestors.contains(&id) {
+      return true;
+    }
+    self.options.track_repeated && !self.seen.insert(id)
+  }
+
+  fn elided(&self, depth: usize) -> bool {
+    self.options.max_depth.is_some_and(|max| depth >= max)
+  }
+
+  fn attrs(&mut self, value: &Value<'_>, depth: usize) -> Result<()> {
+    if self.options.force
+      && self.options.derivation_paths
+      && let Some(drv_path) = derivation_path(value)?
+    {
+      self.out.push_str("«derivation ");
+      self.out.push_str(&drv_path);
+      self.out.push('»');
+      return Ok(());
+    }
+    if self.elided(depth) {
+      self.out.push_str("{ ... }");
+      return Ok(());
+    }
+
+    let mut entries = value.attrs()?.collect::<Result<Vec<_>>>()?;
+    if entries.is_empty() {
+      self.out.push_str("{ }");
+      return Ok(());
+    }
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    self.out.push('{');
+    for (name, child) in &entries {
+      self.separator(depth + 1);
+      self.out.push_str(&attrs::format_attr_name(name));
+      self.out.push_str(" = ");
+      self.value(child, depth + 1)?;
+      self.out.push(';');
+    }
+    self.separator(depth);
+    self.out.push('}');
+    Ok(())
+  }
+
+  fn list(&mut self, value: &Value<'_>, depth: usize) -> Result<()> {
+    if self.elided(depth) {
+      self.out.push_str("[ ... ]");
+      return Ok(());
+    }
+
+    let items = value.list_items()?;
+    if items.is_empty() {
+      self.out.push_str("[ ]");
+      return Ok(());
+    }
+    self.out.push('[');
+    for item in &items {
+      self.separator(depth + 1);
+      // A list element is a single operand, so a leading minus sign must
+      // be parenthesised: `[ -1 ]` does not parse.
+      let start = self.out.len();
+      self.value(item, depth + 1)?;
+      if self.out[start..].starts_with('-') {
+        self.out.insert(start, '(');
+        self.out.push(')');
+      }
+    }
+    self.separator(depth);
+    self.out.push(']');
+    Ok(())
+  }
+
+  /// Start a new line indented to `depth`, or add a space when printing on
+  /// one line.
+  fn separator(&mut self, depth: usize) {
+    match self.options.indent {
+      Some(indent) => {
+        self.out.push('\n');
+        self.out.extend(std::iter::repeat_n(' ', indent * depth));
+      },
+      None => self.out.push(' '),
+    }
+  }
+}
+
+/// The `drvPath` of `value` if it is a derivation (`type = "derivation"`).
+fn derivation_path(value: &Value<'_>) -> Result<Option<String>> {
+  let Some(ty) = value.get_attr_lazy("type")? else {
+    return Ok(None);
+  };
+  ty.force_shared()?;
+  if ty.value_type() != ValueType::String || ty.as_string()? != "derivation" {
+    return Ok(None);
+  }
+  let drv_path = match value.get_attr_lazy("drvPath")? {
+    Some(drv_path) => drv_path.as_string()?,
+    None => "???".to_string(),
+  };
+  Ok(Some(drv_path))
+}
+
+fn write_int(out: &mut String, i: i64) {
+  if i == i64::MIN {
+    // The literal 9223372036854775808 is out of range, so it cannot be
+    // negated.
+    out.push_str("(-9223372036854775807 - 1)");
+  } else {
+    out.push_str(&i.to_string());
+  }
+}
+
+/// Write the shortest representation that reads back as the same float.
+///
+/// Nix float literals need a `.` (`1e5` lexes as `1` applied to `e5`), and
+/// there is no literal for infinities or NaN.
+fn write_float(out: &mut String, f: f64) {
+  if !f.is_finite() {
+    out.push_str(if f.is_nan() {
+      "«nan»"
+    } else if f > 0.0 {
+      "«inf»"
+    } else {
+      "«-inf»"
+    });
+    return;
+  }
+  let repr = format!("{f:?}");
+  let (mantissa, exponent) = match repr.split_once('e') {
+    Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+    None => (repr.as_str(), None),
+  };
+  out.push_str(mantissa);
+  if !mantissa.contains('.') {
+    out.push_str(".0");
+  }
+  if let Some(exponent) = exponent {
+    out.push('e');
+    out.push_str(exponent);
+  }
+}
+
+/// Paths are written as literals when the lexer accepts them, and as
+/// `/. + "..."` otherwise.
+fn write_path(out: &mut String, path: &std::path::Path) {
+  let s = path.to_string_lossy();
+  let literal = s.strip_prefix('/').is_some_and(|rest| {
+    rest.split('/').all(|segment| {
+      !segment.is_empty()
+        && segment
+          .bytes()
+          .all(|b| b.is_ascii_alphanumeric() || b"._-+".contains(&b))
+    })
+  });
+  if literal {
+    out.push_str(&s);
+  } else if s == "/" {
+    out.push_str("/.");
+  } else {
+    out.push_str("(/. + ");
+    out.push_str(&attrs::quote_string(&s));
+    out.push(')');
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_to_nix_string_reparses() {
+    let state = setup();
+    for expr in [
+      "{ \"foo.bar\" = 1; \"if\" = 2; \"\" = 3; a-b' = 4; }",
+      "[ (-1) (-2.5) 0.1 1.0e300 1.0e-7 (-9223372036854775807 - 1) ]",
+      "\"a\\\"b\\\\c\\n\\${x} $ $${y}\"",
+      "[ /tmp/x (/. + \"/tmp/a b\") /. null true [ ] { } ]",
+      "{ a.b.c = [ { d = \"e\"; } ]; }",
+    ] {
+      let value = state.eval_from_string(expr, "<eval>").unwrap();
+      let printed = value.to_nix_string().expect("Failed to print");
+      let equal = state
+        .eval_from_string(&format!("({expr}) == ({printed})"), "<eval>")
+        .unwrap_or_else(|e| panic!("{printed} did not parse: {e}"));
+      assert!(equal.as_bool().unwrap(), "{expr} printed as {printed}");
+    }
+
+    let value = state
+      .eval_from_string("{ \"foo.bar\" = \"${\"$\"}{x}\"; }", "<eval>")
+      .unwrap();
+    assert_eq!(
+      value.to_nix_string().unwrap(),
+      "{ \"foo.bar\" = \"\\${x}\"; }"
+    );
+  }
+
+  #[test]
+  #[serial]
+  fn test_printer_options() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "let d = derivation { name = \"d\"; system = \"x\"; builder = \"b\"; \
+         }; in { l = [ 1 [ 2 ] ]; t = throw \"no\"; inherit d; f = x: x; }",
+        "<eval>",
+      )
+      .unwrap();
+
+    let lazy = NixPrinter::new()
+      .force(false)
+      .derivation_paths(true)
+      .print(&value)
+      .expect("Failed to print");
+    assert_eq!(
+      lazy,
+      "{ d = «thunk»; f = «thunk»; l = «thunk»; t = «thunk»; }"
+    );
+
+    let l = value.get_attr("l").unwrap();
+    let multiline = NixPrinter::new().multiline(2).print(&l).unwrap();
+    assert_eq!(multiline, "[\n  1\n  [\n    2\n  ]\n]");
+    let shallow = NixPrinter::new().max_depth(1).print(&l).unwrap();
+    assert_eq!(shallow, "[ 1 [ ... ] ]");
+
+    let d = value.get_attr("d").unwrap();
+    let drv = NixPrinter::new().derivation_paths(true).print(&d).unwrap();
+    assert!(drv.starts_with("«derivation /nix/store/"), "{drv}");
+    assert!(drv.ends_with("-d.drv»"), "{drv}");
+  }
+
+  #[test]
+  #[serial]
+  #[cfg(feature = "shim")]
+  fn test_printer_repeated() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "let x = { a = 1; }; in rec { p = x; q = x; r = { inherit r; }; }",
+        "<eval>",
+      )
+      .unwrap();
+    let printed = NixPrinter::new()
+      .track_repeated(true)
+      .print(&value)
+      .expect("Failed to print");
+    assert_eq!(
+      printed,
+      "{ p = { a = 1; }; q = «repeated»; r = { r = «repeated»; }; }"
+    );
+  }
+
+  #[test]
+  #[serial]
+  #[cfg(feature = "shim")]
+  fn test_printer_self_reference() {
+    let state = setup();
+    let value = state
+      .eval_from_string("let x = { inherit x; y = [ x ]; }; in x", "<eval>")
+      .unwrap();
+    assert_eq!(
+      value.to_nix_string().expect("Failed to print"),
+      "{ x = «repeated»; y = [ «repeated» ]; }"
+    );
+  }
+
+  #[test]
+  #[serial]
+  fn test_printer_too_deep() {
+    let state = setup();
+    let value = state
+      .eval_from_string("let f = n: { next = f (n + 1); }; in f 0", "<eval>")
+      .unwrap();
+    let printed = value.to_nix_string().expect("Failed to print");
+    assert_eq!(printed.matches("next = ").count(), MAX_PRINT_DEPTH);
+    assert!(printed.contains("next = «too deep»;"), "{printed}");
+  }
+
+  #[test]
+  fn test_write_float() {
+    for (f, expected) in [
+      (1.0, "1.0"),
+      (0.1, "0.1"),
+      (1e300, "1.0e300"),
+      (1.5e-7, "1.5e-7"),
+      (-2.0, "-2.0"),
+      (f64::INFINITY, "«inf»"),
+    ] {
+      let mut out = String::new();
+      write_float(&mut out, f);
+      assert_eq!(out, expected);
+    }
+  }
+}
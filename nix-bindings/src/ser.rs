@@ -0,0 +1,634 @@
+//! Serde [`Serializer`] that builds Nix [`Value`]s from Rust data.
+//!
+//! Structs and maps become attribute sets, sequences and tuples become lists,
+//! `None` and `()` become `null`, and scalars map onto the matching
+//! `EvalState::make_*` constructors. Enums use the same externally tagged
+//! shape the [`Deserializer`](crate::Deserializer) reads: unit variants are
+//! strings, everything else is a single-attribute set `{ Variant = ...; }`.
+
+#![cfg(feature = "serde")]
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::ser::{self, Serialize};
+
+use crate::{Error, EvalState, Result, Value, ValueType};
+
+impl ser::Error for Error {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    Error::Conversion {
+      path:    String::new(),
+      message: msg.to_string(),
+    }
+  }
+}
+
+impl EvalState {
+  /// Build a Nix value from any [`Serialize`] type.
+  ///
+  /// # Example
+  ///
+  /// ```no_run
+  /// # use std::sync::Arc;
+  /// # use nix_bindings::{Context, EvalStateBuilder, Store};
+  /// #[derive(serde::Serialize)]
+  /// struct Args {
+  ///   name:    String,
+  ///   version: Option<String>,
+  /// }
+  ///
+  /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+  /// # let ctx = Arc::new(Context::new()?);
+  /// # let store = Arc::new(Store::open(&ctx, None)?);
+  /// # let state = EvalStateBuilder::new(&store)?.build()?;
+  /// let f = state.eval_from_string("{ name, version }: name", "<eval>")?;
+  /// let args = state.to_value(&Args {
+  ///   name:    "hello".to_string(),
+  ///   version: None,
+  /// })?;
+  /// assert_eq!(f.call(&args)?.as_string()?, "hello");
+  /// # Ok(())
+  /// # }
+  /// ```
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `value` cannot be represented in Nix (e.g. an
+  /// integer outside the signed 64-bit range, a string containing a NUL
+  /// byte, or a map key that is not a string) or value construction fails.
+  pub fn to_value<T: Serialize + ?Sized>(
+    &self,
+    value: &T,
+  ) -> Result<Value<'_>> {
+    value.serialize(Serializer::new(self))
+  }
+}
+
+/// Serde serializer producing values owned by an [`EvalState`].
+///
+/// Most callers want [`EvalState::to_value`].
+pub struct Serializer<'s> {
+  state: &'s EvalState,
+}
+
+impl<'s> Serializer<'s> {
+  /// Create a serializer allocating values in `state`.
+  #[must_use]
+  pub fn new(state: &'s EvalState) -> Self {
+    Serializer { state }
+  }
+}
+
+/// Build the single-attribute set `{ <variant> = <value>; }`.
+fn tagged<'s>(
+  state: &'s EvalState,
+  variant: &str,
+  value: &Value<'_>,
+) -> Result<Value<'s>> {
+  state.make_attrs(&[(variant, value)])
+}
+
+fn list<'s>(state: &'s EvalState, items: &[Value<'s>]) -> Result<Value<'s>> {
+  let refs: Vec<&Value<'_>> = items.iter().collect();
+  state.make_list(&refs)
+}
+
+fn attrs<'s>(
+  state: &'s EvalState,
+  entries: &BTreeMap<String, Value<'s>>,
+) -> Result<Value<'s>> {
+  let pairs: Vec<(&str, &Value<'_>)> =
+    entries.iter().map(|(k, v)| (k.as_str(), v)).collect();
+  state.make_attrs(&pairs)
+}
+
+impl<'s> ser::Serializer for Serializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+  type SerializeMap = AttrsSerializer<'s>;
+  type SerializeSeq = ListSerializer<'s>;
+  type SerializeStruct = AttrsSerializer<'s>;
+  type SerializeStructVariant = AttrsSerializer<'s>;
+  type SerializeTuple = ListSerializer<'s>;
+  type SerializeTupleStruct = ListSerializer<'s>;
+  type SerializeTupleVariant = ListSerializer<'s>;
+
+  fn serialize_bool(self, v: bool) -> Result<Value<'s>> {
+    self.state.make_bool(v)
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<Value<'s>> {
+    self.state.make_int(v)
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<Value<'s>> {
+    self.serialize_i64(i64::from(v))
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<Value<'s>> {
+    let v = i64::try_from(v).map_err(|_| {
+      Error::Conversion {
+        path:    String::new(),
+        message: format!("integer {v} is out of range for a Nix int"),
+      }
+    })?;
+    self.serialize_i64(v)
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<Value<'s>> {
+    self.serialize_f64(f64::from(v))
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<Value<'s>> {
+    self.state.make_float(v)
+  }
+
+  fn serialize_char(self, v: char) -> Result<Value<'s>> {
+    self.state.make_string(v.encode_utf8(&mut [0; 4]))
+  }
+
+  fn serialize_str(self, v: &str) -> Result<Value<'s>> {
+    self.state.make_string(v)
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<Value<'s>> {
+    // Nix has no byte type. A list of ints keeps every byte, and the
+    // deserializer reads it back (as well as plain strings).
+    let items = v
+      .iter()
+      .map(|b| self.state.make_int(i64::from(*b)))
+      .collect::<Result<Vec<_>>>()?;
+    list(self.state, &items)
+  }
+
+  fn serialize_none(self) -> Result<Value<'s>> {
+    self.state.make_null()
+  }
+
+  fn serialize_some<T: Serialize + ?Sized>(
+    self,
+    value: &T,
+  ) -> Result<Value<'s>> {
+    value.serialize(self)
+  }
+
+  fn serialize_unit(self) -> Result<Value<'s>> {
+    self.state.make_null()
+  }
+
+  fn serialize_unit_struct(self, _name: &'static str) -> Result<Value<'s>> {
+    self.serialize_unit()
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<Value<'s>> {
+    self.state.make_string(variant)
+  }
+
+  fn serialize_newtype_struct<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    value: &T,
+  ) -> Result<Value<'s>> {
+    value.serialize(self)
+  }
+
+  fn serialize_newtype_variant<T: Serialize + ?Sized>(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<Value<'s>> {
+    let inner = value.serialize(Serializer::new(self.state))?;
+    tagged(self.state, variant, &inner)
+  }
+
+  fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer<'s>> {
+    Ok(ListSerializer {
+      state:   self.state,
+      items:   Vec::with_capacity(len.unwrap_or(0)),
+      variant: None,
+    })
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<ListSerializer<'s>> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_struct(
+    self,
+    _name: &'static str,
+    len: usize,
+  ) -> Result<ListSerializer<'s>> {
+    self.serialize_seq(Some(len))
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<ListSerializer<'s>> {
+    Ok(ListSerializer {
+      state:   self.state,
+      items:   Vec::with_capacity(len),
+      variant: Some(variant),
+    })
+  }
+
+  fn serialize_map(self, _len: Option<usize>) -> Result<AttrsSerializer<'s>> {
+    Ok(AttrsSerializer {
+      state:    self.state,
+      entries:  BTreeMap::new(),
+      next_key: None,
+      variant:  None,
+    })
+  }
+
+  fn serialize_struct(
+    self,
+    _name: &'static str,
+    len: usize,
+  ) -> Result<AttrsSerializer<'s>> {
+    self.serialize_map(Some(len))
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    _len: usize,
+  ) -> Result<AttrsSerializer<'s>> {
+    Ok(AttrsSerializer {
+      state:    self.state,
+      entries:  BTreeMap::new(),
+      next_key: None,
+      variant:  Some(variant),
+    })
+  }
+}
+
+/// Collects sequence, tuple, and tuple-variant elements into a list.
+pub struct ListSerializer<'s> {
+  state:   &'s EvalState,
+  items:   Vec<Value<'s>>,
+  variant: Option<&'static str>,
+}
+
+impl<'s> ListSerializer<'s> {
+  fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+    self
+      .items
+      .push(value.serialize(Serializer::new(self.state))?);
+    Ok(())
+  }
+
+  fn finish(self) -> Result<Value<'s>> {
+    let value = list(self.state, &self.items)?;
+    match self.variant {
+      Some(variant) => tagged(self.state, variant, &value),
+      None => Ok(value),
+    }
+  }
+}
+
+impl<'s> ser::SerializeSeq for ListSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_element<T: Serialize + ?Sized>(
+    &mut self,
+    value: &T,
+  ) -> Result<()> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+impl<'s> ser::SerializeTuple for ListSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_element<T: Serialize + ?Sized>(
+    &mut self,
+    value: &T,
+  ) -> Result<()> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+impl<'s> ser::SerializeTupleStruct for ListSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_field<T: Serialize + ?Sized>(
+    &mut self,
+    value: &T,
+  ) -> Result<()> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+impl<'s> ser::SerializeTupleVariant for ListSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_field<T: Serialize + ?Sized>(
+    &mut self,
+    value: &T,
+  ) -> Result<()> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+/// Collects map, struct, and struct-variant entries into an attribute set.
+///
+/// Entries are kept in a sorted map so a key serialized twice keeps its last
+/// value instead of producing a malformed attribute set.
+pub struct AttrsSerializer<'s> {
+  state:    &'s EvalState,
+  entries:  BTreeMap<String, Value<'s>>,
+  next_key: Option<String>,
+  variant:  Option<&'static str>,
+}
+
+impl<'s> AttrsSerializer<'s> {
+  fn insert<T: Serialize + ?Sized>(
+    &mut self,
+    key: String,
+    value: &T,
+  ) -> Result<()> {
+    let value = value.serialize(Serializer::new(self.state))?;
+    self.entries.insert(key, value);
+    Ok(())
+  }
+
+  fn finish(self) -> Result<Value<'s>> {
+    let value = attrs(self.state, &self.entries)?;
+    match self.variant {
+      Some(variant) => tagged(self.state, variant, &value),
+      None => Ok(value),
+    }
+  }
+}
+
+impl<'s> ser::SerializeMap for AttrsSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+    // Attribute names are strings. Integer keys are stringified the same
+    // way serde_json treats them for object keys.
+    let key = key.serialize(Serializer::new(self.state))?;
+    let name = match key.value_type() {
+      ValueType::String => key.as_string()?,
+      ValueType::Int => key.as_int()?.to_string(),
+      other => {
+        return Err(Error::Conversion {
+          path:    String::new(),
+          message: format!("attribute names must be strings, got {other}"),
+        });
+      },
+    };
+    self.next_key = Some(name);
+    Ok(())
+  }
+
+  fn serialize_value<T: Serialize + ?Sized>(
+    &mut self,
+    value: &T,
+  ) -> Result<()> {
+    let key = self.next_key.take().ok_or_else(|| {
+      Error::Unknown("serialize_value called before serialize_key".to_string())
+    })?;
+    self.insert(key, value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+impl<'s> ser::SerializeStruct for AttrsSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_field<T: Serialize + ?Sized>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<()> {
+    self.insert(key.to_string(), value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+impl<'s> ser::SerializeStructVariant for AttrsSerializer<'s> {
+  type Error = Error;
+  type Ok = Value<'s>;
+
+  fn serialize_field<T: Serialize + ?Sized>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<()> {
+    self.insert(key.to_string(), value)
+  }
+
+  fn end(self) -> Result<Value<'s>> {
+    self.finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+  use serial_test::serial;
+
+  use crate::{ValueType, from_value, test_util::setup};
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  struct Package {
+    name:     String,
+    version:  Option<String>,
+    priority: i64,
+    weight:   f64,
+    outputs:  Vec<String>,
+    kind:     Kind,
+  }
+
+  /// What `serde_bytes` does: serialize through the bytes methods.
+  #[derive(Debug, PartialEq)]
+  struct Bytes(Vec<u8>);
+
+  impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+      s.serialize_bytes(&self.0)
+    }
+  }
+
+  impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+      struct BytesVisitor;
+
+      impl serde::de::Visitor<'_> for BytesVisitor {
+        type Value = Bytes;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+          f.write_str("bytes")
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Bytes, E> {
+          Ok(Bytes(v))
+        }
+      }
+
+      d.deserialize_byte_buf(BytesVisitor)
+    }
+  }
+
+  #[derive(Debug, Serialize, Deserialize, PartialEq)]
+  enum Kind {
+    Library,
+    Tool { bin: String },
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_value_struct() {
+    let state = setup();
+    let value = state
+      .to_value(&Package {
+        name:     "hello".to_string(),
+        version:  None,
+        priority: 5,
+        weight:   0.5,
+        outputs:  vec!["out".to_string(), "man".to_string()],
+        kind:     Kind::Library,
+      })
+      .expect("Failed to serialize");
+
+    assert_eq!(value.value_type(), ValueType::Attrs);
+    assert_eq!(
+      value.get_attr("name").unwrap().as_string().unwrap(),
+      "hello"
+    );
+    assert_eq!(
+      value.get_attr("version").unwrap().value_type(),
+      ValueType::Null
+    );
+    assert_eq!(value.get_attr("priority").unwrap().as_int().unwrap(), 5);
+    assert_eq!(value.get_attr("outputs").unwrap().list_len().unwrap(), 2);
+    assert_eq!(
+      value.get_attr("kind").unwrap().as_string().unwrap(),
+      "Library"
+    );
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_value_round_trip() {
+    let state = setup();
+    let original = Package {
+      name:     "tool".to_string(),
+      version:  Some("1.0".to_string()),
+      priority: -3,
+      weight:   2.25,
+      outputs:  vec![],
+      kind:     Kind::Tool {
+        bin: "tool".to_string(),
+      },
+    };
+
+    let value = state.to_value(&original).expect("Failed to serialize");
+    let decoded: Package = from_value(&value).expect("Failed to deserialize");
+    assert_eq!(decoded, original);
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_value_as_call_argument() {
+    let state = setup();
+    let f = state
+      .eval_from_string("{ a, b }: a + b", "<eval>")
+      .expect("Failed to evaluate function");
+    let args = state
+      .to_value(&HashMap::from([("a", 40), ("b", 2)]))
+      .expect("Failed to serialize");
+
+    assert_eq!(f.call(&args).unwrap().as_int().unwrap(), 42);
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_value_u64_overflow() {
+    let state = setup();
+    assert!(state.to_value(&u64::MAX).is_err());
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_value_bytes_round_trip() {
+    let state = setup();
+    let original = Bytes(vec![0, 1, 0x7F, 0x80, 0xFF]);
+    let value = state.to_value(&original).expect("Failed to serialize");
+    assert_eq!(value.value_type(), ValueType::List);
+    let decoded: Bytes = from_value(&value).expect("Failed to deserialize");
+    assert_eq!(decoded, original);
+
+    // Strings, including non-UTF-8 ones, deserialize as their bytes.
+    let value = state.make_string_bytes(b"\xffab").unwrap();
+    let decoded: Bytes = from_value(&value).expect("Failed to deserialize");
+    assert_eq!(decoded, Bytes(b"\xffab".to_vec()));
+
+    let value = state.eval_from_string("[ 256 ]", "<eval>").unwrap();
+    assert!(from_value::<Bytes>(&value).is_err());
+  }
+}
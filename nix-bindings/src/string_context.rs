@@ -0,0 +1,398 @@
+//! String context: the store paths and derivations a string depends on.
+//!
+//! Nix tracks, for every string, which store objects it was built from, so
+//! that a derivation using the string depends on them. [`StringContext`]
+//! reads and writes that context as it is, without realising (building)
+//! anything, which is what the C API's `nix_string_realise` would do.
+
+#![cfg(feature = "shim")]
+
+use std::{
+  collections::{BTreeSet, btree_set},
+  ffi::CString,
+  fmt,
+  os::raw::{c_char, c_uint, c_void},
+  str::FromStr,
+};
+
+use crate::{
+  Error,
+  EvalState,
+  Result,
+  Value,
+  ValueType,
+  error::check_err,
+  sys,
+};
+
+/// One element of a string's context.
+///
+/// Displays as, and parses from, Nix's encoding of context elements:
+/// `/nix/store/...` for [`Opaque`](Self::Opaque), `=/nix/store/....drv` for
+/// [`DrvDeep`](Self::DrvDeep) and `!out!/nix/store/....drv` for
+/// [`Built`](Self::Built).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(into = "String", try_from = "String")
+)]
+pub enum ContextElem {
+  /// A store path, e.g. from interpolating a path added to the store.
+  Opaque(String),
+  /// A derivation together with the closure of everything it needs to
+  /// build, as carried by its `drvPath`.
+  DrvDeep(String),
+  /// One output of a derivation, as carried by its `outPath`.
+  Built {
+    /// The derivation's store path. For an output of a derivation that is
+    /// itself the output of another derivation (dynamic derivations), this
+    /// is that output, encoded like a context element.
+    drv_path: String,
+    /// Output name, e.g. `out`.
+    output:   String,
+  },
+}
+
+impl ContextElem {
+  /// Parse an encoded context element.
+  ///
+  /// # Errors
+  ///
+  /// Returns [`Error::Conversion`] if `s` is empty or a `!` output name is
+  /// not closed.
+  pub fn parse(s: &str) -> Result<Self> {
+    if let Some(drv_path) = s.strip_prefix('=') {
+      return Ok(ContextElem::DrvDeep(drv_path.to_string()));
+    }
+    if let Some(rest) = s.strip_prefix('!') {
+      let Some((output, drv_path)) = rest.split_once('!') else {
+        return Err(malformed(format!(
+          "string context element '{s}' has no closing '!'"
+        )));
+      };
+      return Ok(ContextElem::Built {
+        drv_path: drv_path.to_string(),
+        output:   output.to_string(),
+      });
+    }
+    if s.is_empty() {
+      return Err(malformed("empty string context element".into()));
+    }
+    Ok(ContextElem::Opaque(s.to_string()))
+  }
+}
+
+fn malformed(message: String) -> Error {
+  Error::Conversion {
+    path: String::new(),
+    message,
+  }
+}
+
+impl fmt::Display for ContextElem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ContextElem::Opaque(path) => f.write_str(path),
+      ContextElem::DrvDeep(drv_path) => write!(f, "={drv_path}"),
+      ContextElem::Built { drv_path, output } => {
+        write!(f, "!{output}!{drv_path}")
+      },
+    }
+  }
+}
+
+impl FromStr for ContextElem {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    Self::parse(s)
+  }
+}
+
+impl From<ContextElem> for String {
+  fn from(elem: ContextElem) -> Self {
+    elem.to_string()
+  }
+}
+
+impl TryFrom<String> for ContextElem {
+  type Error = Error;
+
+  fn try_from(s: String) -> Result<Self> {
+    Self::parse(&s)
+  }
+}
+
+/// The context of a string: a set of [`ContextElem`]s, empty for plain
+/// strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(transparent)
+)]
+pub struct StringContext(BTreeSet<ContextElem>);
+
+impl StringContext {
+  /// An empty context.
+  #[must_use]
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add an element; returns whether it was new.
+  pub fn insert(&mut self, elem: ContextElem) -> bool {
+    self.0.insert(elem)
+  }
+
+  /// Whether `elem` is part of this context.
+  #[must_use]
+  pub fn contains(&self, elem: &ContextElem) -> bool {
+    self.0.contains(elem)
+  }
+
+  /// Number of elements.
+  #[must_use]
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  /// Whether there are no elements.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// The elements, in order.
+  pub fn iter(&self) -> btree_set::Iter<'_, ContextElem> {
+    self.0.iter()
+  }
+}
+
+impl FromIterator<ContextElem> for StringContext {
+  fn from_iter<I: IntoIterator<Item = ContextElem>>(iter: I) -> Self {
+    StringContext(iter.into_iter().collect())
+  }
+}
+
+impl Extend<ContextElem> for StringContext {
+  fn extend<I: IntoIterator<Item = ContextElem>>(&mut self, iter: I) {
+    self.0.extend(iter);
+  }
+}
+
+impl IntoIterator for StringContext {
+  type IntoIter = btree_set::IntoIter<ContextElem>;
+  type Item = ContextElem;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.0.into_iter()
+  }
+}
+
+impl<'c> IntoIterator for &'c StringContext {
+  type IntoIter = btree_set::Iter<'c, ContextElem>;
+  type Item = &'c ContextElem;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.0.iter()
+  }
+}
+
+impl Value<'_> {
+  /// The context of this string, read without realising it.
+  ///
+  /// Unlike [`as_string_with_context`](Self::as_string_with_context), this
+  /// builds nothing and keeps derivations and their outputs apart from
+  /// plain store paths.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the value is not a string or forcing fails.
+  pub fn string_context(&self) -> Result<StringContext> {
+    self.force_shared()?;
+    if self.value_type() != ValueType::String {
+      return Err(Error::InvalidType {
+        expected: "string",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    // SAFETY: context and value are valid and the value is a forced string
+    unsafe { read_context(self.state.context.as_ptr(), self.inner.as_ptr()) }
+  }
+}
+
+impl EvalState {
+  /// Create a string value carrying `context`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if `s` or an element contains a NUL byte, an element
+  /// is not a valid store path, or value creation fails.
+  pub fn make_string_with_context(
+    &self,
+    s: &str,
+    context: &StringContext,
+  ) -> Result<Value<'_>> {
+    let v = self.alloc_value()?;
+    // SAFETY: context, state and value are valid.
+    unsafe {
+      init_string_with_context(
+        self.context.as_ptr(),
+        self.as_ptr(),
+        v.inner.as_ptr(),
+        s,
+        context,
+      )?;
+    }
+    Ok(v)
+  }
+}
+
+/// Initialise `value` as the string `s` with `context`.
+///
+/// # Safety
+///
+/// `ctx`, `state` and `value` must be valid.
+pub(crate) unsafe fn init_string_with_context(
+  ctx: *mut sys::nix_c_context,
+  state: *mut sys::EvalState,
+  value: *mut sys::nix_value,
+  s: &str,
+  context: &StringContext,
+) -> Result<()> {
+  let s_c = CString::new(s)?;
+  let elems = context
+    .iter()
+    .map(|elem| CString::new(elem.to_string()))
+    .collect::<std::result::Result<Vec<_>, _>>()?;
+  let ptrs: Vec<_> = elems.iter().map(|elem| elem.as_ptr()).collect();
+  // SAFETY: the caller guarantees the pointers; the strings outlive the
+  // call.
+  unsafe {
+    check_err(
+      ctx,
+      sys::nix_init_string_with_context(
+        ctx,
+        state,
+        value,
+        s_c.as_ptr(),
+        ptrs.as_ptr(),
+        ptrs.len(),
+      ),
+    )
+  }
+}
+
+/// Read the context of a forced string value.
+///
+/// # Safety
+///
+/// `ctx` and `value` must be valid, and `value` a forced string.
+pub(crate) unsafe fn read_context(
+  ctx: *mut sys::nix_c_context,
+  value: *mut sys::nix_value,
+) -> Result<StringContext> {
+  let mut elems: Vec<String> = Vec::new();
+  // SAFETY: the caller guarantees the pointers; the callback only runs
+  // during the call.
+  unsafe {
+    check_err(
+      ctx,
+      sys::nix_get_string_context(
+        ctx,
+        value,
+        Some(push_string),
+        (&mut elems as *mut Vec<String>).cast::<c_void>(),
+      ),
+    )?;
+  }
+  elems.iter().map(|elem| ContextElem::parse(elem)).collect()
+}
+
+unsafe extern "C" fn push_string(
+  start: *const c_char,
+  n: c_uint,
+  user_data: *mut c_void,
+) {
+  let elems = unsafe { &mut *user_data.cast::<Vec<String>>() };
+  if !start.is_null() {
+    let bytes =
+      unsafe { std::slice::from_raw_parts(start.cast::<u8>(), n as usize) };
+    elems.push(String::from_utf8_lossy(bytes).into_owned());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  fn test_context_elem_parse() {
+    for (encoded, elem) in [
+      (
+        "/nix/store/a-x",
+        ContextElem::Opaque("/nix/store/a-x".into()),
+      ),
+      (
+        "=/nix/store/a-x.drv",
+        ContextElem::DrvDeep("/nix/store/a-x.drv".into()),
+      ),
+      ("!dev!/nix/store/a-x.drv", ContextElem::Built {
+        drv_path: "/nix/store/a-x.drv".into(),
+        output:   "dev".into(),
+      }),
+    ] {
+      assert_eq!(ContextElem::parse(encoded).unwrap(), elem);
+      assert_eq!(elem.to_string(), encoded);
+    }
+    for encoded in ["!out/nix/store/a-x.drv", ""] {
+      let err = ContextElem::parse(encoded).unwrap_err();
+      assert!(matches!(err, Error::Conversion { .. }), "{err}");
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_string_context() {
+    let state = setup();
+    let value = state
+      .eval_from_string(
+        "let d = derivation { name = \"d\"; system = \"x\"; builder = \"b\"; \
+         }; in \"${d} ${d.drvPath}\"",
+        "<eval>",
+      )
+      .expect("Failed to evaluate");
+    let context = value.string_context().expect("Failed to read context");
+    assert_eq!(context.len(), 2);
+    let mut elems = context.iter();
+    let Some(ContextElem::DrvDeep(drv_path)) = elems.next() else {
+      panic!("expected a deep derivation context, got {context:?}");
+    };
+    assert_eq!(
+      elems.next(),
+      Some(&ContextElem::Built {
+        drv_path: drv_path.clone(),
+        output:   "out".into(),
+      })
+    );
+
+    let s = value.as_string().unwrap();
+    let copy = state
+      .make_string_with_context(&s, &context)
+      .expect("Failed to make string");
+    assert_eq!(copy.string_context().unwrap(), context);
+    assert!(
+      state
+        .make_string("plain")
+        .unwrap()
+        .string_context()
+        .unwrap()
+        .is_empty()
+    );
+  }
+}
@@ -0,0 +1,17 @@
+//! Fixtures shared by the unit tests.
+
+use std::sync::Arc;
+
+use crate::{Context, EvalState, EvalStateBuilder, Store};
+
+/// A builder on a fresh context and the default store.
+pub(crate) fn builder() -> EvalStateBuilder {
+  let ctx = Arc::new(Context::new().expect("Failed to create context"));
+  let store = Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+  EvalStateBuilder::new(&store).expect("Failed to create builder")
+}
+
+/// An evaluator with the default settings.
+pub(crate) fn setup() -> EvalState {
+  builder().build().expect("Failed to build state")
+}
@@ -3,7 +3,12 @@
 
 #![cfg(feature = "expr")]
 
-use std::{ffi::CStr, fmt, ptr::NonNull, sync::Arc};
+use std::{
+  ffi::{CStr, OsString},
+  fmt,
+  ptr::NonNull,
+  sync::Arc,
+};
 
 use crate::{
   Error,
@@ -80,6 +85,24 @@ impl fmt::Display for ValueType {
   }
 }
 
+/// How [`Value::as_string_with`] and [`NixValueOps::as_string_with`] treat
+/// a string's context.
+///
+/// [`NixValueOps::as_string_with`]: crate::NixValueOps::as_string_with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringReadMode {
+  /// Realise the context first, building or substituting every store path
+  /// and derivation output it refers to.
+  Realise,
+  /// Return the contents as they are and leave the store alone.
+  #[default]
+  NoRealise,
+  /// Like [`NoRealise`](Self::NoRealise), but fail if the string has any
+  /// context. Without the `shim` feature the error cannot list the context,
+  /// and the check costs an evaluation of `builtins.hasContext`.
+  RejectContext,
+}
+
 /// A Nix value.
 ///
 /// This represents any value in the Nix language, including primitives,
@@ -90,7 +113,9 @@ pub struct Value<'a> {
   pub(crate) state: &'a EvalState,
 }
 
-impl value_ops::NixValueRaw for Value<'_> {
+impl<'a> value_ops::NixValueRaw for Value<'a> {
+  type Child = Value<'a>;
+
   fn raw_ctx(&self) -> *mut sys::nix_c_context {
     // SAFETY: the wrapper holds the context alive via Arc.
     unsafe { self.state.context.as_ptr() }
@@ -104,6 +129,13 @@ impl value_ops::NixValueRaw for Value<'_> {
   fn raw_inner(&self) -> *mut sys::nix_value {
     self.inner.as_ptr()
   }
+
+  unsafe fn wrap_child(&self, ptr: NonNull<sys::nix_value>) -> Value<'a> {
+    Value {
+      inner: ptr,
+      state: self.state,
+    }
+  }
 }
 
 impl Value<'_> {
@@ -127,7 +159,7 @@ impl Value<'_> {
   /// Returns an error if evaluation fails.
   pub fn force_deep(&mut self) -> Result<()> {
     // SAFETY: context, state, and value are valid
-    unsafe {
+    self.state.interruptible(|| unsafe {
       check_err(
         self.state.context.as_ptr(),
         sys::nix_value_force_deep(
@@ -136,7 +168,7 @@ impl Value<'_> {
           self.inner.as_ptr(),
         ),
       )
-    }
+    })
   }
 
   /// Get the type of this value.
@@ -181,6 +213,19 @@ impl Value<'_> {
     s
   }
 
+  /// The attribute set or list this value holds, shared between values
+  /// that hold the same one; 0 for anything else, and always 0 without the
+  /// `shim` feature.
+  pub(crate) fn identity(&self) -> usize {
+    #[cfg(feature = "shim")]
+    // SAFETY: context and value are valid.
+    unsafe {
+      sys::nix_value_identity(self.state.context.as_ptr(), self.inner.as_ptr())
+    }
+    #[cfg(not(feature = "shim"))]
+    0
+  }
+
   /// Force this value via a shared reference.
   ///
   /// Used internally by the `as_*` accessors. The Nix C API mutates the
@@ -188,7 +233,7 @@ impl Value<'_> {
   /// the wrapper itself is not changed, so `&self` is sound.
   pub(crate) fn force_shared(&self) -> Result<()> {
     // SAFETY: context, state, and value are valid
-    unsafe {
+    self.state.interruptible(|| unsafe {
       check_err(
         self.state.context.as_ptr(),
         sys::nix_value_force(
@@ -197,7 +242,7 @@ impl Value<'_> {
           self.inner.as_ptr(),
         ),
       )
-    }
+    })
   }
 
   /// Convert this value to an integer. Forces the value first.
@@ -260,14 +305,29 @@ impl Value<'_> {
     })
   }
 
-  /// Convert this value to a string.
+  /// Convert this value to a string. Forces the value first.
   ///
-  /// Realises any string context. Forces the value first.
+  /// Does not realise the string's context, so reading e.g. an `outPath`
+  /// never builds anything; use [`as_string_with`](Self::as_string_with)
+  /// for other modes.
   ///
   /// # Errors
   ///
   /// Returns an error if forcing fails or the value is not a string.
   pub fn as_string(&self) -> Result<String> {
+    self.as_string_with(StringReadMode::NoRealise)
+  }
+
+  /// Convert this value to a string, treating its context as `mode` says.
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the value is not a string,
+  /// realisation fails, or `mode` is
+  /// [`RejectContext`](StringReadMode::RejectContext) and the string has
+  /// context.
+  pub fn as_string_with(&self, mode: StringReadMode) -> Result<String> {
     self.force_shared()?;
     if self.value_type() != ValueType::String {
       return Err(Error::InvalidType {
@@ -275,48 +335,78 @@ impl Value<'_> {
         actual:   self.value_type().to_string(),
       });
     }
-
     // SAFETY: context, state, and value are valid; type is checked
-    let realised_str = unsafe {
-      sys::nix_string_realise(
+    unsafe {
+      value_ops::read_string(
         self.state.context.as_ptr(),
         self.state.as_ptr(),
         self.inner.as_ptr(),
-        false,
+        mode,
       )
-    };
-
-    if realised_str.is_null() {
-      return Err(Error::NullPointer);
     }
+  }
 
-    let buffer_start =
-      unsafe { sys::nix_realised_string_get_buffer_start(realised_str) };
-    let buffer_size =
-      unsafe { sys::nix_realised_string_get_buffer_size(realised_str) };
+  /// Return a string's raw bytes without realising its context. Nix strings
+  /// need not be valid UTF-8 (e.g. `builtins.readFile` on a binary file).
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the value is not a string.
+  pub fn as_bytes(&self) -> Result<Vec<u8>> {
+    self.as_bytes_with(StringReadMode::NoRealise)
+  }
 
-    if buffer_start.is_null() {
-      unsafe { sys::nix_realised_string_free(realised_str) };
-      return Err(Error::NullPointer);
+  /// Return a string's raw bytes, treating its context as `mode` says.
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the value is not a string,
+  /// realisation fails, or `mode` is
+  /// [`RejectContext`](StringReadMode::RejectContext) and the string has
+  /// context.
+  pub fn as_bytes_with(&self, mode: StringReadMode) -> Result<Vec<u8>> {
+    self.force_shared()?;
+    if self.value_type() != ValueType::String {
+      return Err(Error::InvalidType {
+        expected: "string",
+        actual:   self.value_type().to_string(),
+      });
     }
+    // SAFETY: context, state, and value are valid; type is checked
+    unsafe {
+      value_ops::read_bytes(
+        self.state.context.as_ptr(),
+        self.state.as_ptr(),
+        self.inner.as_ptr(),
+        mode,
+      )
+    }
+  }
 
-    let bytes = unsafe {
-      std::slice::from_raw_parts(buffer_start.cast::<u8>(), buffer_size)
-    };
-    let string = std::str::from_utf8(bytes)
-      .map_err(|_| Error::Unknown("Invalid UTF-8 in string".to_string()))?
-      .to_owned();
-
-    unsafe { sys::nix_realised_string_free(realised_str) };
-
-    Ok(string)
+  /// Convert a string to an [`OsString`] without realising its context. On
+  /// Unix every byte string converts; elsewhere it must be valid UTF-8.
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the value is not a string, or the
+  /// bytes do not form a valid `OsString` on this platform.
+  pub fn as_os_string(&self) -> Result<OsString> {
+    value_ops::bytes_to_os_string(self.as_bytes()?)
   }
 
   /// Convert this value to a string and return its store-path context.
   ///
-  /// Extended form of [`as_string`](Self::as_string) returning both content
-  /// and any store paths embedded in the string's context. For ordinary
-  /// strings the context vector is empty.
+  /// Extended form of [`as_string_with`](Self::as_string_with) in
+  /// [`Realise`](StringReadMode::Realise) mode, returning both content and
+  /// any store paths embedded in the string's context. For ordinary strings
+  /// the context vector is empty.
+  ///
+  /// This realises the context, building every derivation it refers to.
+  /// With the `shim` feature, `Value::string_context` reads the context
+  /// as it is instead.
   ///
   /// # Errors
   ///
@@ -439,7 +529,7 @@ impl Value<'_> {
   pub fn call(&self, arg: &Value<'_>) -> Result<Value<'_>> {
     let result = self.state.alloc_value()?;
     // SAFETY: context, state, function value, arg value, and result are valid
-    unsafe {
+    self.state.interruptible(|| unsafe {
       check_err(
         self.state.context.as_ptr(),
         sys::nix_value_call(
@@ -449,8 +539,8 @@ impl Value<'_> {
           arg.inner.as_ptr(),
           result.inner.as_ptr(),
         ),
-      )?;
-    }
+      )
+    })?;
     Ok(result)
   }
 
@@ -464,7 +554,7 @@ impl Value<'_> {
     let mut arg_ptrs: Vec<*mut sys::nix_value> =
       args.iter().map(|a| a.inner.as_ptr()).collect();
     // SAFETY: context, state, fn, args array, and result are valid
-    unsafe {
+    self.state.interruptible(|| unsafe {
       check_err(
         self.state.context.as_ptr(),
         sys::nix_value_call_multi(
@@ -475,8 +565,8 @@ impl Value<'_> {
           arg_ptrs.as_mut_ptr(),
           result.inner.as_ptr(),
         ),
-      )?;
-    }
+      )
+    })?;
     Ok(result)
   }
 
@@ -529,69 +619,6 @@ impl Value<'_> {
     }
     Ok(result)
   }
-
-  /// Format this value as Nix syntax.
-  ///
-  /// Forces the value first and recursively renders attribute sets and
-  /// lists. Functions, thunks, and external values render as opaque
-  /// placeholders (`<lambda>`, `<thunk>`, `<external>`) the same way
-  /// `nix-instantiate --eval` does.
-  ///
-  /// # Errors
-  ///
-  /// Returns an error if forcing fails or any nested value cannot be
-  /// rendered.
-  pub fn to_nix_string(&self) -> Result<String> {
-    self.force_shared()?;
-    match self.value_type() {
-      ValueType::Int => Ok(self.as_int()?.to_string()),
-      ValueType::Float => Ok(self.as_float()?.to_string()),
-      ValueType::Bool => {
-        Ok(if self.as_bool()? { "true" } else { "false" }.to_string())
-      },
-      ValueType::String => {
-        let s = self.as_string()?;
-        Ok(format!(
-          "\"{}\"",
-          s.replace('\\', "\\\\").replace('"', "\\\"")
-        ))
-      },
-      ValueType::Null => Ok("null".to_string()),
-      ValueType::Path => {
-        Ok(
-          self
-            .as_path()
-            .map_or_else(|_| "<path>".to_string(), |p| p.display().to_string()),
-        )
-      },
-      ValueType::Attrs => {
-        let mut parts = Vec::new();
-        for entry in self.attrs()? {
-          let (k, v) = entry?;
-          parts.push(format!("{k} = {};", v.to_nix_string()?));
-        }
-        if parts.is_empty() {
-          Ok("{ }".to_string())
-        } else {
-          Ok(format!("{{ {} }}", parts.join(" ")))
-        }
-      },
-      ValueType::List => {
-        let mut parts = Vec::new();
-        for entry in self.list_iter()? {
-          parts.push(entry?.to_nix_string()?);
-        }
-        if parts.is_empty() {
-          Ok("[ ]".to_string())
-        } else {
-          Ok(format!("[ {} ]", parts.join(" ")))
-        }
-      },
-      ValueType::Function => Ok("<lambda>".to_string()),
-      ValueType::Thunk => Ok("<thunk>".to_string()),
-      ValueType::External => Ok("<external>".to_string()),
-    }
-  }
 }
 
 impl Drop for Value<'_> {
@@ -11,18 +11,47 @@
 //! }
 //! ```
 
-use std::ffi::CStr;
+use std::{
+  ffi::{CStr, CString, OsString},
+  ptr::NonNull,
+};
 
-use crate::{Error, Result, ValueType, check_err, sys};
+use crate::{
+  Error,
+  FromNix,
+  Result,
+  StringReadMode,
+  ValueType,
+  check_err,
+  error::{checked_bytes_from_callback, checked_string_from_callback},
+  sys,
+};
 
 pub(crate) mod sealed {
+  use std::ptr::NonNull;
+
   use super::sys;
 
   pub trait NixValueRaw {
+    /// Owned wrapper used for values fetched out of this one (attribute
+    /// values, list elements).
+    type Child: NixValueRaw;
+
     fn raw_ctx(&self) -> *mut sys::nix_c_context;
     fn raw_state(&self) -> *mut sys::EvalState;
     fn raw_inner(&self) -> *mut sys::nix_value;
+
+    /// Wrap a child pointer returned by the C API.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must carry a GC reference owned by the caller and belong to the
+    /// same evaluator as `self`. The returned wrapper releases it on drop.
+    unsafe fn wrap_child(&self, ptr: NonNull<sys::nix_value>) -> Self::Child;
   }
+
+  /// Seals [`NixAlloc`](crate::NixAlloc).
+  pub trait Sealed {}
 }
 
 // Re-exported under the module namespace so other crate modules can
@@ -38,6 +67,10 @@ pub(crate) use sealed::NixValueRaw;
 /// [`primop::PrimOpValue`](crate::primop::PrimOpValue). All accessors force
 /// the value first, mirroring `nix-instantiate`-style semantics: a lazy
 /// attribute that resolves to an int is reported as an int, not a thunk.
+///
+/// Values fetched out of an attribute set or list are returned as
+/// `Self::Child`: [`Value`](crate::Value) for values, and
+/// [`PrimOpValue`](crate::primop::PrimOpValue) inside a primop callback.
 pub trait NixValueOps: sealed::NixValueRaw {
   /// Return the [`ValueType`] of this value.
   ///
@@ -122,7 +155,7 @@ pub trait NixValueOps: sealed::NixValueRaw {
     Ok(unsafe { sys::nix_get_bool(self.raw_ctx(), self.raw_inner()) })
   }
 
-  /// Extract as a UTF-8 string, realising any string context.  Forces
+  /// Extract as a UTF-8 string without realising its context.  Forces
   /// the value first.
   ///
   /// # Errors
@@ -130,6 +163,19 @@ pub trait NixValueOps: sealed::NixValueRaw {
   /// Returns an error if forcing fails, the resolved value is not a
   /// string, or the string contains invalid UTF-8.
   fn as_string(&self) -> Result<String> {
+    self.as_string_with(StringReadMode::NoRealise)
+  }
+
+  /// Extract as a UTF-8 string, treating its context as `mode` says.
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the resolved value is not a
+  /// string, the string contains invalid UTF-8, realisation fails, or
+  /// `mode` is [`RejectContext`](StringReadMode::RejectContext) and the
+  /// string has context.
+  fn as_string_with(&self, mode: StringReadMode) -> Result<String> {
     self.force()?;
     if self.value_type() != ValueType::String {
       return Err(Error::InvalidType {
@@ -137,40 +183,57 @@ pub trait NixValueOps: sealed::NixValueRaw {
         actual:   self.value_type().to_string(),
       });
     }
-
     // SAFETY: type checked.
-    let realised_str = unsafe {
-      sys::nix_string_realise(
-        self.raw_ctx(),
-        self.raw_state(),
-        self.raw_inner(),
-        false,
-      )
-    };
-
-    if realised_str.is_null() {
-      return Err(Error::NullPointer);
+    unsafe {
+      read_string(self.raw_ctx(), self.raw_state(), self.raw_inner(), mode)
     }
+  }
 
-    let buffer_start =
-      unsafe { sys::nix_realised_string_get_buffer_start(realised_str) };
-    let buffer_size =
-      unsafe { sys::nix_realised_string_get_buffer_size(realised_str) };
+  /// Extract a string's raw bytes without realising its context.  Nix
+  /// strings need not be valid UTF-8 (e.g. `builtins.readFile` on a binary
+  /// file).  Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the resolved value is not a
+  /// string.
+  fn as_bytes(&self) -> Result<Vec<u8>> {
+    self.as_bytes_with(StringReadMode::NoRealise)
+  }
 
-    if buffer_start.is_null() {
-      unsafe { sys::nix_realised_string_free(realised_str) };
-      return Err(Error::NullPointer);
+  /// Extract a string's raw bytes, treating its context as `mode` says.
+  /// Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the resolved value is not a
+  /// string, realisation fails, or `mode` is
+  /// [`RejectContext`](StringReadMode::RejectContext) and the string has
+  /// context.
+  fn as_bytes_with(&self, mode: StringReadMode) -> Result<Vec<u8>> {
+    self.force()?;
+    if self.value_type() != ValueType::String {
+      return Err(Error::InvalidType {
+        expected: "string",
+        actual:   self.value_type().to_string(),
+      });
     }
+    // SAFETY: type checked.
+    unsafe {
+      read_bytes(self.raw_ctx(), self.raw_state(), self.raw_inner(), mode)
+    }
+  }
 
-    let bytes = unsafe {
-      std::slice::from_raw_parts(buffer_start.cast::<u8>(), buffer_size)
-    };
-    let s = std::str::from_utf8(bytes)
-      .map_err(|_| Error::Unknown("Invalid UTF-8 in string".into()))?
-      .to_owned();
-
-    unsafe { sys::nix_realised_string_free(realised_str) };
-    Ok(s)
+  /// Extract a string as an [`OsString`] without realising its context.
+  /// On Unix every byte string converts; elsewhere the string must be valid
+  /// UTF-8.  Forces the value first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the resolved value is not a
+  /// string, or the bytes do not form a valid `OsString` on this platform.
+  fn as_os_string(&self) -> Result<OsString> {
+    bytes_to_os_string(self.as_bytes()?)
   }
 
   /// Extract as a filesystem-path string.  Forces the value first.
@@ -199,6 +262,315 @@ pub trait NixValueOps: sealed::NixValueRaw {
       .map(str::to_owned)
       .map_err(|_| Error::Unknown("Invalid UTF-8 in path".into()))
   }
+  /// Return the names of this attribute set's attributes, in the
+  /// evaluator's (sorted) order.  Forces the value first, but none of the
+  /// attribute values.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the resolved value is not an
+  /// attribute set.
+  fn attr_names(&self) -> Result<Vec<String>> {
+    self.force()?;
+    if self.value_type() != ValueType::Attrs {
+      return Err(Error::InvalidType {
+        expected: "attrs",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    // SAFETY: type checked.
+    let count =
+      unsafe { sys::nix_get_attrs_size(self.raw_ctx(), self.raw_inner()) };
+    let mut names = Vec::with_capacity(count as usize);
+    for i in 0..count {
+      // SAFETY: index is below the attribute count; the name is interned
+      // by the evaluator and outlives the copy.
+      let name = unsafe {
+        sys::nix_get_attr_name_byidx(
+          self.raw_ctx(),
+          self.raw_inner(),
+          self.raw_state(),
+          i,
+        )
+      };
+      if name.is_null() {
+        return Err(Error::NullPointer);
+      }
+      names.push(
+        unsafe { CStr::from_ptr(name) }
+          .to_string_lossy()
+          .into_owned(),
+      );
+    }
+    Ok(names)
+  }
+
+  /// Look up the attribute `name` without forcing its value.
+  ///
+  /// Forces this value to an attribute set first. Returns `Ok(None)` when
+  /// the attribute is absent.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails, the resolved value is not an
+  /// attribute set, or `name` contains a NUL byte.
+  fn get_attr_lazy(&self, name: &str) -> Result<Option<Self::Child>> {
+    self.force()?;
+    if self.value_type() != ValueType::Attrs {
+      return Err(Error::InvalidType {
+        expected: "attrs",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    let name_c = CString::new(name)?;
+    // SAFETY: type checked; the name is a valid C string.
+    let present = unsafe {
+      sys::nix_has_attr_byname(
+        self.raw_ctx(),
+        self.raw_inner(),
+        self.raw_state(),
+        name_c.as_ptr(),
+      )
+    };
+    if !present {
+      return Ok(None);
+    }
+    // SAFETY: the attribute exists; the lazy getter returns an owned
+    // (GC-reffed) pointer without forcing it.
+    let ptr = unsafe {
+      sys::nix_get_attr_byname_lazy(
+        self.raw_ctx(),
+        self.raw_inner(),
+        self.raw_state(),
+        name_c.as_ptr(),
+      )
+    };
+    let ptr = NonNull::new(ptr).ok_or(Error::NullPointer)?;
+    // SAFETY: ptr is owned by us and comes from this evaluator.
+    Ok(Some(unsafe { self.wrap_child(ptr) }))
+  }
+
+  /// Return this list's elements without forcing them.  Forces the list
+  /// itself first.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails or the resolved value is not a
+  /// list.
+  fn list_items(&self) -> Result<Vec<Self::Child>> {
+    self.force()?;
+    if self.value_type() != ValueType::List {
+      return Err(Error::InvalidType {
+        expected: "list",
+        actual:   self.value_type().to_string(),
+      });
+    }
+    // SAFETY: type checked.
+    let len =
+      unsafe { sys::nix_get_list_size(self.raw_ctx(), self.raw_inner()) };
+    (0..len)
+      .map(|i| {
+        // SAFETY: index is below the list length; the lazy getter returns
+        // an owned (GC-reffed) pointer without forcing the element.
+        let ptr = unsafe {
+          sys::nix_get_list_byidx_lazy(
+            self.raw_ctx(),
+            self.raw_inner(),
+            self.raw_state(),
+            i,
+          )
+        };
+        let ptr = NonNull::new(ptr).ok_or(Error::NullPointer)?;
+        // SAFETY: ptr is owned by us and comes from this evaluator.
+        Ok(unsafe { self.wrap_child(ptr) })
+      })
+      .collect()
+  }
+
+  /// Convert this value into a Rust type via [`FromNix`].
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if the value does not match the shape of `T`.
+  fn extract<T: FromNix<Self>>(&self) -> Result<T>
+  where
+    Self: Sized,
+  {
+    T::from_nix(self)
+  }
 }
 
 impl<T: sealed::NixValueRaw> NixValueOps for T {}
+
+/// Read a forced string value's contents as `mode` says.
+///
+/// # Safety
+///
+/// `ctx`, `state` and `value` must be valid, and `value` a forced string.
+pub(crate) unsafe fn read_string(
+  ctx: *mut sys::nix_c_context,
+  state: *mut sys::EvalState,
+  value: *mut sys::nix_value,
+  mode: StringReadMode,
+) -> Result<String> {
+  if mode == StringReadMode::NoRealise {
+    // SAFETY: the caller guarantees the pointers.
+    return unsafe {
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_get_string(ctx, value, callback, user_data)
+      })
+    };
+  }
+  // SAFETY: the caller guarantees the pointers.
+  let bytes = unsafe { read_bytes(ctx, state, value, mode) }?;
+  String::from_utf8(bytes)
+    .map_err(|_| Error::Unknown("Invalid UTF-8 in string".into()))
+}
+
+/// Read a forced string value's bytes as `mode` says.
+///
+/// # Safety
+///
+/// `ctx`, `state` and `value` must be valid, and `value` a forced string.
+pub(crate) unsafe fn read_bytes(
+  ctx: *mut sys::nix_c_context,
+  state: *mut sys::EvalState,
+  value: *mut sys::nix_value,
+  mode: StringReadMode,
+) -> Result<Vec<u8>> {
+  match mode {
+    StringReadMode::Realise => {},
+    StringReadMode::NoRealise => {
+      // SAFETY: the caller guarantees the pointers.
+      return unsafe {
+        checked_bytes_from_callback(ctx, |callback, user_data| {
+          sys::nix_get_string(ctx, value, callback, user_data)
+        })
+      };
+    },
+    #[cfg(not(feature = "shim"))]
+    StringReadMode::RejectContext => {
+      // SAFETY: the caller guarantees the pointers.
+      if unsafe { has_context(ctx, state, value)? } {
+        return Err(Error::Conversion {
+          path:    String::new(),
+          message: "string has context".into(),
+        });
+      }
+      // SAFETY: as above.
+      return unsafe {
+        read_bytes(ctx, state, value, StringReadMode::NoRealise)
+      };
+    },
+    #[cfg(feature = "shim")]
+    StringReadMode::RejectContext => {
+      // SAFETY: the caller guarantees the pointers.
+      let context = unsafe { crate::string_context::read_context(ctx, value)? };
+      if !context.is_empty() {
+        let elems: Vec<_> = context.iter().map(ToString::to_string).collect();
+        return Err(Error::Conversion {
+          path:    String::new(),
+          message: format!("string has context: {}", elems.join(", ")),
+        });
+      }
+      // SAFETY: as above.
+      return unsafe {
+        read_bytes(ctx, state, value, StringReadMode::NoRealise)
+      };
+    },
+  }
+
+  // SAFETY: the caller guarantees the pointers.
+  let realised_str =
+    unsafe { sys::nix_string_realise(ctx, state, value, false) };
+  if realised_str.is_null() {
+    return Err(Error::NullPointer);
+  }
+
+  let buffer_start =
+    unsafe { sys::nix_realised_string_get_buffer_start(realised_str) };
+  let buffer_size =
+    unsafe { sys::nix_realised_string_get_buffer_size(realised_str) };
+
+  if buffer_start.is_null() {
+    unsafe { sys::nix_realised_string_free(realised_str) };
+    return Err(Error::NullPointer);
+  }
+
+  let bytes = unsafe {
+    std::slice::from_raw_parts(buffer_start.cast::<u8>(), buffer_size)
+  }
+  .to_vec();
+
+  unsafe { sys::nix_realised_string_free(realised_str) };
+  Ok(bytes)
+}
+
+/// Whether a string value has context, as `builtins.hasContext` says.
+/// Without the shim the context itself cannot be read.
+///
+/// # Safety
+///
+/// `ctx`, `state` and `value` must be valid, and `value` a forced string.
+#[cfg(not(feature = "shim"))]
+unsafe fn has_context(
+  ctx: *mut sys::nix_c_context,
+  state: *mut sys::EvalState,
+  value: *mut sys::nix_value,
+) -> Result<bool> {
+  /// A value allocated here; its reference is released on drop.
+  struct Owned(*mut sys::nix_c_context, *mut sys::nix_value);
+
+  impl Drop for Owned {
+    fn drop(&mut self) {
+      // SAFETY: the value was allocated by `alloc` and is still referenced.
+      unsafe { sys::nix_value_decref(self.0, self.1) };
+    }
+  }
+
+  let alloc = || {
+    // SAFETY: the caller guarantees the pointers.
+    let value = unsafe { sys::nix_alloc_value(ctx, state) };
+    if value.is_null() {
+      Err(Error::NullPointer)
+    } else {
+      Ok(Owned(ctx, value))
+    }
+  };
+  let function = alloc()?;
+  let result = alloc()?;
+  // SAFETY: the caller guarantees the pointers; the allocated values are
+  // live, and the call forces its result, a Boolean.
+  unsafe {
+    check_err(
+      ctx,
+      sys::nix_expr_eval_from_string(
+        ctx,
+        state,
+        c"builtins.hasContext".as_ptr(),
+        c"/".as_ptr(),
+        function.1,
+      ),
+    )?;
+    check_err(
+      ctx,
+      sys::nix_value_call(ctx, state, function.1, value, result.1),
+    )?;
+    Ok(sys::nix_get_bool(ctx, result.1))
+  }
+}
+
+/// Convert a Nix byte string to an [`OsString`].
+pub(crate) fn bytes_to_os_string(bytes: Vec<u8>) -> Result<OsString> {
+  #[cfg(unix)]
+  {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(OsString::from_vec(bytes))
+  }
+  #[cfg(not(unix))]
+  {
+    String::from_utf8(bytes)
+      .map(OsString::from)
+      .map_err(|_| Error::Unknown("Invalid UTF-8 in string".into()))
+  }
+}
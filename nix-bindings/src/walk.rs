@@ -0,0 +1,383 @@
+//! Depth-first walks over nested values.
+//!
+//! [`Value::walk`] drives a [`ValueVisitor`] through attribute sets and
+//! lists, leaving forcing, depth limits and cycle detection to
+//! [`WalkOptions`].
+
+#![cfg(feature = "expr")]
+
+use crate::{Result, Value, ValueType};
+
+/// What a [`ValueVisitor`] wants the walk to do next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+  /// Carry on, descending into the value just visited.
+  Continue,
+  /// Do not descend into the value just visited; carry on with its
+  /// siblings.
+  Skip,
+  /// End the walk.
+  Stop,
+}
+
+/// Callbacks for [`Value::walk`].
+///
+/// Every method defaults to [`WalkControl::Continue`], so a visitor only
+/// implements what it needs. `depth` is 0 for the value the walk starts at
+/// and grows by one per attribute set or list entered.
+pub trait ValueVisitor<'a> {
+  /// An attribute set is about to be entered. [`WalkControl::Skip`] leaves
+  /// its attributes unvisited.
+  ///
+  /// # Errors
+  ///
+  /// An error ends the walk and is returned from [`Value::walk`].
+  fn visit_attrs_enter(
+    &mut self,
+    value: &Value<'a>,
+    depth: usize,
+  ) -> Result<WalkControl> {
+    let _ = (value, depth);
+    Ok(WalkControl::Continue)
+  }
+
+  /// Attribute `name` of the set being walked. [`WalkControl::Skip`] does
+  /// not descend into `value`.
+  ///
+  /// # Errors
+  ///
+  /// An error ends the walk and is returned from [`Value::walk`].
+  fn visit_attr(
+    &mut self,
+    name: &str,
+    value: &Value<'a>,
+    depth: usize,
+  ) -> Result<WalkControl> {
+    let _ = (name, value, depth);
+    Ok(WalkControl::Continue)
+  }
+
+  /// Element `index` of the list being walked. [`WalkControl::Skip`] does
+  /// not descend into `value`.
+  ///
+  /// # Errors
+  ///
+  /// An error ends the walk and is returned from [`Value::walk`].
+  fn visit_list_item(
+    &mut self,
+    index: usize,
+    value: &Value<'a>,
+    depth: usize,
+  ) -> Result<WalkControl> {
+    let _ = (index, value, depth);
+    Ok(WalkControl::Continue)
+  }
+
+  /// A value that is not descended into: anything but an attribute set or
+  /// list, an unforced thunk, a set or list at
+  /// [`max_depth`](WalkOptions::max_depth) or [`MAX_WALK_DEPTH`], or one
+  /// that contains itself (see
+  /// [`detect_cycles`](WalkOptions::detect_cycles)).
+  ///
+  /// # Errors
+  ///
+  /// An error ends the walk and is returned from [`Value::walk`].
+  fn visit_leaf(
+    &mut self,
+    value: &Value<'a>,
+    depth: usize,
+  ) -> Result<WalkControl> {
+    let _ = (value, depth);
+    Ok(WalkControl::Continue)
+  }
+}
+
+/// The depth at which [`Value::walk`] stops entering attribute sets and
+/// lists, whatever [`WalkOptions::max_depth`] says. The walk recurses per
+/// level, so an infinitely deep lazy value is cut off here instead of
+/// overflowing the stack.
+pub const MAX_WALK_DEPTH: usize = 1024;
+
+/// Options for [`Value::walk`].
+///
+/// Default: force everything, no depth limit, detect cycles.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions {
+  /// Force each value before visiting it. When `false`, values that are
+  /// still thunks are passed to [`ValueVisitor::visit_leaf`] unevaluated,
+  /// so only what is already evaluated is walked.
+  pub force_thunks:  bool,
+  /// Do not enter attribute sets or lists at this depth or deeper; they are
+  /// passed to [`ValueVisitor::visit_leaf`] instead. [`MAX_WALK_DEPTH`]
+  /// applies on top of this.
+  pub max_depth:     Option<usize>,
+  /// Pass an attribute set or list that is already being walked further up
+  /// (e.g. `self` in `rec { self = { inherit self; }; }`) to
+  /// [`ValueVisitor::visit_leaf`] instead of entering it again. Requires
+  /// the `shim` feature; without it nothing is detected.
+  pub detect_cycles: bool,
+}
+
+impl Default for WalkOptions {
+  fn default() -> Self {
+    WalkOptions {
+      force_thunks:  true,
+      max_depth:     None,
+      detect_cycles: true,
+    }
+  }
+}
+
+impl<'a> Value<'a> {
+  /// Walk this value depth first, calling `visitor` for each set, attribute,
+  /// list element and leaf.
+  ///
+  /// Attributes are visited in the evaluator's order and list elements by
+  /// index.
+  ///
+  /// # Errors
+  ///
+  /// Returns the first error raised by forcing or by the visitor. Type
+  /// errors carry the attribute path to the offending value; evaluation
+  /// errors are returned as the evaluator reported them.
+  pub fn walk<V>(&self, visitor: &mut V, options: WalkOptions) -> Result<()>
+  where
+    V: ValueVisitor<'a> + ?Sized,
+  {
+    let mut walker = Walker {
+      visitor,
+      options,
+      ancestors: Vec::new(),
+    };
+    walker.node(self, 0)?;
+    Ok(())
+  }
+}
+
+struct Walker<'v, V: ?Sized> {
+  visitor:   &'v mut V,
+  options:   WalkOptions,
+  /// Identities of the sets and lists being walked, outermost first.
+  ancestors: Vec<usize>,
+}
+
+impl<'a, V: ValueVisitor<'a> + ?Sized> Walker<'_, V> {
+  /// Walk `value`; `Ok(false)` once the visitor has asked to stop.
+  fn node(&mut self, value: &Value<'a>, depth: usize) -> Result<bool> {
+    if self.options.force_thunks {
+      value.force_shared()?;
+    }
+    let container =
+      matches!(value.value_type(), ValueType::Attrs | ValueType::List);
+    let too_deep = depth >= MAX_WALK_DEPTH
+      || self.options.max_depth.is_some_and(|max| depth >= max);
+    let id = if container && self.options.detect_cycles {
+      value.identity()
+    } else {
+      0
+    };
+    if !container || too_deep || (id != 0 && self.ancestors.contains(&id)) {
+      return Ok(self.visitor.visit_leaf(value, depth)? != WalkControl::Stop);
+    }
+
+    self.ancestors.push(id);
+    let result = if value.value_type() == ValueType::Attrs {
+      self.attrs(value, depth)
+    } else {
+      self.list(value, depth)
+    };
+    self.ancestors.pop();
+    result
+  }
+
+  fn attrs(&mut self, value: &Value<'a>, depth: usize) -> Result<bool> {
+    match self.visitor.visit_attrs_enter(value, depth)? {
+      WalkControl::Continue => {},
+      WalkControl::Skip => return Ok(true),
+      WalkControl::Stop => return Ok(false),
+    }
+    for entry in value.attrs()? {
+      let (name, child) = entry?;
+      match self.visitor.visit_attr(&name, &child, depth + 1)? {
+        WalkControl::Continue => {},
+        WalkControl::Skip => continue,
+        WalkControl::Stop => return Ok(false),
+      }
+      if !self.node(&child, depth + 1).map_err(|e| e.in_attr(&name))? {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+
+  fn list(&mut self, value: &Value<'a>, depth: usize) -> Result<bool> {
+    for (index, child) in value.list_iter_lazy()?.enumerate() {
+      let child = child.map_err(|e| e.in_index(index))?;
+      match self.visitor.visit_list_item(index, &child, depth + 1)? {
+        WalkControl::Continue => {},
+        WalkControl::Skip => continue,
+        WalkControl::Stop => return Ok(false),
+      }
+      if !self
+        .node(&child, depth + 1)
+        .map_err(|e| e.in_index(index))?
+      {
+        return Ok(false);
+      }
+    }
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::{Error, test_util::setup};
+
+  /// Records what it sees as `depth:event` strings.
+  #[derive(Default)]
+  struct Recorder {
+    events: Vec<String>,
+    skip:   &'static str,
+    stop:   &'static str,
+  }
+
+  impl<'a> ValueVisitor<'a> for Recorder {
+    fn visit_attrs_enter(
+      &mut self,
+      _value: &Value<'a>,
+      depth: usize,
+    ) -> Result<WalkControl> {
+      self.events.push(format!("{depth}:{{"));
+      Ok(WalkControl::Continue)
+    }
+
+    fn visit_attr(
+      &mut self,
+      name: &str,
+      _value: &Value<'a>,
+      depth: usize,
+    ) -> Result<WalkControl> {
+      self.events.push(format!("{depth}:{name}"));
+      Ok(match name {
+        n if n == self.skip => WalkControl::Skip,
+        n if n == self.stop => WalkControl::Stop,
+        _ => WalkControl::Continue,
+      })
+    }
+
+    fn visit_list_item(
+      &mut self,
+      index: usize,
+      _value: &Value<'a>,
+      depth: usize,
+    ) -> Result<WalkControl> {
+      self.events.push(format!("{depth}:[{index}]"));
+      Ok(WalkControl::Continue)
+    }
+
+    fn visit_leaf(
+      &mut self,
+      value: &Value<'a>,
+      depth: usize,
+    ) -> Result<WalkControl> {
+      self.events.push(format!("{depth}:{}", value.value_type()));
+      Ok(WalkControl::Continue)
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_walk() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{ a = [ 1 { b = 2; } ]; c = throw \"c\"; }", "<eval>")
+      .expect("Failed to evaluate");
+
+    let mut recorder = Recorder {
+      skip: "c",
+      ..Recorder::default()
+    };
+    value
+      .walk(&mut recorder, WalkOptions::default())
+      .expect("Failed to walk");
+    assert_eq!(recorder.events, [
+      "0:{", "1:a", "2:[0]", "2:int", "2:[1]", "2:{", "3:b", "3:int", "1:c"
+    ]);
+
+    let mut recorder = Recorder {
+      skip: "c",
+      ..Recorder::default()
+    };
+    let options = WalkOptions {
+      max_depth: Some(1),
+      ..WalkOptions::default()
+    };
+    value.walk(&mut recorder, options).expect("Failed to walk");
+    assert_eq!(recorder.events, ["0:{", "1:a", "1:list", "1:c"]);
+
+    let mut recorder = Recorder {
+      stop: "a",
+      ..Recorder::default()
+    };
+    value
+      .walk(&mut recorder, WalkOptions::default())
+      .expect("Failed to walk");
+    assert_eq!(recorder.events, ["0:{", "1:a"]);
+
+    let err = value
+      .walk(&mut Recorder::default(), WalkOptions::default())
+      .expect_err("c throws");
+    assert!(matches!(err, Error::EvalError(_)), "{err}");
+  }
+
+  #[test]
+  #[serial]
+  fn test_walk_lazy_and_cycles() {
+    let state = setup();
+    let value = state
+      .eval_from_string("rec { self = { inherit self; }; }", "<eval>")
+      .expect("Failed to evaluate");
+
+    let mut recorder = Recorder::default();
+    let options = WalkOptions {
+      force_thunks: false,
+      ..WalkOptions::default()
+    };
+    value.walk(&mut recorder, options).expect("Failed to walk");
+    assert_eq!(recorder.events, ["0:{", "1:self", "1:thunk"]);
+
+    #[cfg(feature = "shim")]
+    {
+      let mut recorder = Recorder::default();
+      value
+        .walk(&mut recorder, WalkOptions::default())
+        .expect("Failed to walk");
+      assert_eq!(recorder.events, [
+        "0:{", "1:self", "1:{", "2:self", "2:attrs"
+      ]);
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_walk_too_deep() {
+    let state = setup();
+    let value = state
+      .eval_from_string("let f = n: { next = f (n + 1); }; in f 0", "<eval>")
+      .expect("Failed to evaluate");
+
+    let mut recorder = Recorder::default();
+    let options = WalkOptions {
+      detect_cycles: false,
+      ..WalkOptions::default()
+    };
+    value.walk(&mut recorder, options).expect("Failed to walk");
+    assert_eq!(
+      recorder.events.last().map(String::as_str),
+      Some(format!("{MAX_WALK_DEPTH}:attrs").as_str())
+    );
+  }
+}
@@ -0,0 +1,114 @@
+//! XML export matching `builtins.toXML`.
+//!
+//! The document is produced by the evaluator's own `printValueAsXML`, so it
+//! follows the same schema: `<attrs>`, `<list>`, `<derivation>`, functions as
+//! `<function>` with `<attrspat>`, and so on. External values write their own
+//! elements through
+//! [`NixExternal::write_xml`](crate::external::NixExternal::write_xml).
+
+#![cfg(feature = "shim")]
+
+use crate::{Result, Value, error::checked_string_from_callback, sys};
+
+/// Options for [`Value::to_xml_with`].
+///
+/// Default: identical to `builtins.toXML`.
+#[derive(Debug, Clone, Copy)]
+pub struct XmlOptions {
+  /// Force nested values. When `false`, values that are still thunks are
+  /// written as `<unevaluated />`.
+  pub strict:   bool,
+  /// Add `path`, `line` and `column` attributes to attributes and
+  /// functions, as `nix-instantiate --eval --xml` does.
+  pub location: bool,
+}
+
+impl Default for XmlOptions {
+  fn default() -> Self {
+    XmlOptions {
+      strict:   true,
+      location: false,
+    }
+  }
+}
+
+impl Value<'_> {
+  /// Render this value as XML, exactly like `builtins.toXML`.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails.
+  pub fn to_xml(&self) -> Result<String> {
+    self.to_xml_with(XmlOptions::default())
+  }
+
+  /// Render this value as XML with the given options.
+  ///
+  /// # Errors
+  ///
+  /// Returns an error if forcing fails.
+  pub fn to_xml_with(&self, options: XmlOptions) -> Result<String> {
+    // SAFETY: context, state and value are valid
+    unsafe {
+      let ctx = self.state.context.as_ptr();
+      checked_string_from_callback(ctx, |callback, user_data| {
+        sys::nix_value_to_xml(
+          ctx,
+          self.state.as_ptr(),
+          self.inner.as_ptr(),
+          options.strict,
+          options.location,
+          callback,
+          user_data,
+        )
+      })
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serial_test::serial;
+
+  use super::*;
+  use crate::test_util::setup;
+
+  #[test]
+  #[serial]
+  fn test_to_xml_matches_builtin() {
+    let state = setup();
+    for expr in [
+      "{ a = 1; b = [ true null \"s\" ]; c = 1.5; }",
+      "{ x, y ? 1, ... }@args: x",
+      "derivation { name = \"d\"; system = \"x\"; builder = \"b\"; }",
+    ] {
+      let value = state
+        .eval_from_string(expr, "<eval>")
+        .expect("Failed to evaluate");
+      let expected = state
+        .eval_from_string(&format!("builtins.toXML ({expr})"), "<eval>")
+        .expect("Failed to evaluate")
+        .as_string()
+        .unwrap();
+      assert_eq!(value.to_xml().expect("Failed to render"), expected);
+    }
+  }
+
+  #[test]
+  #[serial]
+  fn test_to_xml_options() {
+    let state = setup();
+    let value = state
+      .eval_from_string("{ a = 1; b = throw \"b\"; }", "<eval>")
+      .expect("Failed to evaluate");
+    assert!(value.to_xml().is_err());
+
+    let lazy = XmlOptions {
+      strict:   false,
+      location: true,
+    };
+    let xml = value.to_xml_with(lazy).expect("Failed to render");
+    assert!(xml.contains("<unevaluated />"), "{xml}");
+    assert!(xml.contains("<attr column=\"3\""), "{xml}");
+  }
+}
@@ -273,3 +273,55 @@ fn test_realize_derivation() {
     "Output path should contain derivation name"
   );
 }
+
+#[cfg(feature = "derive")]
+#[test]
+#[serial]
+fn test_derive_from_nix_into_nix() {
+  use nix_bindings::{FromNix, IntoNix, NixValueOps, Value};
+
+  #[derive(FromNix, IntoNix)]
+  struct Service<'a> {
+    port:    u16,
+    #[nix(default)]
+    enable:  bool,
+    #[nix(rename = "extra-args")]
+    args:    Vec<String>,
+    user:    Option<String>,
+    #[nix(lazy)]
+    package: Value<'a>,
+  }
+
+  let ctx = Arc::new(Context::new().expect("Failed to create context"));
+  let store = Arc::new(Store::open(&ctx, None).expect("Failed to open store"));
+  let state = EvalStateBuilder::new(&store)
+    .expect("Failed to create builder")
+    .build()
+    .expect("Failed to build state");
+
+  let value = state
+    .eval_from_string(
+      "{ port = 80; extra-args = [ \"-v\" ]; package = throw \"lazy\"; }",
+      "<test>",
+    )
+    .expect("Failed to evaluate");
+  let service: Service = value.extract().expect("Failed to convert");
+  assert_eq!(service.port, 80);
+  assert!(!service.enable);
+  assert_eq!(service.args, ["-v"]);
+  assert_eq!(service.user, None);
+  assert_eq!(service.package.value_type(), ValueType::Thunk);
+
+  let back = service.to_nix(&state).expect("Failed to convert back");
+  assert_eq!(back.get_attr("port").unwrap().as_int().unwrap(), 80);
+  assert!(back.has_attr("extra-args").unwrap());
+  assert_eq!(back.get_attr("user").unwrap().value_type(), ValueType::Null);
+
+  let err = state
+    .eval_from_string("{ extra-args = [ ]; package = 1; }", "<test>")
+    .expect("Failed to evaluate")
+    .extract::<Service>()
+    .err()
+    .expect("missing port should fail");
+  assert_eq!(err.to_string(), "port: missing attribute");
+}